@@ -1,8 +1,12 @@
-use core::num::Wrapping;
+use core::{
+    num::Wrapping,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use crate::{
     cpu::CPU_TABLE,
-    proc::{either_copy_in, either_copy_out},
+    kalloc,
+    proc::{either_copy_in, either_copy_out, signal::SIGINT},
     process::PROCESS_TABLE,
     spinlock::SpinLock,
     uart::{self, UART_TX},
@@ -17,29 +21,30 @@ pub fn putc(c: u8) {
 }
 
 /// user write()s to the console go here.
-pub fn write(is_user: bool, src: *const u8, n: usize) {
+pub fn write(is_user: bool, src: *const u8, n: usize) -> Result<(), &'static str> {
     for i in 0..n {
         let mut c = 0u8;
-        either_copy_in(is_user, unsafe { src.offset(i as isize) }, &mut c, 1);
+        either_copy_in(is_user, unsafe { src.offset(i as isize) }, &mut c, 1)?;
         unsafe {
             UART_TX.putc(c);
         }
     }
+    Ok(())
 }
 
 /// user read()s from the console go here.
-pub fn read(is_user: bool, dst: *mut u8, mut n: usize) -> Result<usize, ()> {
+pub fn read(is_user: bool, dst: *mut u8, mut n: usize) -> Result<usize, &'static str> {
     let target = n;
     let mut cons = CONSOLE.lock();
     while n > 0 {
         // wait until intr handler has put some input into cons.buf
         while cons.r == cons.w {
-            // TODO: killed
-            cons = unsafe {
-                CPU_TABLE
-                    .my_proc()
-                    .sleep(&cons.r as *const Wrapping<usize> as usize, cons)
-            };
+            let p = unsafe { CPU_TABLE.my_proc() };
+            if p.is_killed() {
+                drop(cons);
+                return Err("read: killed");
+            }
+            cons = p.sleep(&cons.r as *const Wrapping<usize> as usize, cons);
         }
 
         cons.r += Wrapping(1);
@@ -53,7 +58,10 @@ pub fn read(is_user: bool, dst: *mut u8, mut n: usize) -> Result<usize, ()> {
             break;
         }
 
-        either_copy_out(is_user, dst, &c, 1);
+        // the copy may fault the page in and sleep, so the console is let go meanwhile.
+        drop(cons);
+        either_copy_out(is_user, dst, &c, 1)?;
+        cons = CONSOLE.lock();
         unsafe { dst.offset(1) };
         n -= 1;
 
@@ -89,10 +97,27 @@ impl Console {
 
 static CONSOLE: SpinLock<Console> = SpinLock::new(Console::new(), "console");
 
+// set by ^T, for the memory usage to be printed out of the interrupt.
+static REPORT: AtomicBool = AtomicBool::new(false);
+
+/// prints the memory usage if ^T asked for it. the schedulers call it with no lock held and
+/// interrupts on, since the dump is long and spins on the UART.
+pub fn report() {
+    if REPORT.swap(false, Ordering::Relaxed) {
+        kalloc::dump();
+    }
+}
+
 pub fn intr(c: u8) {
     let mut cons = CONSOLE.lock();
 
     match c {
+        CTRL_C => {
+            // there are no process groups, so everything but the session leaders, which init
+            // makes of the shells it starts, is in the foreground.
+            unsafe { PROCESS_TABLE.kill_foreground(SIGINT) };
+        }
+        CTRL_T => REPORT.store(true, Ordering::Relaxed),
         CTRL_BS | b'\x7f' => {
             if cons.e != cons.w {
                 cons.e -= Wrapping(1);
@@ -118,8 +143,10 @@ pub fn intr(c: u8) {
 }
 
 // https://man7.org/linux/man-pages/man4/console_codes.4.html
+const CTRL_C: u8 = 0x03;
 const CTRL_D: u8 = 0x04;
 const CTRL_BS: u8 = 0x08;
+const CTRL_T: u8 = 0x14;
 const CTRL_LF: u8 = 0x0A;
 const CTRL_CR: u8 = 0x0D;
 
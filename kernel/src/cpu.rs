@@ -1,10 +1,12 @@
 use core::ptr;
 
 use crate::{
+    console,
     param::NCPU,
     proc::{Context, Proc, ProcInner, ProcState},
     process::PROCESS_TABLE,
     register::{sstatus, tp},
+    sched,
     spinlock::SpinLockGuard,
 };
 
@@ -33,16 +35,19 @@ impl CpuTable {
             fn swtch(old: *mut Context, new: *const Context); // in swtch.S
         }
         let cpu = self.my_cpu_mut();
+        sched::online(Self::cpu_id());
 
         loop {
             // Avoid deadlock by ensuring that devices can interrupt.
             sstatus::intr_on();
+            console::report();
 
             if let Some(p) = PROCESS_TABLE.find_runnable() {
                 cpu.proc = p as *mut _;
 
                 let mut guard = p.inner.lock();
                 guard.state = ProcState::Running;
+                guard.last_cpu = Self::cpu_id();
 
                 // Save the scheduler context as soon as it is switched to the process's context.
                 swtch(&mut cpu.scheduler, p.data.get_mut().get_context());
@@ -120,7 +125,7 @@ impl Cpu {
     /// Passing in and out a guard because we need to the lock during this function.
     pub fn sched<'a>(
         &mut self,
-        guard: SpinLockGuard<'a, ProcInner>,
+        mut guard: SpinLockGuard<'a, ProcInner>,
         ctx: &mut Context,
     ) -> SpinLockGuard<'a, ProcInner> {
         if self.noff != 1 {
@@ -133,6 +138,13 @@ impl Cpu {
             panic!("sched: interruptable");
         }
 
+        // a process that is still runnable has been preempted, the others give up the cpu.
+        if guard.state == ProcState::Runnable {
+            guard.usage.nivcsw += 1;
+        } else {
+            guard.usage.nvcsw += 1;
+        }
+
         let intena = self.intena;
 
         extern "C" {
@@ -147,12 +159,14 @@ impl Cpu {
         guard
     }
 
-    pub unsafe fn yield_process(&mut self) {
+    /// charges a clock tick to the process running on this cpu, which yields when its time slice
+    /// is used up.
+    pub unsafe fn tick(&mut self, is_user: bool) {
         if !self.proc.is_null() {
             let proc = self.proc.as_mut().unwrap();
-            proc.yield_process();
+            proc.tick(is_user);
         }
-        // If proc is null, we can't yield the process
+        // If proc is null, the scheduler is running on this cpu
         // This is a no-op
     }
 }
@@ -163,7 +163,7 @@ impl E1000 {
         write_e1000_regs(E1000_RDT, RX_RING_SIZE as u32);
 
         for i in 0..RX_RING_SIZE {
-            let mut m = MBuf::alloc(0);
+            let mut m = MBuf::alloc(0).expect("e1000: cannot alloc rx mbuf");
             self.rx_ring[i].addr = m.get_buf_head() as u64;
             self.rx_mbufs[i].replace(m);
         }
@@ -245,7 +245,17 @@ impl SpinLock<E1000> {
                 break;
             }
 
-            let mut new_m = MBuf::alloc(0);
+            let mut new_m = match MBuf::alloc(0) {
+                Ok(new_m) => new_m,
+                Err(_) => {
+                    // without a mbuf to replace it, the packet is dropped and its mbuf given back
+                    // to the ring.
+                    guard.rx_ring[pos].status = 0;
+                    write_e1000_regs(E1000_RDT, pos as u32);
+                    pos = (pos + 1) % RX_RING_SIZE;
+                    continue;
+                }
+            };
             let new_m_ptr = new_m.get_buf_head() as u64;
 
             // Pass a new mbuf's (which is allocated to replace the one just derivered to network stack)
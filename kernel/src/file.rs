@@ -1,7 +1,7 @@
 //! A cool aspect of the Unix interface is that most resources in Unix are represented as files,
 //! including devices such as the console, pipes, and of course, real files. The file descriptor
 //! layer is the layer that archives this uniformity.
-use core::{cell::UnsafeCell, panic};
+use core::{cell::UnsafeCell, cmp::min, panic};
 
 use alloc::{boxed::Box, sync::Arc};
 
@@ -9,8 +9,10 @@ use crate::{
     console,
     cpu::CPU_TABLE,
     fs::{FileStat, Inode, InodeType, INODE_TABLE},
+    kalloc::{Tag, Tracked, ENOMEM},
     log::LOG,
     net::{self, Socket},
+    pagecache,
     process::PROCESS_TABLE,
     spinlock::SpinLock,
 };
@@ -32,19 +34,20 @@ pub struct File {
     pub readable: bool,
     pub writable: bool,
     inner: FileInner,
+    _tracked: Tracked,
 }
 
 impl File {
-    pub fn open(path: &[u8], o_mode: i32) -> Option<Arc<Self>> {
+    pub fn open(path: &[u8], o_mode: i32) -> Result<Arc<Self>, &'static str> {
         LOG.begin_op();
         let inode = if o_mode & O_CREATE > 0 {
             Some(INODE_TABLE.create(&path, InodeType::File, 0, 0))
         } else {
             INODE_TABLE.namei(&path)
         }
-        .or_else(|| {
+        .ok_or_else(|| {
             LOG.end_op();
-            None
+            "open: no such file"
         })?;
 
         let readable = o_mode & O_WRONLY == 0;
@@ -58,7 +61,7 @@ impl File {
                     drop(idata);
                     drop(inode);
                     LOG.end_op();
-                    return None;
+                    return Err("open: is a directory");
                 }
                 drop(idata);
                 FileInner::Inode(FileInode {
@@ -87,26 +90,49 @@ impl File {
         };
         LOG.end_op();
 
-        Some(Arc::new(Self {
+        Arc::try_new(Self {
             readable,
             writable,
             inner,
-        }))
+            _tracked: Tracked::new(Tag::File),
+        })
+        .or(Err(ENOMEM))
     }
 
-    pub fn alloc_pipe() -> (Arc<File>, Arc<File>) {
-        let p = Arc::new(SpinLock::new(FilePipe::new(), "pipe"));
-        let rf = Arc::new(Self {
+    /// a read-only open file of `inode`, for the kernel to map it, like the segments of a program.
+    /// the file takes a reference to the inode only once it is allocated, as the caller may hold
+    /// the inode locked in a log operation, where the reference cannot be dropped.
+    pub fn from_inode(inode: &Inode) -> Result<Arc<Self>, &'static str> {
+        let mut f = Arc::try_new_uninit().or(Err(ENOMEM))?;
+        Arc::get_mut(&mut f).unwrap().write(Self {
             readable: true,
             writable: false,
-            inner: FileInner::Pipe(p.clone()),
+            inner: FileInner::Inode(FileInode {
+                inode: Some(INODE_TABLE.idup(inode)),
+                offset: UnsafeCell::new(0),
+            }),
+            _tracked: Tracked::new(Tag::File),
         });
-        let wf = Arc::new(Self {
+        Ok(unsafe { f.assume_init() })
+    }
+
+    pub fn alloc_pipe() -> Result<(Arc<File>, Arc<File>), &'static str> {
+        let p = Arc::try_new(SpinLock::new(FilePipe::new(), "pipe")).or(Err(ENOMEM))?;
+        let rf = Arc::try_new(Self {
+            readable: true,
+            writable: false,
+            inner: FileInner::Pipe(p.clone()),
+            _tracked: Tracked::new(Tag::File),
+        })
+        .or(Err(ENOMEM))?;
+        let wf = Arc::try_new(Self {
             readable: false,
             writable: true,
             inner: FileInner::Pipe(p.clone()),
-        });
-        (rf, wf)
+            _tracked: Tracked::new(Tag::File),
+        })
+        .or(Err(ENOMEM))?;
+        Ok((rf, wf))
     }
 
     pub fn alloc_socket(domain: u16, typ: u8, protocol: u8) -> Result<Arc<Self>, &'static str> {
@@ -115,10 +141,11 @@ impl File {
         let f = Self {
             readable: true,
             writable: true,
-            inner: FileInner::Socket(Box::new(s)),
+            inner: FileInner::Socket(Box::try_new(s).or(Err(ENOMEM))?),
+            _tracked: Tracked::new(Tag::File),
         };
 
-        Ok(Arc::new(f))
+        Arc::try_new(f).or(Err(ENOMEM))
     }
 
     pub fn write(&self, addr: usize, n: usize) -> Result<usize, &'static str> {
@@ -131,42 +158,46 @@ impl File {
                 if f.major != 1 {
                     panic!("device_write not implemented");
                 }
-                console::write(true, addr as *const u8, n);
+                console::write(true, addr as *const u8, n)?;
                 Ok(n)
             }
             FileInner::Pipe(ref f) => {
                 let p = unsafe { CPU_TABLE.my_proc() };
-                let mut guard = f.lock();
+                let mut buf = [0u8; PIPE_SIZE];
                 let mut i = 0;
                 while i < n {
-                    if !guard.read_open {
-                        // TODO killed
-                        drop(guard);
-                        return Err("pipe_write: no read open");
+                    // copied in without the lock, since the copy may fault the page in and sleep.
+                    let len = min(n - i, PIPE_SIZE);
+                    if let Err(msg) = p.data.get_mut().copy_in(buf.as_mut_ptr(), addr + i, len) {
+                        if i == 0 {
+                            return Err(msg);
+                        }
+                        break;
                     }
 
-                    if guard.n_write == guard.n_read + PIPE_SIZE {
-                        // reached full size
-                        unsafe { PROCESS_TABLE.wakeup(&guard.n_read as *const _ as usize) };
-                        guard = p.sleep(&guard.n_write as *const _ as usize, guard);
-                    } else {
-                        let mut ch = 0u8;
-                        if p.data
-                            .get_mut()
-                            .copy_in(&mut ch as *mut _, addr + i as usize, 1)
-                            .is_err()
-                        {
-                            break;
+                    let mut guard = f.lock();
+                    let mut j = 0;
+                    while j < len {
+                        if !guard.read_open || p.is_killed() {
+                            drop(guard);
+                            return Err("pipe_write: no read open");
+                        }
+
+                        if guard.n_write == guard.n_read + PIPE_SIZE {
+                            // reached full size
+                            unsafe { PROCESS_TABLE.wakeup(&guard.n_read as *const _ as usize) };
+                            guard = p.sleep(&guard.n_write as *const _ as usize, guard);
+                        } else {
+                            let n_write = guard.n_write + 1;
+                            guard.data[n_write % PIPE_SIZE] = buf[j];
+                            guard.n_write = n_write;
+                            j += 1;
                         }
-                        let n_write = guard.n_write + 1;
-                        guard.data[n_write % PIPE_SIZE] = ch;
-                        guard.n_write = n_write;
-                        i += 1;
                     }
+                    unsafe { PROCESS_TABLE.wakeup(&guard.n_read as *const _ as usize) };
+                    drop(guard);
+                    i += len;
                 }
-
-                unsafe { PROCESS_TABLE.wakeup(&guard.n_read as *const _ as usize) };
-                drop(guard);
                 Ok(i)
             }
             FileInner::Socket(ref s) => s.write(addr, n),
@@ -174,13 +205,13 @@ impl File {
                 LOG.begin_op();
                 let mut idata = f.inode.as_ref().unwrap().ilock();
                 let offset = unsafe { &mut (*f.offset.get()) };
-                idata
-                    .writei(true, addr as *const u8, *offset, n)
-                    .or_else(|()| Err("cannot write the file"))?;
-                *offset += n;
+                let res = idata.writei(true, addr as *const u8, *offset, n);
+                if res.is_ok() {
+                    *offset += n;
+                }
                 drop(idata);
                 LOG.end_op();
-                Ok(n)
+                res.map(|()| n)
             }
         }
     }
@@ -195,15 +226,13 @@ impl File {
                 if f.major != 1 {
                     panic!("device_read not implemented");
                 }
-                console::read(true, addr as *mut u8, n).or_else(|_| Err("read: cannot read"))
+                console::read(true, addr as *mut u8, n)
             }
             FileInner::Inode(ref f) => {
                 let mut idata = f.inode.as_ref().unwrap().ilock();
 
                 let offset = unsafe { &mut (*f.offset.get()) };
-                let read_n = idata
-                    .readi(true, addr as *mut u8, *offset, n)
-                    .or_else(|()| Err("cannot read the file"))?;
+                let read_n = idata.readi(true, addr as *mut u8, *offset, n)?;
                 *offset += read_n;
                 drop(idata);
                 Ok(read_n)
@@ -212,35 +241,81 @@ impl File {
                 let p = unsafe { CPU_TABLE.my_proc() };
                 let mut guard = f.lock();
                 while guard.n_read == guard.n_write && guard.write_open {
-                    // TODO: killed
+                    if p.is_killed() {
+                        drop(guard);
+                        return Err("pipe_read: killed");
+                    }
                     // pipe is still empty. sleep.
                     guard = p.sleep(&guard.n_read as *const _ as usize, guard);
                 }
 
-                for i in 0..n as isize {
-                    if guard.n_read == guard.n_write {
-                        break;
-                    }
-                    // copy into addr
+                // taken out under the lock and copied out without it, since the copy may fault the
+                // page in and sleep.
+                let mut buf = [0u8; PIPE_SIZE];
+                let mut i = 0;
+                while i < min(n, PIPE_SIZE) && guard.n_read != guard.n_write {
                     guard.n_read += 1;
-                    let ch = &guard.data[guard.n_read % PIPE_SIZE];
-                    if p.data
-                        .get_mut()
-                        .copy_out(addr + i as usize, ch as *const _, 1)
-                        .is_err()
-                    {
-                        break;
-                    }
+                    buf[i] = guard.data[guard.n_read % PIPE_SIZE];
+                    i += 1;
                 }
                 // wakeup writer
                 unsafe { PROCESS_TABLE.wakeup(&guard.n_write as *const _ as usize) };
                 drop(guard);
-                Ok(0)
+                p.data.get_mut().copy_out(addr, buf.as_ptr(), i)?;
+                Ok(i)
             }
             FileInner::Socket(ref s) => s.read(addr, n),
         }
     }
 
+    /// Read up to `n` bytes of the file at `offset` into kernel memory at `dst`, leaving the I/O
+    /// offset alone. returns the number of bytes read, which is short at the end of the file.
+    pub fn read_at(&self, dst: *mut u8, offset: usize, n: usize) -> Result<usize, &'static str> {
+        match &self.inner {
+            FileInner::Inode(ref f) => {
+                let mut idata = f.inode.as_ref().unwrap().ilock();
+                if offset >= idata.get_size() {
+                    drop(idata);
+                    return Ok(0);
+                }
+                let read_n = idata
+                    .readi(false, dst, offset, n)
+                    .or(Err("cannot read the file"))?;
+                drop(idata);
+                Ok(read_n)
+            }
+            _ => Err("read_at: not an inode"),
+        }
+    }
+
+    /// the page holding `n` bytes of the file from `offset`, and zeros after them, shared through
+    /// the page cache. returns its physical address, with a reference taken for the caller.
+    pub fn read_cached(&self, offset: usize, n: usize) -> Result<usize, &'static str> {
+        match &self.inner {
+            FileInner::Inode(ref f) => pagecache::read(f.inode.as_ref().unwrap(), offset, n),
+            _ => Err("read_cached: not an inode"),
+        }
+    }
+
+    /// Write up to `n` bytes from kernel memory at `src` to the file at `offset`, leaving the I/O
+    /// offset alone. never extends the file, the bytes past its end are dropped.
+    /// returns the number of bytes written.
+    pub fn write_at(&self, src: *const u8, offset: usize, n: usize) -> Result<usize, &'static str> {
+        match &self.inner {
+            FileInner::Inode(ref f) => {
+                LOG.begin_op();
+                let mut idata = f.inode.as_ref().unwrap().ilock();
+                let n = min(n, idata.get_size().saturating_sub(offset));
+                let res = idata.writei(false, src, offset, n);
+                drop(idata);
+                LOG.end_op();
+                res.or(Err("cannot write the file"))?;
+                Ok(n)
+            }
+            _ => Err("write_at: not an inode"),
+        }
+    }
+
     /// Get metadata about the file.
     pub fn stat(&self, st: &mut FileStat) {
         match &self.inner {
@@ -344,6 +419,7 @@ struct FilePipe {
     write_open: bool,
     n_read: usize,
     n_write: usize,
+    _tracked: Tracked,
 }
 
 impl FilePipe {
@@ -354,6 +430,7 @@ impl FilePipe {
             write_open: true,
             n_read: 0,
             n_write: 0,
+            _tracked: Tracked::new(Tag::Pipe),
         }
     }
 }
@@ -366,7 +443,7 @@ mod tests {
 
     #[test_case]
     fn share_pipe() {
-        let (r, w) = File::alloc_pipe();
+        let (r, w) = File::alloc_pipe().expect("cannot alloc pipe");
         assert_eq!(true, r.readable);
         assert_eq!(false, r.writable);
         assert_eq!(false, w.readable);
@@ -392,16 +469,22 @@ mod tests {
         // remap
         let p = unsafe { CPU_TABLE.my_proc() };
         let pdata = p.data.get_mut();
-        let pgt = pdata.page_table.as_mut().unwrap();
-        pgt.unmap_pages(0, 1, true).expect("cannot unmap initcode");
-        pgt.uvm_init(&[0, 0, 0, 0, 0, 1, 2, 3, 4, 5])
+        let mut mm = pdata.mm();
+        mm.page_table
+            .unmap_pages(0, 1, true)
+            .expect("cannot unmap initcode");
+        mm.page_table
+            .uvm_init(&[0, 0, 0, 0, 0, 1, 2, 3, 4, 5])
             .expect("cannot map into the page");
+        drop(mm);
 
-        let (r, w) = File::alloc_pipe();
+        let (r, w) = File::alloc_pipe().expect("cannot alloc pipe");
 
         w.write(5, 5).expect("cannot write");
         r.read(0, 5).expect("cannot read");
 
+        let mut mm = pdata.mm();
+        let pgt = &mut mm.page_table;
         let pa = pgt.walk_addr(0).expect("cannot walk") as *const u8;
         let actual = unsafe { (pa as *const [u8; 10]).as_ref() }.unwrap();
         assert_eq!(&[1, 2, 3, 4, 5, 1, 2, 3, 4, 5], actual);
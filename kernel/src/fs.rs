@@ -47,6 +47,7 @@ use crate::{
     bmap,
     cpu::CPU_TABLE,
     log::LOG,
+    pagecache,
     param::ROOTDEV,
     proc::{either_copy_in, either_copy_out},
     sleeplock::{SleepLock, SleepLockGuard},
@@ -342,8 +343,10 @@ impl InodeTable {
         let mut inode = if path[0] == b'/' {
             self.iget(ROOTDEV, ROOTINO)
         } else {
-            let cwd = unsafe { CPU_TABLE.my_proc().data.get_mut().cwd.as_ref().unwrap() };
-            self.idup(cwd)
+            let files = unsafe { CPU_TABLE.my_proc().data.get_mut().files() };
+            let cwd = self.idup(files.cwd.as_ref().unwrap());
+            drop(files);
+            cwd
         };
         
         let mut path_pos = 0;
@@ -444,6 +447,11 @@ pub struct Inode {
 }
 
 impl Inode {
+    /// (dev, inum), which tells the inode apart from the others.
+    pub fn id(&self) -> (u32, u32) {
+        (self.dev, self.inum)
+    }
+
     /// Lock the inode.
     /// Reads the inode from the disk if necessary.
     pub fn ilock(&self) -> SleepLockGuard<InodeData> {
@@ -526,6 +534,11 @@ impl InodeData {
         self.dinode.major
     }
 
+    #[inline]
+    pub fn get_size(&self) -> usize {
+        self.dinode.size as usize
+    }
+
     /// Returns the disk block number of the offset'th data block in the inode.
     /// If there is no such block yet, bmap() allocates one.
     fn bmap(&mut self, mut offset: usize) -> u32 {
@@ -610,10 +623,10 @@ impl InodeData {
         mut dst: *mut u8,
         mut offset: usize,
         mut n: usize,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, &'static str> {
         let (dev, _) = self.valid.unwrap();
 
-        let size = offset.checked_add(n).ok_or_else(|| ())?;
+        let size = offset.checked_add(n).ok_or("readi: invalid offset")?;
 
         let ret = if size > self.dinode.size as usize {
             self.dinode.size as usize - offset
@@ -629,7 +642,7 @@ impl InodeData {
             let buf = BCACHE.bread(dev, self.bmap(offset / BSIZE));
             let src_ptr =
                 unsafe { (buf.data_ptr() as *const u8).offset((offset % BSIZE) as isize) };
-            either_copy_out(is_user, dst, src_ptr, read_n);
+            either_copy_out(is_user, dst, src_ptr, read_n)?;
             drop(buf);
             offset += read_n;
             n -= read_n;
@@ -640,29 +653,34 @@ impl InodeData {
     }
 
     /// Write data to inode.
+    /// on a failure to copy from user space, the bytes copied so far are kept.
     pub fn writei(
         &mut self,
         is_user: bool,
         mut src: *const u8,
         mut offset: usize,
         mut n: usize,
-    ) -> Result<(), ()> {
-        let (dev, _) = *self.valid.as_ref().unwrap();
+    ) -> Result<(), &'static str> {
+        let (dev, inum) = *self.valid.as_ref().unwrap();
 
-        if offset > self.dinode.size as usize
-            || offset.checked_add(n).ok_or_else(|| ())? > MAXFILE * BSIZE
-        {
-            return Err(());
+        match offset.checked_add(n) {
+            Some(end) if offset <= self.dinode.size as usize && end <= MAXFILE * BSIZE => {}
+            _ => return Err("writei: out of the file"),
         }
 
+        let mut res = Ok(());
         while n > 0 {
             let write_n = min(n, BSIZE - offset % BSIZE);
             let mut buf = BCACHE.bread(dev, self.bmap(offset / BSIZE));
             let dst_ptr =
                 unsafe { (buf.data_ptr_mut() as *mut u8).offset((offset % BSIZE) as isize) };
-            either_copy_in(is_user, src, dst_ptr, write_n);
+            res = either_copy_in(is_user, src, dst_ptr, write_n);
+            // the block may have been partly copied into.
             LOG.write(&mut buf);
             drop(buf);
+            if res.is_err() {
+                break;
+            }
             offset += write_n;
             n -= write_n;
             src = unsafe { src.offset(write_n as isize) };
@@ -673,8 +691,9 @@ impl InodeData {
         }
 
         self.iupdate();
+        pagecache::invalidate(dev, inum);
 
-        Ok(())
+        res
     }
 
     pub fn stati(&self, dst: &mut FileStat) {
@@ -721,7 +740,7 @@ impl InodeData {
     /// Truncate inode (discard contents).
     /// Caller must hold sleep-lock.
     pub fn itrunc(&mut self) {
-        let (dev, _) = self.valid.unwrap();
+        let (dev, inum) = self.valid.unwrap();
 
         // direct blocks
         for i in 0..NDIRECT {
@@ -748,6 +767,7 @@ impl InodeData {
 
         self.dinode.size = 0;
         self.iupdate();
+        pagecache::invalidate(dev, inum);
     }
 
     /// Copy a modified in-memory inode to disk.
@@ -1033,10 +1053,11 @@ mod tests {
         let inode = INODE_TABLE
             .nameiparent(&[b'i', b'n', b'i', b't', 0], &mut name)
             .expect("the parent not found");
-        let pdata = unsafe { CPU_TABLE.my_proc() }.data.get_mut();
-        let cwd = pdata.cwd.as_ref().unwrap();
+        let files = unsafe { CPU_TABLE.my_proc() }.data.get_mut().files();
+        let cwd = files.cwd.as_ref().unwrap();
         assert_eq!(cwd.dev, inode.dev);
         assert_eq!(cwd.inum, inode.inum);
+        drop(files);
         drop(inode);
 
         let mut exp_name: [u8; DIRSIZ] = [0; DIRSIZ];
@@ -1050,10 +1071,11 @@ mod tests {
     #[test_case]
     fn lookup_by_dot() {
         let inode = INODE_TABLE.namei(&[b'.', 0]).expect("lookup");
-        let pdata = unsafe { CPU_TABLE.my_proc() }.data.get_mut();
-        let cwd = pdata.cwd.as_ref().unwrap();
+        let files = unsafe { CPU_TABLE.my_proc() }.data.get_mut().files();
+        let cwd = files.cwd.as_ref().unwrap();
         assert_eq!(cwd.dev, inode.dev);
         assert_eq!(cwd.inum, inode.inum);
+        drop(files);
         drop(inode);
     }
 
@@ -1106,7 +1128,7 @@ mod tests {
 
         // change directory
         let pdata = unsafe { CPU_TABLE.my_proc() }.data.get_mut();
-        let root_dir = pdata.cwd.replace(inode);
+        let root_dir = pdata.files().cwd.replace(inode);
         drop(root_dir);
 
         // lookup the parent
@@ -1129,7 +1151,7 @@ mod tests {
         let root = INODE_TABLE
             .namei(&[b'/', 0])
             .expect("cannot find root inode by b'/'");
-        let inode = pdata.cwd.replace(root).unwrap();
+        let inode = pdata.files().cwd.replace(root).unwrap();
         assert_eq!(new_inum, inode.inum);
         drop(inode);
         LOG.end_op();
@@ -1,15 +1,74 @@
-use crate::param::PHYSTOP;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::oom;
+use crate::pagecache;
+use crate::param::{PAGESIZE, PHYSTOP};
+use crate::println;
 use crate::spinlock::SpinLock;
-use alloc::alloc::Layout;
+use crate::swap;
+
+use self::buddy::{order_of, BuddyAllocator, MAX_ORDER};
+pub use self::tag::{Tag, TagCount, Tracked, NTAG};
 
-use self::linked_list::LinkedListAllocator;
+mod buddy;
+mod slab;
+mod tag;
 
-mod linked_list;
+/// the physical pages. page tables, user pages and the objects too large for a slab are allocated
+/// from it directly.
+pub static FRAMES: SpinLock<BuddyAllocator> = SpinLock::new(BuddyAllocator::new(), "frames");
+
+pub struct KernelAllocator;
 
 #[global_allocator]
-pub static ALLOCATOR: SpinLock<LinkedListAllocator> =
-    SpinLock::new(LinkedListAllocator::new(), "allocator");
+pub static ALLOCATOR: KernelAllocator = KernelAllocator;
+
+/// the bytes of the live allocations, as requested, and the most there have been.
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
 
+/// the objects up to slab::MAX_SIZE come from the slab caches, and the larger ones from FRAMES in
+/// whole blocks.
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let p = if let Some(class) = slab::class_of(layout.size(), layout.align()) {
+            slab::alloc(class)
+        } else {
+            let order = order_of(layout.size().max(layout.align()));
+            if order > MAX_ORDER {
+                return ptr::null_mut();
+            }
+            FRAMES.lock().alloc(order).unwrap_or(0)
+        };
+        if p != 0 {
+            let in_use = IN_USE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(in_use, Ordering::Relaxed);
+        }
+        p as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+        if let Some(class) = slab::class_of(layout.size(), layout.align()) {
+            slab::free(ptr as usize, class);
+        } else {
+            let order = order_of(layout.size().max(layout.align()));
+            FRAMES.lock().free(ptr as usize, order);
+        }
+    }
+}
+
+/// the error of an allocation that finds no memory left. a syscall that fails with it returns
+/// -ENOMEM_ERRNO rather than -1, so that user space can tell it apart.
+pub const ENOMEM: &str = "out of memory";
+pub const ENOMEM_ERRNO: isize = 12;
+
+/// the allocations that cannot fail, such as those of the collections, still end here. the paths
+/// of the syscalls allocate with the try_ methods and return ENOMEM instead.
 #[alloc_error_handler]
 fn alloc_error_handler(layout: Layout) -> ! {
     panic!("allocation error: {:?}", layout)
@@ -19,9 +78,88 @@ pub fn heap_init() {
     extern "C" {
         fn end(); // see kernel.ld linker script
     }
-    let heap_start: usize = end as usize;
+    let start = (end as usize + PAGESIZE - 1) & !(PAGESIZE - 1);
     unsafe {
-        ALLOCATOR.lock().init(heap_start, PHYSTOP - heap_start);
+        FRAMES.lock().init(start, PHYSTOP);
+    }
+}
+
+/// meminfo() flags: print the live tagged objects by the site that created them on the console.
+pub const MEMINFO_SITES: i32 = 1;
+
+/// the memory usage reported by meminfo().
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MemInfo {
+    /// the pages of physical memory past the kernel, and those free.
+    pub total_pages: usize,
+    pub free_pages: usize,
+    /// the pages held by the slab caches, and by the page cache.
+    pub slab_pages: usize,
+    pub cached_pages: usize,
+    /// the pages of the swap area, and those in use.
+    pub swap_pages: usize,
+    pub swap_used: usize,
+    /// the bytes of the live kernel allocations, and the most there have been.
+    pub heap_bytes: usize,
+    pub heap_peak: usize,
+    /// the processes killed for memory.
+    pub oom_kills: usize,
+    pub tags: [TagCount; NTAG],
+}
+
+pub fn meminfo() -> MemInfo {
+    let frames = FRAMES.lock();
+    let (total_pages, free_pages) = (frames.total_pages(), frames.free_pages());
+    drop(frames);
+    let (swap_pages, swap_used) = swap::stats();
+    MemInfo {
+        total_pages,
+        free_pages,
+        slab_pages: slab::stats().iter().map(|s| s.pages).sum(),
+        cached_pages: pagecache::pages(),
+        swap_pages,
+        swap_used,
+        heap_bytes: IN_USE.load(Ordering::Relaxed),
+        heap_peak: PEAK.load(Ordering::Relaxed),
+        oom_kills: oom::kills(),
+        tags: tag::counts(),
+    }
+}
+
+/// prints the live tagged objects by the site that created them. the sites are recorded with
+/// the kalloc-debug feature only.
+pub fn dump_sites() -> Result<(), &'static str> {
+    tag::dump()
+}
+
+/// prints the usage of the slab caches and of the memory, for the console's ^T.
+pub fn dump() {
+    println!("size   slabs  objs   inuse  cached hits     misses");
+    for s in slab::stats().iter() {
+        println!(
+            "{:<6} {:<6} {:<6} {:<6} {:<6} {:<8} {}",
+            s.size, s.slabs, s.capacity, s.inuse, s.cached, s.hits, s.misses
+        );
+    }
+    let info = meminfo();
+    println!(
+        "pages: {} total {} free {} cached; swap: {} total {} used; heap: {} bytes, peak {}",
+        info.total_pages,
+        info.free_pages,
+        info.cached_pages,
+        info.swap_pages,
+        info.swap_used,
+        info.heap_bytes,
+        info.heap_peak
+    );
+    for (i, count) in info.tags.iter().enumerate() {
+        println!(
+            "{}: {} live, {} total",
+            tag::name(i),
+            count.live,
+            count.total
+        );
     }
 }
 
@@ -29,6 +167,9 @@ pub fn heap_init() {
 mod tests {
     use alloc::boxed::Box;
 
+    use super::*;
+    use crate::page_table::{Page, SinglePage};
+
     #[test_case]
     fn simple_allocation() {
         let v1 = Box::new(41);
@@ -36,4 +177,23 @@ mod tests {
         assert_eq!(41, *v1);
         assert_eq!(13, *v2);
     }
+
+    #[test_case]
+    fn pages_come_from_frames() {
+        let nfree = FRAMES.lock().free_pages();
+        let page = unsafe { SinglePage::alloc_into_raw() }.expect("alloc page");
+        assert_eq!(nfree - 1, FRAMES.lock().free_pages());
+        unsafe { SinglePage::free_from_raw(page) };
+        assert_eq!(nfree, FRAMES.lock().free_pages());
+    }
+
+    #[test_case]
+    fn heap_bytes_are_counted() {
+        let v = Box::new([0u8; 100]);
+        let info = meminfo();
+        assert!(info.heap_bytes >= 100);
+        assert!(info.heap_peak >= info.heap_bytes);
+        assert!(info.free_pages < info.total_pages);
+        drop(v);
+    }
 }
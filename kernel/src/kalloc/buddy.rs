@@ -0,0 +1,246 @@
+//! The physical page-frame allocator, a buddy system.
+//!
+//! A block of order k is 2^k pages, aligned to its size, up to MAX_ORDER (2MB). The free blocks
+//! of each order are kept in a doubly-linked list that lives in the blocks themselves. Freeing a
+//! block merges it with its buddy, the other half of the block of the next order, while the buddy
+//! is free too.
+//!
+//! Each page has a `Frame` for its metadata: the order and state of the block it heads, and the
+//! references to it beyond the first, for the pages shared by fork() or the page cache.
+
+use core::ptr;
+
+use bitflags::bitflags;
+
+use crate::param::{KERNBASE, PAGESIZE, PHYSTOP};
+
+pub const MAX_ORDER: usize = 9;
+
+const NPAGE: usize = (PHYSTOP - KERNBASE) / PAGESIZE;
+
+bitflags! {
+    struct FrameFlag: u8 {
+        // heads a free block, which is on the free list of its order.
+        const FREE = 1 << 0;
+        // heads an allocated block.
+        const USED = 1 << 1;
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Frame {
+    // the references beyond the first.
+    refs: u16,
+    order: u8,
+    flags: FrameFlag,
+}
+
+impl Frame {
+    const fn new() -> Self {
+        Self {
+            refs: 0,
+            order: 0,
+            flags: FrameFlag::empty(),
+        }
+    }
+}
+
+/// the links of a free block, in its first bytes.
+struct FreeBlock {
+    next: usize,
+    prev: usize,
+}
+
+pub struct BuddyAllocator {
+    frames: [Frame; NPAGE],
+    // the first free block of each order, or 0.
+    free_lists: [usize; MAX_ORDER + 1],
+    nfree: usize,
+    npage: usize,
+}
+
+impl BuddyAllocator {
+    pub const fn new() -> Self {
+        Self {
+            frames: [Frame::new(); NPAGE],
+            free_lists: [0; MAX_ORDER + 1],
+            nfree: 0,
+            npage: 0,
+        }
+    }
+
+    /// Initialize the allocator with the pages in [start, end), which must be page aligned.
+    ///
+    /// This function is unsafe because the caller must ensure that the memory is unused.
+    /// This method must be called once.
+    pub unsafe fn init(&mut self, start: usize, end: usize) {
+        let mut pa = start;
+        while pa < end {
+            // the largest block aligned at `pa` that fits.
+            let mut order = MAX_ORDER;
+            while (pa - KERNBASE) % (PAGESIZE << order) != 0 || pa + (PAGESIZE << order) > end {
+                order -= 1;
+            }
+            self.push(pa, order);
+            pa += PAGESIZE << order;
+        }
+        self.npage = self.nfree;
+    }
+
+    /// allocates a block of 2^order pages. its contents are not initialized.
+    pub fn alloc(&mut self, order: usize) -> Option<usize> {
+        let mut k = (order..=MAX_ORDER).find(|&k| self.free_lists[k] != 0)?;
+        let pa = self.free_lists[k];
+        unsafe { self.remove(pa, k) };
+
+        // give back the upper halves that are not needed.
+        while k > order {
+            k -= 1;
+            unsafe { self.push(pa + (PAGESIZE << k), k) };
+        }
+
+        let frame = &mut self.frames[index(pa)];
+        frame.flags = FrameFlag::USED;
+        frame.order = order as u8;
+        frame.refs = 0;
+        Some(pa)
+    }
+
+    /// frees the block of 2^order pages at `pa`, which `alloc(order)` returned.
+    pub fn free(&mut self, pa: usize, order: usize) {
+        let frame = &mut self.frames[index(pa)];
+        if !frame.flags.contains(FrameFlag::USED) || frame.order as usize != order {
+            panic!(
+                "buddy free: {:#x} is not an allocated block of order {}",
+                pa, order
+            );
+        }
+        frame.flags = FrameFlag::empty();
+
+        let (mut pa, mut k) = (pa, order);
+        while k < MAX_ORDER {
+            let buddy = KERNBASE + ((pa - KERNBASE) ^ (PAGESIZE << k));
+            if buddy >= PHYSTOP {
+                break;
+            }
+            let frame = &self.frames[index(buddy)];
+            if !frame.flags.contains(FrameFlag::FREE) || frame.order as usize != k {
+                break;
+            }
+            unsafe { self.remove(buddy, k) };
+            pa = pa.min(buddy);
+            k += 1;
+        }
+        unsafe { self.push(pa, k) };
+    }
+
+    /// the number of free pages.
+    pub fn free_pages(&self) -> usize {
+        self.nfree
+    }
+
+    /// the number of pages the allocator was given.
+    pub fn total_pages(&self) -> usize {
+        self.npage
+    }
+
+    /// takes another reference to the page at `pa`.
+    pub fn share(&mut self, pa: usize) {
+        let frame = &mut self.frames[index(pa)];
+        frame.refs = frame
+            .refs
+            .checked_add(1)
+            .expect("share_page: too many references");
+    }
+
+    /// tells whether the page at `pa` has more than one reference.
+    pub fn is_shared(&self, pa: usize) -> bool {
+        self.frames[index(pa)].refs > 0
+    }
+
+    /// drops a reference to the page at `pa`. returns true if it was the last one.
+    pub fn release(&mut self, pa: usize) -> bool {
+        let frame = &mut self.frames[index(pa)];
+        if frame.refs == 0 {
+            return true;
+        }
+        frame.refs -= 1;
+        false
+    }
+
+    /// puts the free block at `pa` on the list of `order`.
+    unsafe fn push(&mut self, pa: usize, order: usize) {
+        let next = self.free_lists[order];
+        ptr::write(pa as *mut FreeBlock, FreeBlock { next, prev: 0 });
+        if next != 0 {
+            (*(next as *mut FreeBlock)).prev = pa;
+        }
+        self.free_lists[order] = pa;
+
+        let frame = &mut self.frames[index(pa)];
+        frame.flags = FrameFlag::FREE;
+        frame.order = order as u8;
+        self.nfree += 1 << order;
+    }
+
+    /// takes the free block at `pa` off the list of `order`.
+    unsafe fn remove(&mut self, pa: usize, order: usize) {
+        let block = ptr::read(pa as *const FreeBlock);
+        if block.prev != 0 {
+            (*(block.prev as *mut FreeBlock)).next = block.next;
+        } else {
+            self.free_lists[order] = block.next;
+        }
+        if block.next != 0 {
+            (*(block.next as *mut FreeBlock)).prev = block.prev;
+        }
+
+        self.frames[index(pa)].flags = FrameFlag::empty();
+        self.nfree -= 1 << order;
+    }
+}
+
+#[inline]
+fn index(pa: usize) -> usize {
+    (pa - KERNBASE) / PAGESIZE
+}
+
+/// the smallest order of a block that holds `size` bytes.
+pub fn order_of(size: usize) -> usize {
+    let pages = (size + PAGESIZE - 1) / PAGESIZE;
+    pages.next_power_of_two().trailing_zeros() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kalloc::FRAMES;
+
+    #[test_case]
+    fn alloc_splits_and_free_merges() {
+        let mut frames = FRAMES.lock();
+        let nfree = frames.free_pages();
+
+        let big = frames.alloc(MAX_ORDER).expect("alloc max order");
+        assert_eq!(0, (big - KERNBASE) % (PAGESIZE << MAX_ORDER));
+        let small = frames.alloc(0).expect("alloc a page");
+        let quad = frames.alloc(2).expect("alloc 4 pages");
+        assert_eq!(0, (quad - KERNBASE) % (PAGESIZE << 2));
+        assert_eq!(nfree - (1 << MAX_ORDER) - 1 - 4, frames.free_pages());
+
+        frames.free(small, 0);
+        frames.free(quad, 2);
+        frames.free(big, MAX_ORDER);
+        assert_eq!(nfree, frames.free_pages());
+        drop(frames);
+    }
+
+    #[test_case]
+    fn orders() {
+        assert_eq!(0, order_of(1));
+        assert_eq!(0, order_of(PAGESIZE));
+        assert_eq!(1, order_of(PAGESIZE + 1));
+        assert_eq!(2, order_of(PAGESIZE * 4));
+        assert_eq!(MAX_ORDER, order_of(PAGESIZE << MAX_ORDER));
+    }
+}
@@ -1,148 +0,0 @@
-use core::{
-    alloc::{GlobalAlloc, Layout},
-    mem, ptr,
-};
-
-use crate::spinlock::SpinLock;
-
-struct ListNode {
-    size: usize,
-    next: Option<&'static mut ListNode>,
-}
-
-impl ListNode {
-    const fn new(size: usize) -> Self {
-        Self { size, next: None }
-    }
-
-    fn start_addr(&self) -> usize {
-        self as *const Self as usize
-    }
-
-    fn end_addr(&self) -> usize {
-        self.start_addr() + self.size
-    }
-}
-
-pub struct LinkedListAllocator {
-    head: ListNode,
-}
-
-impl LinkedListAllocator {
-    pub const fn new() -> Self {
-        Self {
-            head: ListNode::new(0),
-        }
-    }
-
-    /// Initialize the allocator with the given heap bounds.
-    ///
-    /// This function is unsafe because the caller must be ensure that the given heap bounds
-    /// are valid and that the heap unused.
-    /// This method must be called once.
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        self.add_free_region(heap_start, heap_size);
-    }
-
-    /// Adds the given memory region to the front of the list.
-    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
-        // ensure that the freed region is capable of holding ListNode
-        // In the original code, it expected the address to be already aligned
-        // However, unaligned addresses can be passed in practice
-        // So we align the address and adjust the size accordingly
-        
-        let aligned_addr = Self::align_up(addr, mem::align_of::<ListNode>());
-        
-        // Calculate size reduction due to alignment
-        let size_reduction = aligned_addr - addr;
-        
-        // If the size after alignment is too small, it's an error
-        // This prevents memory regions from being lost
-        assert!(size > size_reduction);
-        assert!(size - size_reduction >= mem::size_of::<ListNode>());
-        
-        let adjusted_size = size - size_reduction;
-        
-        // Create a new list node and append it at the start of the list
-        let mut node = ListNode::new(adjusted_size);
-        node.next = self.head.next.take();
-        let node_ptr = aligned_addr as *mut ListNode;
-        node_ptr.write(node);
-        self.head.next = Some(&mut *node_ptr);
-    }
-
-    fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
-        let mut current = &mut self.head;
-
-        while let Some(ref mut region) = current.next {
-            if let Ok(alloc_start) = Self::alloc_from_region(&region, size, align) {
-                let next = region.next.take();
-                let ret = Some((current.next.take().unwrap(), alloc_start));
-                current.next = next;
-                return ret;
-            } else {
-                current = current.next.as_mut().unwrap();
-            }
-        }
-
-        None
-    }
-
-    /// Try to use the given region for an allocation with given size and alignment.
-    /// Returns the allocation start address on success.
-    fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
-        let alloc_start = Self::align_up(region.start_addr(), align);
-        let alloc_end = alloc_start.checked_add(size).ok_or_else(|| ())?;
-
-        if alloc_end > region.end_addr() {
-            // region too small
-            return Err(());
-        }
-
-        let excess_size = region.end_addr() - alloc_end;
-
-        if excess_size > 0 && excess_size < mem::size_of::<ListNode>() {
-            // rest of region too small to hold a ListNode
-            return Err(());
-        }
-
-        Ok(alloc_start)
-    }
-
-    fn size_align(layout: Layout) -> (usize, usize) {
-        let layout = layout
-            .align_to(mem::align_of::<ListNode>())
-            .expect("adjusting alignment failed")
-            .pad_to_align();
-        let size = layout.size().max(mem::size_of::<ListNode>());
-        (size, layout.align())
-    }
-
-    fn align_up(addr: usize, align: usize) -> usize {
-        (addr + align - 1) & !(align - 1)
-    }
-}
-
-unsafe impl GlobalAlloc for SpinLock<LinkedListAllocator> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let (size, align) = LinkedListAllocator::size_align(layout);
-        let mut allocator = self.lock();
-
-        if let Some((region, alloc_start)) = allocator.find_region(size, align) {
-            let alloc_end = alloc_start.checked_add(size).expect("overflow");
-            let excess_size = region.end_addr() - alloc_end;
-            if excess_size > 0 {
-                allocator.add_free_region(alloc_end, excess_size);
-            }
-
-            return alloc_start as *mut u8;
-        }
-
-        ptr::null_mut()
-    }
-
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let (size, _) = LinkedListAllocator::size_align(layout);
-        self.lock().add_free_region(ptr as usize, size);
-    }
-}
@@ -0,0 +1,325 @@
+//! The slab allocator, for the kernel objects smaller than a page.
+//!
+//! The objects are grouped by size into caches of the power-of-two classes from MIN_SIZE to
+//! MAX_SIZE bytes. A cache carves slabs, blocks from FRAMES, into objects of its class. The header
+//! of a slab is in its first bytes and a slab is aligned to its size, so the slab of an object is
+//! found by rounding its address down. The slabs with free objects are on the partial list of their
+//! cache, and a slab goes back to FRAMES once all of its objects are freed.
+//!
+//! In front of the caches, each CPU has a magazine of free objects per class, so most allocations
+//! and frees don't touch the shared caches. An empty magazine is refilled from its cache, and a full
+//! one flushed to it, half at a time.
+
+use core::{mem, ptr};
+
+use array_macro::array;
+
+use crate::cpu::CpuTable;
+use crate::param::{NCPU, PAGESIZE};
+use crate::spinlock::SpinLock;
+
+use super::{buddy::order_of, FRAMES};
+
+pub const MIN_SIZE: usize = 16;
+pub const MAX_SIZE: usize = 2048;
+pub const NCLASS: usize = 8;
+
+// a slab is large enough for this many objects, less the room of the header.
+const SLAB_OBJECTS: usize = 8;
+
+const MAG_SIZE: usize = 32;
+
+static CACHES: [SpinLock<Cache>; NCLASS] =
+    array![i => SpinLock::new(Cache::new(MIN_SIZE << i), "slab"); NCLASS];
+
+static MAGAZINES: [SpinLock<[Magazine; NCLASS]>; NCPU] =
+    array![_ => SpinLock::new(array![_ => Magazine::new(); NCLASS], "magazine"); NCPU];
+
+/// the header of a slab, in its first bytes.
+struct Slab {
+    // the first free object, which holds the address of the next one, or 0.
+    free: usize,
+    inuse: usize,
+    // the links of the partial list.
+    next: usize,
+    prev: usize,
+}
+
+struct Cache {
+    size: usize,
+    // the first slab with free objects, or 0.
+    partial: usize,
+    slabs: usize,
+    // the objects taken from the slabs, including those in the magazines.
+    inuse: usize,
+}
+
+impl Cache {
+    const fn new(size: usize) -> Self {
+        Self {
+            size,
+            partial: 0,
+            slabs: 0,
+            inuse: 0,
+        }
+    }
+
+    fn slab_size(&self) -> usize {
+        (self.size * SLAB_OBJECTS).max(PAGESIZE)
+    }
+
+    fn order(&self) -> usize {
+        order_of(self.slab_size())
+    }
+
+    /// the offset of the first object, after the header. the sizes are powers of two, so are the
+    /// header and the objects aligned to their size.
+    fn first(&self) -> usize {
+        self.size.max(mem::size_of::<Slab>())
+    }
+
+    /// the number of objects in a slab.
+    fn per_slab(&self) -> usize {
+        (self.slab_size() - self.first()) / self.size
+    }
+
+    /// takes a free object, growing the cache by a slab if it has none.
+    unsafe fn alloc(&mut self) -> Option<usize> {
+        if self.partial == 0 {
+            self.grow()?;
+        }
+        let slab = self.partial as *mut Slab;
+        let obj = (*slab).free;
+        (*slab).free = ptr::read(obj as *const usize);
+        (*slab).inuse += 1;
+        if (*slab).free == 0 {
+            self.unlink(slab);
+        }
+        self.inuse += 1;
+        Some(obj)
+    }
+
+    /// gives back the object at `obj`, and its slab to FRAMES if it is the last one in use. one
+    /// empty slab is kept while no other slab has free objects, so that allocating and freeing an
+    /// object in turn doesn't allocate a slab each time.
+    unsafe fn free(&mut self, obj: usize) {
+        let slab = (obj & !(self.slab_size() - 1)) as *mut Slab;
+        let full = (*slab).free == 0;
+        ptr::write(obj as *mut usize, (*slab).free);
+        (*slab).free = obj;
+        (*slab).inuse -= 1;
+        self.inuse -= 1;
+        if full {
+            self.link(slab);
+        }
+
+        if (*slab).inuse == 0 && ((*slab).next != 0 || (*slab).prev != 0) {
+            self.unlink(slab);
+            self.slabs -= 1;
+            FRAMES.lock().free(slab as usize, self.order());
+        }
+    }
+
+    unsafe fn grow(&mut self) -> Option<()> {
+        let start = FRAMES.lock().alloc(self.order())?;
+        let mut free = 0;
+        for i in (0..self.per_slab()).rev() {
+            let obj = start + self.first() + i * self.size;
+            ptr::write(obj as *mut usize, free);
+            free = obj;
+        }
+        let slab = start as *mut Slab;
+        ptr::write(
+            slab,
+            Slab {
+                free,
+                inuse: 0,
+                next: 0,
+                prev: 0,
+            },
+        );
+        self.link(slab);
+        self.slabs += 1;
+        Some(())
+    }
+
+    /// puts the slab on the partial list.
+    unsafe fn link(&mut self, slab: *mut Slab) {
+        (*slab).next = self.partial;
+        (*slab).prev = 0;
+        if self.partial != 0 {
+            (*(self.partial as *mut Slab)).prev = slab as usize;
+        }
+        self.partial = slab as usize;
+    }
+
+    /// takes the slab off the partial list.
+    unsafe fn unlink(&mut self, slab: *mut Slab) {
+        if (*slab).prev != 0 {
+            (*((*slab).prev as *mut Slab)).next = (*slab).next;
+        } else {
+            self.partial = (*slab).next;
+        }
+        if (*slab).next != 0 {
+            (*((*slab).next as *mut Slab)).prev = (*slab).prev;
+        }
+        (*slab).next = 0;
+        (*slab).prev = 0;
+    }
+}
+
+/// the free objects of a class cached by a CPU.
+struct Magazine {
+    objs: [usize; MAG_SIZE],
+    len: usize,
+    hits: usize,
+    misses: usize,
+}
+
+impl Magazine {
+    const fn new() -> Self {
+        Self {
+            objs: [0; MAG_SIZE],
+            len: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+}
+
+/// the class of the objects of `size` bytes aligned to `align`, if they are small enough for a
+/// slab.
+pub fn class_of(size: usize, align: usize) -> Option<usize> {
+    let size = size.max(align).max(MIN_SIZE).next_power_of_two();
+    if size > MAX_SIZE {
+        return None;
+    }
+    Some((size / MIN_SIZE).trailing_zeros() as usize)
+}
+
+/// allocates an object of `class`. returns 0 if there is no memory left.
+pub fn alloc(class: usize) -> usize {
+    // another CPU's magazine would do as well, if this process moves meanwhile.
+    let mut mags = MAGAZINES[CpuTable::cpu_id()].lock();
+    let mag = &mut mags[class];
+    if mag.len > 0 {
+        mag.hits += 1;
+        mag.len -= 1;
+        return mag.objs[mag.len];
+    }
+
+    mag.misses += 1;
+    let mut cache = CACHES[class].lock();
+    while mag.len < MAG_SIZE / 2 {
+        match unsafe { cache.alloc() } {
+            Some(obj) => {
+                mag.objs[mag.len] = obj;
+                mag.len += 1;
+            }
+            None => break,
+        }
+    }
+    drop(cache);
+    if mag.len == 0 {
+        return 0;
+    }
+    mag.len -= 1;
+    mag.objs[mag.len]
+}
+
+/// frees the object at `obj`, which `alloc(class)` returned.
+pub fn free(obj: usize, class: usize) {
+    let mut mags = MAGAZINES[CpuTable::cpu_id()].lock();
+    let mag = &mut mags[class];
+    if mag.len == MAG_SIZE {
+        let mut cache = CACHES[class].lock();
+        while mag.len > MAG_SIZE / 2 {
+            mag.len -= 1;
+            unsafe { cache.free(mag.objs[mag.len]) };
+        }
+        drop(cache);
+    }
+    mag.objs[mag.len] = obj;
+    mag.len += 1;
+}
+
+/// the usage of a cache.
+#[derive(Clone, Copy, Debug)]
+pub struct CacheStats {
+    /// the size of its objects.
+    pub size: usize,
+    pub slabs: usize,
+    /// the pages of its slabs.
+    pub pages: usize,
+    /// the objects its slabs have room for.
+    pub capacity: usize,
+    /// the objects in use, not counting those cached in the magazines.
+    pub inuse: usize,
+    /// the free objects in the magazines of the CPUs.
+    pub cached: usize,
+    /// the allocations served by a magazine, and those that went to the cache.
+    pub hits: usize,
+    pub misses: usize,
+}
+
+/// the usage of the cache of each class.
+pub fn stats() -> [CacheStats; NCLASS] {
+    let mut stats = array![class => {
+        let cache = CACHES[class].lock();
+        CacheStats {
+            size: cache.size,
+            slabs: cache.slabs,
+            pages: cache.slabs << cache.order(),
+            capacity: cache.slabs * cache.per_slab(),
+            inuse: cache.inuse,
+            cached: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }; NCLASS];
+    for mags in MAGAZINES.iter() {
+        let mags = mags.lock();
+        for (stat, mag) in stats.iter_mut().zip(mags.iter()) {
+            stat.cached += mag.len;
+            stat.hits += mag.hits;
+            stat.misses += mag.misses;
+        }
+    }
+    for stat in stats.iter_mut() {
+        stat.inuse -= stat.cached.min(stat.inuse);
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn classes() {
+        assert_eq!(Some(0), class_of(1, 1));
+        assert_eq!(Some(0), class_of(16, 8));
+        assert_eq!(Some(1), class_of(17, 8));
+        assert_eq!(Some(2), class_of(8, 64));
+        assert_eq!(Some(NCLASS - 1), class_of(MAX_SIZE, 8));
+        assert_eq!(None, class_of(MAX_SIZE + 1, 8));
+    }
+
+    #[test_case]
+    fn objects_are_distinct_and_reused() {
+        let class = class_of(64, 8).unwrap();
+        let objs: [usize; 3 * MAG_SIZE] = array![_ => alloc(class); 3 * MAG_SIZE];
+        for (i, &obj) in objs.iter().enumerate() {
+            assert_ne!(0, obj);
+            assert_eq!(0, obj % 64);
+            assert!(objs[i + 1..].iter().all(|&other| other != obj));
+        }
+        for &obj in objs.iter() {
+            free(obj, class);
+        }
+
+        let obj = alloc(class);
+        assert!(objs.contains(&obj));
+        free(obj, class);
+    }
+}
@@ -0,0 +1,168 @@
+//! Counting the kernel objects by kind.
+//!
+//! The objects of a tagged kind, such as the open files and the packet buffers, hold a `Tracked`
+//! that counts them from their creation to their drop. With the `kalloc-debug` feature, the call
+//! site that created each live object is recorded as well, so that `dump` can list the objects
+//! that are never freed by where they come from.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use array_macro::array;
+
+pub const NTAG: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Tag {
+    File = 0,
+    Pipe = 1,
+    Socket = 2,
+    MBuf = 3,
+}
+
+const NAMES: [&str; NTAG] = ["file", "pipe", "socket", "mbuf"];
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TagCount {
+    /// the objects alive.
+    pub live: usize,
+    /// the objects ever created.
+    pub total: usize,
+}
+
+static LIVE: [AtomicUsize; NTAG] = array![_ => AtomicUsize::new(0); NTAG];
+static TOTAL: [AtomicUsize; NTAG] = array![_ => AtomicUsize::new(0); NTAG];
+
+/// counts an object of its tag while it lives.
+pub struct Tracked {
+    tag: Tag,
+    #[cfg(feature = "kalloc-debug")]
+    id: usize,
+}
+
+impl Tracked {
+    #[track_caller]
+    pub fn new(tag: Tag) -> Self {
+        LIVE[tag as usize].fetch_add(1, Ordering::Relaxed);
+        TOTAL[tag as usize].fetch_add(1, Ordering::Relaxed);
+        Self {
+            tag,
+            #[cfg(feature = "kalloc-debug")]
+            id: sites::insert(tag, core::panic::Location::caller()),
+        }
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        LIVE[self.tag as usize].fetch_sub(1, Ordering::Relaxed);
+        #[cfg(feature = "kalloc-debug")]
+        sites::remove(self.id);
+    }
+}
+
+pub fn counts() -> [TagCount; NTAG] {
+    array![i => TagCount {
+        live: LIVE[i].load(Ordering::Relaxed),
+        total: TOTAL[i].load(Ordering::Relaxed),
+    }; NTAG]
+}
+
+pub fn name(i: usize) -> &'static str {
+    NAMES[i]
+}
+
+/// prints the live objects, grouped by the site that created them, with the age of the oldest
+/// one in ticks.
+#[cfg(feature = "kalloc-debug")]
+pub fn dump() -> Result<(), &'static str> {
+    sites::dump();
+    Ok(())
+}
+
+#[cfg(not(feature = "kalloc-debug"))]
+pub fn dump() -> Result<(), &'static str> {
+    Err("the call sites are recorded with the kalloc-debug feature only")
+}
+
+#[cfg(feature = "kalloc-debug")]
+mod sites {
+    use alloc::collections::BTreeMap;
+    use core::panic::Location;
+
+    use crate::{println, spinlock::SpinLock, trap};
+
+    use super::{Tag, NAMES};
+
+    struct Sites {
+        next: usize,
+        // the tag, site and creation tick of each live object, by id.
+        live: BTreeMap<usize, (Tag, &'static Location<'static>, usize)>,
+    }
+
+    static SITES: SpinLock<Sites> = SpinLock::new(
+        Sites {
+            next: 0,
+            live: BTreeMap::new(),
+        },
+        "sites",
+    );
+
+    pub fn insert(tag: Tag, site: &'static Location<'static>) -> usize {
+        let now = trap::ticks();
+        let mut sites = SITES.lock();
+        let id = sites.next;
+        sites.next += 1;
+        sites.live.insert(id, (tag, site, now));
+        drop(sites);
+        id
+    }
+
+    pub fn remove(id: usize) {
+        SITES.lock().live.remove(&id);
+    }
+
+    pub fn dump() {
+        let now = trap::ticks();
+        // (the objects, the oldest creation tick) by site.
+        let mut by_site: BTreeMap<(Tag, &'static Location<'static>), (usize, usize)> =
+            BTreeMap::new();
+        let sites = SITES.lock();
+        for &(tag, site, created) in sites.live.values() {
+            let entry = by_site.entry((tag, site)).or_insert((0, created));
+            entry.0 += 1;
+            entry.1 = entry.1.min(created);
+        }
+        drop(sites);
+
+        println!("live objects by site:");
+        for ((tag, site), (n, oldest)) in by_site {
+            println!(
+                "{:<6} {:>6} {}:{} oldest {} ticks",
+                NAMES[tag as usize],
+                n,
+                site.file(),
+                site.line(),
+                now - oldest
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn tracked_objects_are_counted() {
+        let before = counts()[Tag::Pipe as usize];
+        let t = Tracked::new(Tag::Pipe);
+        let during = counts()[Tag::Pipe as usize];
+        assert_eq!(before.live + 1, during.live);
+        assert_eq!(before.total + 1, during.total);
+        drop(t);
+        let after = counts()[Tag::Pipe as usize];
+        assert_eq!(before.live, after.live);
+        assert_eq!(during.total, after.total);
+    }
+}
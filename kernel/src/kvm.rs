@@ -4,7 +4,7 @@ use crate::{
     page_table::{PageTable, PteFlag},
     param::{
         CLINT, CLINT_MAP_SIZE, E1000_REGS_ADDR, ECAM0, KERNBASE, PAGESIZE, PHYSTOP, PLIC,
-        PLIC_MAP_SIZE, TRAMPOLINE, UART0, VIRTIO0,
+        PLIC_MAP_SIZE, TRAMPOLINE, UART0, VIRTIO0, VIRTIO1,
     },
     register::satp,
 };
@@ -27,6 +27,7 @@ pub unsafe fn init() {
 
     // virtio registers
     kvm_map(VIRTIO0, VIRTIO0, PAGESIZE, PteFlag::READ | PteFlag::WRITE);
+    kvm_map(VIRTIO1, VIRTIO1, PAGESIZE, PteFlag::READ | PteFlag::WRITE);
 
     // PCI-E ECAM (configuration space) for e1000
     kvm_map(ECAM0, ECAM0, 0x1000_0000, PteFlag::READ | PteFlag::WRITE);
@@ -39,18 +39,23 @@ mod kvm;
 mod log;
 mod mbuf;
 mod net;
+mod oom;
 mod page_table;
+mod pagecache;
 mod param;
 mod pci;
 mod plic;
 pub mod printf;
 mod proc;
 mod process;
+mod random;
 mod register;
+mod sched;
 mod sleeplock;
 mod spinlock;
 mod start;
 mod superblock;
+mod swap;
 mod trap;
 mod uart;
 mod virtio;
@@ -71,7 +76,8 @@ pub unsafe fn bootstrap() -> ! {
         plic::init(); // set up interrupt controller
         plic::init_hart(cpu_id); // ask PLIC for device interrupts
         BCACHE.init(); // buffer cache
-        DISK.lock().init(); // emulated hard disk
+        DISK.lock().init().expect("virtio disk"); // emulated hard disk
+        swap::init(); // swap area on the second disk
         pci::init(); // pci
 
         PROCESS_TABLE.user_init(); // first user process
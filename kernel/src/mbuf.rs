@@ -1,5 +1,7 @@
 use alloc::boxed::Box;
 
+use crate::kalloc::{Tag, Tracked, ENOMEM};
+
 const MBUF_SIZE: usize = 2048;
 
 /// packet buffer management
@@ -9,24 +11,28 @@ pub struct MBuf {
     len: isize,
     // buffer
     buf: [u8; MBUF_SIZE],
+    _tracked: Tracked,
 }
 
 // https://doc.rust-lang.org/nomicon/send-and-sync.html
 // unsafe impl Send for MBuf {}
 
 impl MBuf {
-    pub fn alloc(headroom: usize) -> Box<Self> {
+    #[track_caller]
+    pub fn alloc(headroom: usize) -> Result<Box<Self>, &'static str> {
         if headroom > MBUF_SIZE {
             panic!("mbuf_alloc");
         }
 
-        let mut mbuf = Box::new(Self {
+        let mut mbuf = Box::try_new(Self {
             head: 0,
             len: 0,
             buf: [0u8; MBUF_SIZE],
-        });
+            _tracked: Tracked::new(Tag::MBuf),
+        })
+        .or(Err(ENOMEM))?;
         mbuf.head = mbuf.buf.as_mut_ptr() as usize + headroom;
-        mbuf
+        Ok(mbuf)
     }
 
     pub fn get_buf_head(&mut self) -> *mut u8 {
@@ -104,8 +104,13 @@ impl Table {
     }
 }
 
-pub fn tx(op: Operand, hw_addr: &[u8; 6], dst_mac: &[u8; 6], dst_ip: u32) {
-    let mut m = MBuf::alloc(128);
+pub fn tx(
+    op: Operand,
+    hw_addr: &[u8; 6],
+    dst_mac: &[u8; 6],
+    dst_ip: u32,
+) -> Result<(), &'static str> {
+    let mut m = MBuf::alloc(128)?;
     let mut hdr = unsafe { (m.append(mem::size_of::<Header>()) as *mut Header).as_mut() }.unwrap();
     hdr.htype = toggle_endian16(ETH_HTYPE);
     hdr.ptype = toggle_endian16(IPV4_PTYPE);
@@ -118,6 +123,7 @@ pub fn tx(op: Operand, hw_addr: &[u8; 6], dst_mac: &[u8; 6], dst_ip: u32) {
     hdr.tpa = toggle_endian32(dst_ip);
 
     ethernet::tx(m, ethernet::Type::ARP, dst_mac);
+    Ok(())
 }
 
 pub fn rx(mut m: Box<MBuf>) {
@@ -125,7 +131,8 @@ pub fn rx(mut m: Box<MBuf>) {
     let op = toggle_endian16(hdr.oper);
     match op.try_into().unwrap() {
         Operand::Request => {
-            tx(Operand::Reply, &hdr.sha, &hdr.sha, toggle_endian32(hdr.spa));
+            // without memory for the reply, the request is dropped. the peer will ask again.
+            let _ = tx(Operand::Reply, &hdr.sha, &hdr.sha, toggle_endian32(hdr.spa));
         }
         Operand::Reply => {
             // println!("arp_rx: reply op received");
@@ -151,7 +158,7 @@ pub fn resolve(ip_addr: u32, mac_addr: &mut [u8; 6]) -> Result<(), &'static str>
                     &ETHERNET_MAC_ADDR_ANY,
                     &ETHERNET_MAC_ADDR_BROADCAST,
                     ip_addr,
-                );
+                )?;
                 return Err("same");
             }
             unsafe { ptr::copy_nonoverlapping(entry.mac_addr.as_ptr(), mac_addr.as_mut_ptr(), 6) };
@@ -169,7 +176,7 @@ pub fn resolve(ip_addr: u32, mac_addr: &mut [u8; 6]) -> Result<(), &'static str>
                 &GATEWAY_MAC_ADDR,
                 &ETHERNET_MAC_ADDR_BROADCAST,
                 ip_addr,
-            );
+            )?;
             return Err("cannot resolved");
         }
         None => return Err("full arp table"),
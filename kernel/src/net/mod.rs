@@ -2,7 +2,11 @@ use core::convert::{TryFrom, TryInto};
 
 use alloc::boxed::Box;
 
-use crate::{cpu::CPU_TABLE, mbuf::MBuf};
+use crate::{
+    cpu::CPU_TABLE,
+    kalloc::{Tag, Tracked},
+    mbuf::MBuf,
+};
 
 mod arp;
 mod ethernet;
@@ -80,9 +84,11 @@ impl TryFrom<u8> for SocketType {
 pub struct Socket {
     cb_idx: usize,
     typ: SocketType,
+    _tracked: Tracked,
 }
 
 impl Socket {
+    #[track_caller]
     pub fn new(typ: u8) -> Result<Self, &'static str> {
         let typ = typ.try_into().unwrap();
         let cb_idx = match &typ {
@@ -93,7 +99,11 @@ impl Socket {
             }
         };
 
-        Ok(Self { cb_idx, typ })
+        Ok(Self {
+            cb_idx,
+            typ,
+            _tracked: Tracked::new(Tag::Socket),
+        })
     }
 
     pub fn bind(&self, addr: &SockAddr) -> Result<(), &'static str> {
@@ -129,7 +139,8 @@ impl Socket {
             SocketType::TCP => panic!("unimplemented"),
             SocketType::UDP => {
                 // setup a mbuf and copy data from user space
-                let mut m = MBuf::alloc(ethernet::HEADER_SIZE + ip::HEADER_SIZE + udp::HEADER_SIZE);
+                let mut m =
+                    MBuf::alloc(ethernet::HEADER_SIZE + ip::HEADER_SIZE + udp::HEADER_SIZE)?;
                 m.append(len);
                 
                 let p = unsafe { CPU_TABLE.my_proc() };
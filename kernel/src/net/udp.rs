@@ -235,23 +235,26 @@ pub fn read(idx: usize, addr: usize, mut len: usize) -> Result<usize, &'static s
     let mut guard = cb.data.lock();
     let slept_chan = cb.meta.get() as usize;
     while guard.mbuf_queue.is_empty() {
+        let p = unsafe { CPU_TABLE.my_proc() };
+        if p.is_killed() {
+            drop(guard);
+            return Err("udp read: killed");
+        }
         // sleep the process while connection's mbuf queue is empty.
-        guard = unsafe { CPU_TABLE.my_proc() }.sleep(slept_chan, guard);
+        guard = p.sleep(slept_chan, guard);
     }
 
     // TODO: naive implementation
     let mut m = guard.mbuf_queue.remove(0);
+    // the copy may fault the user page in and sleep, so the queue is let go first.
+    drop(guard);
     if len > m.get_len() {
         len = m.get_len();
     }
 
-    unsafe { CPU_TABLE.my_proc() }
-        .data
-        .get_mut()
-        .copy_out(addr, m.get_buf_head(), len)?;
-    drop(guard);
+    let res = either_copy_out(true, addr as *mut u8, m.get_buf_head(), len);
     drop(m);
-    Ok(len)
+    res.map(|()| len)
 }
 
 #[cfg(test)]
@@ -339,7 +342,7 @@ mod tests {
         )
         .is_ok());
 
-        let mut m = MBuf::alloc(mem::size_of::<Header>());
+        let mut m = MBuf::alloc(mem::size_of::<Header>()).unwrap();
         send(m, c0).unwrap();
 
         let mut m = unsafe { MBUFS.pop() }.unwrap();
@@ -0,0 +1,55 @@
+//! Recovering from running out of memory in a page fault.
+//!
+//! A syscall that finds no memory left fails with ENOMEM, but a page fault has no caller to
+//! return it to. the process would take the fault again and again, so memory is freed for it
+//! instead: first the pages of the page cache that no process maps, then the user pages swapped
+//! out, then the pages of a process killed for it, the one that has the most. the faulting
+//! instruction is retried once the process runs again, unless it is the one killed. init is never
+//! killed, as the kernel cannot go on without it: it waits for memory to be freed instead.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    pagecache, println,
+    proc::{signal, Proc},
+    process::PROCESS_TABLE,
+    swap, trap,
+};
+
+static KILLS: AtomicUsize = AtomicUsize::new(0);
+
+/// the processes killed for memory so far, reported by meminfo().
+pub fn kills() -> usize {
+    KILLS.load(Ordering::Relaxed)
+}
+
+pub unsafe fn recover(p: &mut Proc) {
+    if pagecache::shrink() > 0 || swap::reclaim(swap::BATCH) > 0 {
+        return;
+    }
+
+    match PROCESS_TABLE.oom_victim(p) {
+        Some(victim) if victim.index != p.index => {
+            let mut guard = victim.inner.lock();
+            if !guard.killed {
+                println!("oom: killing pid {}", guard.pid);
+                signal::post(victim, &mut guard, signal::SIGKILL);
+                KILLS.fetch_add(1, Ordering::Relaxed);
+            }
+            drop(guard);
+            // let the victim exit, which frees its memory.
+            p.yield_process();
+        }
+        _ if p.index == 0 => {
+            // a process exiting, or the page cache, may free some meanwhile.
+            let _ = trap::sleep_ticks(p, 1);
+        }
+        _ => {
+            let mut guard = p.inner.lock();
+            println!("oom: killing pid {}", guard.pid);
+            signal::post(p, &mut guard, signal::SIGKILL);
+            KILLS.fetch_add(1, Ordering::Relaxed);
+            drop(guard);
+        }
+    }
+}
@@ -8,7 +8,12 @@ use core::{
 use alloc::boxed::Box;
 use bitflags::bitflags;
 
-use crate::param::{PAGESIZE, TRAMPOLINE, TRAPFRAME};
+use crate::{
+    kalloc::{ENOMEM, FRAMES},
+    param::{MAXVA, PAGESIZE, TRAMPOLINE, TRAPFRAME},
+    process::PROCESS_TABLE,
+    swap,
+};
 
 bitflags! {
     pub struct PteFlag: usize {
@@ -20,6 +25,36 @@ bitflags! {
         const GLOB = 1 << 5;
         const ACCES = 1 << 6;
         const DIRTY = 1 << 7;
+        // reserved for software: the page is shared copy-on-write.
+        const COW = 1 << 8;
+        // reserved for software: the PTE is not valid, and holds the swap slot of the page
+        // swapped out in place of its physical page number.
+        const SWAP = 1 << 9;
+    }
+}
+
+/// the error of an access to a page that is swapped out, which must be faulted in first.
+pub const SWAPPED_OUT: &str = "the page is swapped out";
+
+/// takes another reference to the page, kept in its frame's metadata.
+/// pages shared by fork() are freed by whichever page table lets go of them last.
+pub fn share_page(pa: usize) {
+    FRAMES.lock().share(pa);
+}
+
+pub fn is_shared_page(pa: usize) -> bool {
+    FRAMES.lock().is_shared(pa)
+}
+
+/// drops a reference to the page. returns true if it was the last one, and the page is to be freed.
+fn release_page(pa: usize) -> bool {
+    FRAMES.lock().release(pa)
+}
+
+/// drops a reference to the page, and frees it with the last one.
+pub fn put_page(pa: usize) {
+    if release_page(pa) {
+        unsafe { SinglePage::free_from_raw(pa as *mut SinglePage) };
     }
 }
 
@@ -67,30 +102,39 @@ impl PageTable {
         (1 << 63) | ((self as *const PageTable as usize) >> 12)
     }
 
+    /// waits until the harts running other threads of this page table have dropped the TLB
+    /// entries of the pages unmapped or made less accessible so far.
+    pub fn flush_tlb(&self) {
+        unsafe { PROCESS_TABLE.flush_tlb(self.as_satp()) };
+    }
+
     /// Allocate a new user page table.
-    pub fn alloc_user_page_table(trapframe: usize) -> Option<Box<Self>> {
+    pub fn alloc_user_page_table(trapframe: usize) -> Result<Box<Self>, &'static str> {
         extern "C" {
             fn trampoline(); // in trampoline.S
         }
-        let mut pgt = unsafe { Box::<Self>::try_new_zeroed().ok()?.assume_init() };
+        let mut pgt = unsafe { Box::<Self>::try_new_zeroed().or(Err(ENOMEM))?.assume_init() };
 
         pgt.map_pages(
             TRAMPOLINE,
             trampoline as usize,
             PAGESIZE,
             PteFlag::READ | PteFlag::EXEC,
-        )
-        .ok()?;
+        )?;
 
-        pgt.map_pages(
+        if let Err(msg) = pgt.map_pages(
             TRAPFRAME,
             trapframe,
             PAGESIZE,
             PteFlag::READ | PteFlag::WRITE,
-        )
-        .ok()?;
+        ) {
+            // the page table is freed only without leaves.
+            pgt.unmap_pages(TRAMPOLINE, 1, false)
+                .expect("cannot unmap trampoline");
+            return Err(msg);
+        }
 
-        Some(pgt)
+        Ok(pgt)
     }
 
     /// Load the user initcode into address 0 of pagetable,
@@ -104,11 +148,12 @@ impl PageTable {
         let mem = unsafe {
             SinglePage::alloc_into_raw().or_else(|_| Err("uvm_init: insufficient memory"))?
         };
+        // dirty, as the page is not zero and can only be swapped out.
         self.map_pages(
             0,
             mem as usize,
             PAGESIZE,
-            PteFlag::READ | PteFlag::WRITE | PteFlag::EXEC | PteFlag::USER,
+            PteFlag::READ | PteFlag::WRITE | PteFlag::EXEC | PteFlag::USER | PteFlag::DIRTY,
         )?;
 
         // copy the code
@@ -126,11 +171,47 @@ impl PageTable {
         self.unmap_pages(TRAPFRAME, 1, false)
             .expect("cannot unmap trampframe");
         if sz > 0 {
-            self.unmap_pages(0, align_up(sz, PAGESIZE) / PAGESIZE, true)
+            self.uvm_unmap(0, align_up(sz, PAGESIZE) / PAGESIZE)
                 .expect("cannot unmap process");
         }
     }
 
+    /// Unmap `n` pages of user memory from `va_start` and free them.
+    /// pages that have never been touched, and so never allocated, are skipped. the slots of the
+    /// pages swapped out are freed.
+    pub fn uvm_unmap(&mut self, va_start: usize, n: usize) -> Result<(), &'static str> {
+        for va in (va_start..(va_start + n * PAGESIZE)).step_by(PAGESIZE) {
+            if matches!(self.walk(va), Some(pte) if pte.is_valid()) {
+                self.unmap_pages(va, 1, true)?;
+            } else if let Some(slot) = self.take_swapped(va) {
+                swap::put(slot);
+            }
+        }
+        Ok(())
+    }
+
+    /// Allocate zeroed pages for the part of [va, va + len) below `sz` that is not yet mapped.
+    /// the heap grown by sbrk() is only backed by physical memory once it is touched.
+    pub fn uvm_lazy_alloc(&mut self, va: usize, len: usize, sz: usize) -> Result<(), &'static str> {
+        let end = min(va.saturating_add(len), sz);
+        for va in (align_down(va, PAGESIZE)..end).step_by(PAGESIZE) {
+            if self.walk_addr(va).is_ok() {
+                continue;
+            }
+            let mem = unsafe { SinglePage::alloc_into_raw() }.or(Err(ENOMEM))?;
+            if let Err(msg) = self.map_pages(
+                va,
+                mem as usize,
+                PAGESIZE,
+                PteFlag::READ | PteFlag::WRITE | PteFlag::EXEC | PteFlag::USER,
+            ) {
+                unsafe { SinglePage::free_from_raw(mem) };
+                return Err(msg);
+            }
+        }
+        Ok(())
+    }
+
     /// Allocate PTEs and physical memory to grow process from oldsz to newsz, which need not to be
     /// aligned. returns new size or an error.
     pub fn uvm_alloc(&mut self, oldsz: usize, newsz: usize) -> Result<usize, &'static str> {
@@ -145,7 +226,7 @@ impl PageTable {
                     Ok(mem) => mem,
                     Err(_) => {
                         self.uvm_dealloc(oldsz, newsz)?;
-                        return Err("uvm_alloc: insufficient memory");
+                        return Err(ENOMEM);
                     }
                 }
             };
@@ -181,7 +262,7 @@ impl PageTable {
         oldsz = align_up(oldsz, PAGESIZE);
         newsz = align_up(newsz, PAGESIZE);
         if newsz < oldsz {
-            self.unmap_pages(newsz, (oldsz - newsz) / PAGESIZE, true)?;
+            self.uvm_unmap(newsz, (oldsz - newsz) / PAGESIZE)?;
         }
 
         Ok(newsz)
@@ -192,27 +273,150 @@ impl PageTable {
         pte.data &= !PteFlag::USER.bits();
     }
 
-    /// copy its memory into a child's page table.
-    /// copies both the page table and the phisical memory.
-    pub fn uvm_copy(&mut self, child: &mut PageTable, sz: usize) -> Result<(), ()> {
-        for i in (0..sz).step_by(PAGESIZE) {
-            let pte = self.walk(i).expect("uvm_copy: pte should exist");
-            if !pte.is_valid() {
-                panic!("uvm_copy: page not present");
-            }
+    /// share its memory in [start, sz) with a child's page table.
+    /// copies only the page table. writable pages become read-only and copy-on-write in both, and
+    /// the first store to one gets a private copy with `resolve_cow()`.
+    /// pages not allocated yet are left to be allocated lazily in the child too.
+    pub fn uvm_copy(&mut self, child: &mut PageTable, start: usize, sz: usize) -> Result<(), ()> {
+        let n = (align_up(sz, PAGESIZE) - start) / PAGESIZE;
+        self.uvm_share(child, start, n, true)
+    }
+
+    /// maps the pages mapped in [va_start, va_start + n pages) into `child` at the same addresses.
+    /// if `cow`, writable pages become copy-on-write in both, like `uvm_copy()`. otherwise they
+    /// stay writable, and stores from either side are seen by the other, as in a MAP_SHARED
+    /// region. the pages swapped out share their slots, and each side reads its own copy back.
+    /// on failure, the pages mapped into `child` are unmapped again.
+    pub fn uvm_share(
+        &mut self,
+        child: &mut PageTable,
+        va_start: usize,
+        n: usize,
+        cow: bool,
+    ) -> Result<(), ()> {
+        for va in (va_start..(va_start + n * PAGESIZE)).step_by(PAGESIZE) {
+            let pte = match self.walk_mut(va) {
+                Some(pte) if pte.is_valid() => pte,
+                Some(pte) if pte.is_swapped() => {
+                    let slot = pte.as_swap_slot();
+                    match child.walk_alloc(va) {
+                        Some(cpte) if !cpte.is_valid() && !cpte.is_swapped() => {
+                            cpte.set_swap_slot(slot);
+                            swap::share(slot);
+                            continue;
+                        }
+                        _ => {
+                            child
+                                .uvm_unmap(va_start, (va - va_start) / PAGESIZE)
+                                .expect("uvm_share: cannot undo");
+                            return Err(());
+                        }
+                    }
+                }
+                _ => continue,
+            };
             let pa = pte.as_phys_addr();
-            let flag = pte.get_flag();
-            let mem = unsafe { SinglePage::alloc_into_raw() }.or_else(|_| Err(()))?;
-            unsafe { ptr::copy_nonoverlapping(pa as *const SinglePage, mem as *mut _, 1) };
-            if child.map_pages(i, mem as usize, PAGESIZE, flag).is_err() {
-                unsafe { SinglePage::free_from_raw(mem) };
+            let mut flag = pte.get_flag();
+            if cow && flag.contains(PteFlag::WRITE) {
+                flag.remove(PteFlag::WRITE);
+                flag.insert(PteFlag::COW);
+                pte.set_addr(as_pte_addr(pa), flag);
+            }
+            if child.map_pages(va, pa, PAGESIZE, flag).is_err() {
+                child
+                    .uvm_unmap(va_start, (va - va_start) / PAGESIZE)
+                    .expect("uvm_share: cannot undo");
                 return Err(());
             };
+            share_page(pa);
         }
 
         Ok(())
     }
 
+    /// gives the page at `va` a private, writable copy if it is shared copy-on-write.
+    /// returns false if the page is not copy-on-write.
+    pub fn resolve_cow(&mut self, va: usize) -> Result<bool, &'static str> {
+        let va = align_down(va, PAGESIZE);
+        if va >= MAXVA {
+            return Ok(false);
+        }
+        let pte = match self.walk_mut(va) {
+            Some(pte) if pte.is_valid() && pte.is_user() && pte.is_cow() => pte,
+            _ => return Ok(false),
+        };
+
+        let pa = pte.as_phys_addr();
+        let mut flag = pte.get_flag();
+        flag.remove(PteFlag::COW);
+        flag.insert(PteFlag::WRITE);
+        if !is_shared_page(pa) {
+            // the others have already made their copies.
+            pte.set_addr(as_pte_addr(pa), flag);
+            return Ok(true);
+        }
+
+        let mem = unsafe { SinglePage::alloc_into_raw() }.or(Err(ENOMEM))?;
+        unsafe { ptr::copy_nonoverlapping(pa as *const SinglePage, mem, 1) };
+        pte.set_addr(as_pte_addr(mem as usize), flag);
+        // the other threads may still read the old page through their TLBs, and the others may
+        // have let go of it while copying.
+        self.flush_tlb();
+        if release_page(pa) {
+            unsafe { SinglePage::free_from_raw(pa as *mut SinglePage) };
+        }
+
+        // the stale TLB entry is flushed on the way back to user space.
+        Ok(true)
+    }
+
+    /// changes the permissions of the pages mapped in [va_start, va_start + n pages) to `prot`, a
+    /// combination of READ, WRITE and EXEC. the pages not allocated yet are skipped.
+    /// if `cow`, a page still shared with another page table becomes copy-on-write rather than
+    /// writable.
+    pub fn uvm_protect(&mut self, va_start: usize, n: usize, prot: PteFlag, cow: bool) {
+        for va in (va_start..(va_start + n * PAGESIZE)).step_by(PAGESIZE) {
+            let pte = match self.walk_mut(va) {
+                Some(pte) if pte.is_valid() => pte,
+                _ => continue,
+            };
+            let pa = pte.as_phys_addr();
+            let mut flag = pte.get_flag();
+            flag.remove(
+                PteFlag::READ | PteFlag::WRITE | PteFlag::EXEC | PteFlag::USER | PteFlag::COW,
+            );
+            if prot.is_empty() {
+                // a valid PTE without any permission would point to the next level, so keep the
+                // page readable but out of reach of user space, like a guard page.
+                flag.insert(PteFlag::READ);
+            } else {
+                flag.insert(PteFlag::USER | (prot & (PteFlag::READ | PteFlag::EXEC)));
+                if prot.contains(PteFlag::WRITE) {
+                    flag.insert(PteFlag::READ);
+                    if cow && is_shared_page(pa) {
+                        flag.insert(PteFlag::COW);
+                    } else {
+                        flag.insert(PteFlag::WRITE);
+                    }
+                }
+            }
+            pte.set_addr(as_pte_addr(pa), flag);
+        }
+    }
+
+    /// tells whether the page at `va` has been written to since the last call, and clears the
+    /// dirty bit. the hardware sets it on a store from user space, and `copy_out()` on one from
+    /// the kernel.
+    pub fn take_dirty(&mut self, va: usize) -> bool {
+        match self.walk_mut(va) {
+            Some(pte) if pte.is_valid() && pte.is_dirty() => {
+                pte.data &= !PteFlag::DIRTY.bits();
+                true
+            }
+            _ => false,
+        }
+    }
+
     pub fn map_pages(
         &mut self,
         va: usize,
@@ -228,14 +432,14 @@ impl PageTable {
         for va in (va_start..va_end).step_by(PAGESIZE) {
             match self.walk_alloc(va) {
                 Some(pte) => {
-                    if pte.is_valid() {
+                    if pte.is_valid() || pte.is_swapped() {
                         return Err("map_pages: remap");
                     } else {
                         pte.set_addr(as_pte_addr(pa), perm);
                     }
                 }
                 None => {
-                    return Err("map_pages: not enough memory for new page table");
+                    return Err(ENOMEM);
                 }
             }
 
@@ -269,7 +473,9 @@ impl PageTable {
                     }
                     if freeing {
                         let pa = pte.as_phys_addr();
-                        unsafe { SinglePage::free_from_raw(pa as *mut SinglePage) };
+                        if release_page(pa) {
+                            unsafe { SinglePage::free_from_raw(pa as *mut SinglePage) };
+                        }
                     }
                     pte.data = 0;
                 }
@@ -333,10 +539,108 @@ impl PageTable {
         unsafe { Some(&mut page_table.as_mut().unwrap()[get_index(va, 0)]) }
     }
 
+    /// the physical address and the flags of the page mapped at `va`, whether it is a user page or
+    /// not.
+    pub fn lookup(&self, va: usize) -> Option<(usize, PteFlag)> {
+        if va >= MAXVA {
+            return None;
+        }
+        match self.walk(va) {
+            Some(pte) if pte.is_valid() => Some((pte.as_phys_addr(), pte.get_flag())),
+            _ => None,
+        }
+    }
+
+    /// the lowest address in [va, end) that a user page is mapped at. the tables that map
+    /// nothing are skipped over as a whole.
+    pub fn next_user_page(&self, va: usize, end: usize) -> Option<usize> {
+        let mut va = align_down(va, PAGESIZE);
+        'next: while va < end.min(MAXVA) {
+            let mut page_table = self as *const PageTable;
+            for level in (1..=2).rev() {
+                let pte = unsafe { &page_table.as_ref().unwrap()[get_index(va, level)] };
+                if !pte.is_valid() {
+                    let size = PAGESIZE << (9 * level);
+                    va = align_down(va, size) + size;
+                    continue 'next;
+                }
+                page_table = pte.as_page_table();
+            }
+            let pte = unsafe { &page_table.as_ref().unwrap()[get_index(va, 0)] };
+            if pte.is_valid() && pte.is_user() {
+                return Some(va);
+            }
+            va += PAGESIZE;
+        }
+        None
+    }
+
+    /// tells whether the page at `va` has been accessed since the last call, and clears the
+    /// accessed bit. the hardware sets it on any access from user space.
+    pub fn take_accessed(&mut self, va: usize) -> bool {
+        match self.walk_mut(va) {
+            Some(pte) if pte.is_valid() && pte.get_flag().contains(PteFlag::ACCES) => {
+                pte.data &= !PteFlag::ACCES.bits();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// the swap slot of the page at `va`, if it is swapped out.
+    pub fn swapped(&self, va: usize) -> Option<usize> {
+        match self.walk(va) {
+            Some(pte) if pte.is_swapped() => Some(pte.as_swap_slot()),
+            _ => None,
+        }
+    }
+
+    /// replaces the page mapped at `va` with `slot` of the swap area, which holds a copy of it.
+    /// returns the physical address of the page, which the caller frees.
+    pub fn swap_out(&mut self, va: usize, slot: usize) -> usize {
+        let pte = match self.walk_mut(va) {
+            Some(pte) if pte.is_valid() && pte.is_leaf() => pte,
+            _ => panic!("swap_out: not mapped"),
+        };
+        let pa = pte.as_phys_addr();
+        pte.set_swap_slot(slot);
+        pa
+    }
+
+    /// takes the slot of the page swapped out at `va`, which is left unmapped.
+    pub fn take_swapped(&mut self, va: usize) -> Option<usize> {
+        match self.walk_mut(va) {
+            Some(pte) if pte.is_swapped() => {
+                let slot = pte.as_swap_slot();
+                pte.data = 0;
+                Some(slot)
+            }
+            _ => None,
+        }
+    }
+
+    /// the number of user pages mapped.
+    pub fn user_pages(&self) -> usize {
+        self.count_user_pages(2)
+    }
+
+    fn count_user_pages(&self, level: usize) -> usize {
+        self.entries
+            .iter()
+            .filter(|pte| pte.is_valid())
+            .map(|pte| match level {
+                0 => pte.is_user() as usize,
+                _ => unsafe { (*pte.as_page_table()).count_user_pages(level - 1) },
+            })
+            .sum()
+    }
+
     pub fn walk_addr(&self, va: usize) -> Result<usize, &'static str> {
         match self.walk(va) {
             Some(pte) => {
-                if !pte.is_valid() {
+                if pte.is_swapped() {
+                    Err(SWAPPED_OUT)
+                } else if !pte.is_valid() {
                     Err("walk_addr: pte is not valid")
                 } else if !pte.is_user() {
                     Err("walk_addr: pte is not user")
@@ -406,8 +710,10 @@ impl PageTable {
 
     /// Copy from kernel to user.
     /// Copy `count` bytes from `src` to virtual address `dstva` in a given page table.
+    /// Pages shared copy-on-write are copied first, and the pages are marked dirty. Fails on a page
+    /// that user space cannot write to either.
     pub fn copy_out(
-        &self,
+        &mut self,
         mut dstva: usize,
         mut src: *const u8,
         mut count: usize,
@@ -415,7 +721,13 @@ impl PageTable {
         while count > 0 {
             let va_base = align_down(dstva, PAGESIZE);
             let distance = dstva as usize - va_base;
+            self.resolve_cow(va_base)?;
             let dstpa = unsafe { (self.walk_addr(va_base)? as *mut u8).offset(distance as isize) };
+            let pte = self.walk_mut(va_base).unwrap();
+            if !pte.is_writable() {
+                return Err("copy_out: page is read-only");
+            }
+            pte.data |= PteFlag::DIRTY.bits();
 
             let n = min(PAGESIZE - distance, count);
             unsafe {
@@ -491,6 +803,21 @@ impl PageTableEntry {
         (self.data & PteFlag::VALID.bits()) > 0
     }
 
+    #[inline]
+    fn is_swapped(&self) -> bool {
+        !self.is_valid() && (self.data & PteFlag::SWAP.bits()) > 0
+    }
+
+    #[inline]
+    fn as_swap_slot(&self) -> usize {
+        self.data >> 10
+    }
+
+    #[inline]
+    fn set_swap_slot(&mut self, slot: usize) {
+        self.data = (slot << 10) | PteFlag::SWAP.bits();
+    }
+
     #[inline]
     fn is_leaf(&self) -> bool {
         (self.data & (PteFlag::READ | PteFlag::WRITE | PteFlag::EXEC).bits()) > 0
@@ -501,6 +828,21 @@ impl PageTableEntry {
         (self.data & PteFlag::USER.bits()) > 0
     }
 
+    #[inline]
+    fn is_writable(&self) -> bool {
+        (self.data & PteFlag::WRITE.bits()) > 0
+    }
+
+    #[inline]
+    fn is_dirty(&self) -> bool {
+        (self.data & PteFlag::DIRTY.bits()) > 0
+    }
+
+    #[inline]
+    fn is_cow(&self) -> bool {
+        (self.data & PteFlag::COW.bits()) > 0
+    }
+
     #[inline]
     fn get_flag(&self) -> PteFlag {
         PteFlag::from_bits_truncate(self.data)
@@ -593,6 +935,30 @@ mod tests {
         pgt.unmap_user_page_table(sz);
     }
 
+    #[test_case]
+    fn lazy_alloc_user_page_table() {
+        let trapframe =
+            unsafe { SinglePage::alloc_into_raw() }.expect("trapframe") as *mut TrapFrame;
+        let mut pgt = PageTable::alloc_user_page_table(trapframe as usize)
+            .expect("cannot alloc user page table");
+
+        // only the touched page in the middle gets memory.
+        let sz = 3 * PAGESIZE;
+        pgt.uvm_lazy_alloc(PAGESIZE + 8, 1, sz)
+            .expect("uvm_lazy_alloc");
+        assert!(pgt.walk_addr(0).is_err());
+        assert!(pgt.walk_addr(PAGESIZE).is_ok());
+        assert!(pgt.walk_addr(2 * PAGESIZE).is_err());
+        assert_eq!(1, pgt.user_pages());
+
+        // nothing beyond the size.
+        pgt.uvm_lazy_alloc(sz, 1, sz).expect("uvm_lazy_alloc");
+        assert!(pgt.walk_addr(sz).is_err());
+
+        pgt.unmap_user_page_table(sz);
+        unsafe { SinglePage::free_from_raw(trapframe as *mut SinglePage) };
+    }
+
     #[test_case]
     fn copy_out() {
         let pgt = Box::<PageTable>::try_new_zeroed();
@@ -666,7 +1032,7 @@ mod tests {
         let mut child = PageTable::alloc_user_page_table(child_tf as usize)
             .expect("cannot alloc user page table");
 
-        parent.uvm_copy(&mut child, CODE_SZ).expect("uvm_copy");
+        parent.uvm_copy(&mut child, 0, CODE_SZ).expect("uvm_copy");
 
         let mut child_code = [0u8; CODE_SZ];
         child
@@ -678,4 +1044,74 @@ mod tests {
         child.unmap_user_page_table(CODE_SZ);
         parent.unmap_user_page_table(CODE_SZ);
     }
+
+    #[test_case]
+    fn uvm_copy_shares_until_written() {
+        let parent_tf =
+            unsafe { SinglePage::alloc_into_raw() }.expect("trapframe") as *mut TrapFrame;
+        let mut parent = PageTable::alloc_user_page_table(parent_tf as usize)
+            .expect("cannot alloc user page table");
+        let code = [b'a', b'b', b'c', 0];
+        const CODE_SZ: usize = 4;
+        parent.uvm_init(&code).expect("uvm_init");
+
+        let child_tf =
+            unsafe { SinglePage::alloc_into_raw() }.expect("trapframe") as *mut TrapFrame;
+        let mut child = PageTable::alloc_user_page_table(child_tf as usize)
+            .expect("cannot alloc user page table");
+
+        parent.uvm_copy(&mut child, 0, CODE_SZ).expect("uvm_copy");
+        assert_eq!(parent.walk_addr(0), child.walk_addr(0));
+
+        // a store gives the child its own page, the parent's stays as it was.
+        child.copy_out(0, [b'x'].as_ptr(), 1).expect("copy_out");
+        assert_ne!(parent.walk_addr(0), child.walk_addr(0));
+        let mut parent_code = [0u8; CODE_SZ];
+        parent
+            .copy_in(parent_code.as_mut_ptr(), 0, CODE_SZ)
+            .expect("copy_in");
+        assert_eq!(&code, &parent_code);
+
+        // the parent is the only owner now, so its store keeps the page.
+        let pa = parent.walk_addr(0).expect("walk_addr");
+        assert_eq!(Ok(true), parent.resolve_cow(0));
+        assert_eq!(Ok(pa), parent.walk_addr(0));
+        assert_eq!(Ok(false), parent.resolve_cow(0));
+
+        child.unmap_user_page_table(CODE_SZ);
+        parent.unmap_user_page_table(CODE_SZ);
+        unsafe {
+            SinglePage::free_from_raw(child_tf as *mut SinglePage);
+            SinglePage::free_from_raw(parent_tf as *mut SinglePage);
+        }
+    }
+
+    #[test_case]
+    fn swap_out_keeps_the_slot_in_place_of_the_page() {
+        let tf = unsafe { SinglePage::alloc_into_raw() }.expect("trapframe") as *mut TrapFrame;
+        let mut pgt =
+            PageTable::alloc_user_page_table(tf as usize).expect("cannot alloc user page table");
+        let code = [b'a', b'b', b'c', 0];
+        pgt.uvm_init(&code).expect("uvm_init");
+        // no access from user space yet.
+        assert!(!pgt.take_accessed(0));
+
+        let pa = pgt.walk_addr(0).expect("walk_addr");
+        assert_eq!(None, pgt.swapped(0));
+        assert_eq!(pa, pgt.swap_out(0, 42));
+        assert_eq!(Some(42), pgt.swapped(0));
+        assert_eq!(Err(SWAPPED_OUT), pgt.walk_addr(0));
+        assert_eq!(None, pgt.lookup(0));
+        assert_eq!(None, pgt.next_user_page(0, PAGESIZE));
+        assert!(pgt.map_pages(0, pa, PAGESIZE, PteFlag::READ).is_err());
+
+        assert_eq!(Some(42), pgt.take_swapped(0));
+        assert_eq!(None, pgt.swapped(0));
+        let flag = PteFlag::READ | PteFlag::WRITE | PteFlag::USER;
+        pgt.map_pages(0, pa, PAGESIZE, flag).expect("map_pages");
+        assert_eq!(Some(0), pgt.next_user_page(0, PAGESIZE));
+
+        pgt.unmap_user_page_table(code.len());
+        unsafe { SinglePage::free_from_raw(tf as *mut SinglePage) };
+    }
 }
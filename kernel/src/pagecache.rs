@@ -0,0 +1,167 @@
+//! The page cache keeps the pages read from files for read-only private mappings, such as the
+//! text of the programs, so that the processes mapping the same part of a file share a physical
+//! page rather than each reading its own copy.
+//!
+//! A page is keyed by the inode, the offset in the file, and the number of bytes read from the
+//! file. the rest of the page is zeroed, like the end of a segment that is not in the file.
+//!
+//! The cache holds a reference to each page, and each mapping of it another one, so a page lives
+//! until it is both out of the cache and unmapped everywhere. Writing to or truncating the file
+//! drops its pages from the cache. the processes mapping them keep the old contents.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::cmp::min;
+
+use crate::{
+    fs::Inode,
+    kalloc::ENOMEM,
+    page_table::{is_shared_page, put_page, share_page, Page, SinglePage},
+    spinlock::SpinLock,
+};
+
+/// (dev, inum, offset, the bytes from the file)
+type Key = (u32, u32, usize, usize);
+
+static PAGES: SpinLock<BTreeMap<Key, usize>> = SpinLock::new(BTreeMap::new(), "pagecache");
+
+/// the page holding `len` bytes of `inode` from `offset`, read from the file unless it is cached
+/// already. returns its physical address, with a reference taken for the caller.
+pub fn read(inode: &Inode, offset: usize, len: usize) -> Result<usize, &'static str> {
+    let (dev, inum) = inode.id();
+    let key = (dev, inum, offset, len);
+
+    // the inode is locked until the page is cached, so the file is not written to meanwhile,
+    // which would leave the old contents in the cache.
+    let mut idata = inode.ilock();
+    let pages = PAGES.lock();
+    if let Some(&pa) = pages.get(&key) {
+        share_page(pa);
+        drop(pages);
+        drop(idata);
+        return Ok(pa);
+    }
+    drop(pages);
+
+    let pa = unsafe { SinglePage::alloc_into_raw() }.or(Err(ENOMEM))? as usize;
+    let len_in_file = min(len, idata.get_size().saturating_sub(offset));
+    if len_in_file > 0
+        && idata
+            .readi(false, pa as *mut u8, offset, len_in_file)
+            .is_err()
+    {
+        drop(idata);
+        put_page(pa);
+        return Err("pagecache: cannot read the file");
+    }
+
+    // one reference for the cache, and the allocation for the caller.
+    share_page(pa);
+    PAGES.lock().insert(key, pa);
+    drop(idata);
+    Ok(pa)
+}
+
+/// the number of pages in the cache.
+pub fn pages() -> usize {
+    PAGES.lock().len()
+}
+
+/// drops the pages that no process maps from the cache, to free memory. returns how many there
+/// were.
+pub fn shrink() -> usize {
+    // nothing is allocated on the way, as there may be no memory left.
+    let mut freed = 0;
+    PAGES.lock().retain(|_, &mut pa| {
+        if is_shared_page(pa) {
+            return true;
+        }
+        put_page(pa);
+        freed += 1;
+        false
+    });
+    freed
+}
+
+/// drops the pages of the inode (`dev`, `inum`) from the cache, since its contents have changed.
+pub fn invalidate(dev: u32, inum: u32) {
+    let mut pages = PAGES.lock();
+    let keys: Vec<Key> = pages
+        .range((dev, inum, 0, 0)..=(dev, inum, usize::MAX, usize::MAX))
+        .map(|(&key, _)| key)
+        .collect();
+    for key in keys {
+        let pa = pages.remove(&key).unwrap();
+        put_page(pa);
+    }
+    drop(pages);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        fs::{InodeType, INODE_TABLE},
+        log::LOG,
+        param::PAGESIZE,
+    };
+
+    use super::*;
+
+    #[test_case]
+    fn share_cached_pages() {
+        let inode = INODE_TABLE.namei(b"/init\0").expect("'/init' not found");
+        let pa1 = read(&inode, 0, PAGESIZE).expect("cannot read");
+        let pa2 = read(&inode, 0, PAGESIZE).expect("cannot read");
+        // one page, held by the cache and by both readers.
+        assert_eq!(pa1, pa2);
+        assert!(is_shared_page(pa1));
+
+        // fewer bytes of the file are another page, zeroed past them.
+        let pa3 = read(&inode, 0, 4).expect("cannot read");
+        assert_ne!(pa1, pa3);
+        let page = unsafe { &*(pa3 as *const [u8; PAGESIZE]) };
+        assert_eq!(&[0x7f, b'E', b'L', b'F'], &page[..4]);
+        assert!(page[4..].iter().all(|&b| b == 0));
+
+        // nobody maps them any more, so the cache lets go of them.
+        for &pa in [pa1, pa2, pa3].iter() {
+            put_page(pa);
+        }
+        let cached = pages();
+        assert!(shrink() >= 2);
+        assert!(pages() <= cached - 2);
+        drop(inode);
+    }
+
+    #[test_case]
+    fn invalidate_on_write() {
+        let path = b"pagecache\0";
+        LOG.begin_op();
+        let inode = INODE_TABLE.create(path, InodeType::File, 0, 0);
+        let res = inode.ilock().writei(false, b"old".as_ptr(), 0, 3);
+        LOG.end_op();
+        assert!(res.is_ok());
+
+        let old = read(&inode, 0, 3).expect("cannot read");
+        LOG.begin_op();
+        let res = inode.ilock().writei(false, b"new".as_ptr(), 0, 3);
+        LOG.end_op();
+        assert!(res.is_ok());
+        let new = read(&inode, 0, 3).expect("cannot read");
+
+        // the write dropped the old page from the cache, and its holder still sees the old bytes.
+        assert_ne!(old, new);
+        assert!(!is_shared_page(old));
+        assert_eq!(b"old", unsafe { &*(old as *const [u8; 3]) });
+        assert_eq!(b"new", unsafe { &*(new as *const [u8; 3]) });
+        put_page(old);
+        put_page(new);
+
+        // truncating the file on its last reference drops the new one.
+        let cached = pages();
+        LOG.begin_op();
+        assert!(INODE_TABLE.unlink(path).is_ok());
+        drop(inode);
+        LOG.end_op();
+        assert_eq!(cached - 1, pages());
+    }
+}
@@ -29,20 +29,34 @@ pub const TRAMPOLINE: usize = MAXVA - PAGESIZE; // 0x003f_ffff_f000
 
 // User memory layout.
 // # 0x0000_0000_0000
-//   text
+//   text, at a random base past PIE_BASE if position-independent
 //   original data and bss
-//   fixed-size stack
 //   expandable heap
 //   ...
-//   virtual memory area
+//   virtual memory area, from a random gap below the stack
+//   guard page
+//   stack, growing down to USTACK_SIZE, from a random gap below the trapframes
+//   trapframes of the threads
 //   TRAPFRAME (p->trapframe, used by the trampoline)
 //   TRAMPOLINE (the same page as in the kernel)
 // # 0x003f_ffff_e000
 pub const TRAPFRAME: usize = TRAMPOLINE - PAGESIZE;
+// the most the user stack grows to. its pages are allocated as it grows.
+pub const USTACK_SIZE: usize = PAGESIZE * 256;
+// the randomization of the user layout on each exec, in the number of pages the gaps may take.
+pub const PIE_BASE: usize = 0x1_0000; // the lowest a position-independent program is loaded at.
+pub const ASLR_LOAD_PAGES: usize = 1 << 16;
+pub const ASLR_STACK_PAGES: usize = 1 << 12;
+pub const ASLR_MMAP_PAGES: usize = 1 << 12;
 
 // virtio mmio interface
 pub const VIRTIO0: usize = 0x1000_1000;
 pub const VIRTIO0_IRQ: usize = 1;
+// the second virtio disk, which holds the swap area.
+pub const VIRTIO1: usize = 0x1000_2000;
+pub const VIRTIO1_IRQ: usize = 2;
+// the most pages the swap area holds, 2GB.
+pub const SWAPPAGES: usize = 1 << 19;
 
 // local interrupt controller, which contains the timer.
 pub const CLINT: usize = 0x2000000;
@@ -5,6 +5,7 @@ use crate::{cpu, param};
 pub unsafe fn init() {
     write(param::UART0_IRQ * 4, 1);
     write(param::VIRTIO0_IRQ * 4, 1);
+    write(param::VIRTIO1_IRQ * 4, 1);
 
     // TODO: ?
     // PCIE IRQs are 32 to 35
@@ -17,7 +18,7 @@ pub unsafe fn init_hart(hart: usize) {
     // set uart's enable bit for this hart's S-mode.
     write(
         SENABLE + SENABLE_HART * hart,
-        (1 << param::UART0_IRQ) | (1 << param::VIRTIO0_IRQ),
+        (1 << param::UART0_IRQ) | (1 << param::VIRTIO0_IRQ) | (1 << param::VIRTIO1_IRQ),
     );
     // hack to get at next 32 IRQs for e1000
     write(SENABLE + SENABLE_HART * hart + 4, 0xffffffff);
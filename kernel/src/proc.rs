@@ -1,6 +1,10 @@
-use core::{cell::UnsafeCell, mem, ptr};
+use core::{
+    cell::UnsafeCell,
+    cmp, mem, ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc};
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec};
 use array_macro::array;
 use bitflags::bitflags;
 
@@ -8,19 +12,28 @@ use crate::{
     cpu::{CpuTable, CPU_TABLE},
     file::File,
     fs::{self, Inode, INODE_TABLE},
-    page_table::{align_down, Page, PageTable, PteFlag, SinglePage},
-    param::{KSTACK_SIZE, MAXVA, NOFILE, PAGESIZE, ROOTDEV},
+    kalloc::{ENOMEM, ENOMEM_ERRNO},
+    log::LOG,
+    page_table::{
+        align_down, align_up, put_page, Page, PageTable, PteFlag, SinglePage, SWAPPED_OUT,
+    },
+    param::{KSTACK_SIZE, MAXVA, NOFILE, PAGESIZE, ROOTDEV, TRAMPOLINE, TRAPFRAME, USTACK_SIZE},
     println,
-    process::PROCESS_TABLE,
+    process::{NPROC, PROCESS_TABLE},
     register::satp,
+    sched::{self, SchedInfo, ALL_CPUS},
     spinlock::{SpinLock, SpinLockGuard},
+    start, swap,
     trap::{user_trap_ret, usertrap},
 };
 
 mod elf;
+pub mod futex;
+pub mod rusage;
+pub mod signal;
 mod syscall;
 
-use self::syscall::Syscall;
+use self::{rusage::Rusage, signal::SigState, syscall::Syscall};
 
 const MAXARG: usize = 16;
 const MAXARGLEN: usize = 64;
@@ -129,16 +142,40 @@ pub struct ProcInner {
     pub pid: usize,
     pub killed: bool,
     pub exit_status: i32,
+    // created by clone(), reaped by join() instead of wait().
+    pub thread: bool,
+    // the tick at which a timed sleep gives up, or 0.
+    pub timeout: usize,
+    pub sig: SigState,
+    pub sched: SchedInfo,
+    pub usage: Rusage,
+    // the usage of the children that have been reaped.
+    pub child_usage: Rusage,
+    // the cpus the process may run on.
+    pub affinity: usize,
+    // the cpu the process last ran on.
+    pub last_cpu: usize,
+    // the session, the pid of its leader.
+    pub sid: usize,
 }
 
 impl ProcInner {
-    const fn new() -> Self {
+    pub const fn new() -> Self {
         Self {
             state: ProcState::Unused,
             chan: 0,
             pid: 0,
             killed: false,
             exit_status: 0,
+            thread: false,
+            timeout: 0,
+            sig: SigState::new(),
+            sched: SchedInfo::new(),
+            usage: Rusage::new(),
+            child_usage: Rusage::new(),
+            affinity: ALL_CPUS,
+            last_cpu: 0,
+            sid: 0,
         }
     }
 }
@@ -155,34 +192,485 @@ pub enum ProcState {
 
 pub struct ProcData {
     kstack: usize,
-    sz: usize,
-    pub page_table: Option<Box<PageTable>>,
     trapframe: *mut TrapFrame,
+    // where the trapframe is mapped in user space, for the trampoline.
+    trapframe_va: usize,
     context: Context,
-    pub cwd: Option<Inode>,
-    pub o_files: [Option<Arc<File>>; NOFILE],
-    vm_area: [Option<VMA>; 100],
-    cur_max: usize,
+    pub mm: Option<Arc<SpinLock<Mm>>>,
+    pub files: Option<Arc<SpinLock<Files>>>,
     // Environment variables storage
     pub env_vars: Option<BTreeMap<String, String>>,
 }
 
+/// The user address space. The threads created by clone() share it.
+pub struct Mm {
+    pub page_table: Box<PageTable>,
+    pub sz: usize,
+    // the heap lies in [heap_start, sz). the segments of the program exec-ed are mmap-ed below it.
+    pub heap_start: usize,
+    // the regions mmap-ed without an address go down from here, randomized on exec.
+    mmap_top: usize,
+    // the mmap-ed regions, keyed by their start address.
+    vmas: BTreeMap<usize, VMA>,
+    // the threads that have not exited yet. the last of them writes the mmap-ed files back.
+    users: usize,
+}
+
+/// the pages right below the trampoline hold the trapframes, one for each process that may share
+/// the address space, so the VMAs start beneath them.
+const MMAP_TOP: usize = TRAPFRAME - NPROC * PAGESIZE;
+
+impl Mm {
+    pub fn new(page_table: Box<PageTable>) -> Self {
+        Self {
+            page_table,
+            sz: 0,
+            heap_start: 0,
+            mmap_top: MMAP_TOP,
+            vmas: BTreeMap::new(),
+            users: 1,
+        }
+    }
+
+    /// the start of the lowest mmap-ed region between the heap and `mmap_top`, or of the guard
+    /// page below it, which the heap must not grow past. new regions without an address go beneath
+    /// it, so the VMAs are allocated from top to bottom.
+    pub fn mmap_base(&self) -> usize {
+        self.vmas
+            .range(self.heap_start..self.mmap_top)
+            .next()
+            .map(|(_, vm)| vm)
+            .map_or(self.mmap_top, |vm| vm.guarded_start())
+    }
+
+    /// reserves the user stack of USTACK_SIZE bytes beneath `top`, which is at most MMAP_TOP,
+    /// and returns `top`. only the top page is allocated, for the arguments. the others are
+    /// faulted in as the stack grows down, until it hits the guard page.
+    fn map_stack(&mut self, top: usize) -> Result<usize, &'static str> {
+        self.insert_vma(VMA {
+            addr_start: top - USTACK_SIZE,
+            addr_end: top,
+            prot: PteFlag::READ | PteFlag::WRITE,
+            flags: MapFlag::PRIVATE | MapFlag::ANONYMOUS | MapFlag::GROWSDOWN,
+            file: None,
+            offset: 0,
+            file_end: top,
+        });
+
+        let pa = unsafe { SinglePage::alloc_into_raw() }.or(Err(ENOMEM))? as usize;
+        let flag = PteFlag::READ | PteFlag::WRITE | PteFlag::USER;
+        if let Err(msg) = self
+            .page_table
+            .map_pages(top - PAGESIZE, pa, PAGESIZE, flag)
+        {
+            unsafe { SinglePage::free_from_raw(pa as *mut SinglePage) };
+            return Err(msg);
+        }
+        Ok(top)
+    }
+
+    /// maps the page at `pa` at `va` with the permissions of the VMA that `va` lives in, and the
+    /// accessed and dirty bits in `flag`. the page is mapped out of reach of user space first,
+    /// then given the permissions by `uvm_protect()`, which keeps a page of a private region
+    /// copy-on-write while it is shared, as with the page cache.
+    fn map_vma_page(&mut self, va: usize, pa: usize, flag: PteFlag) -> Result<(), &'static str> {
+        let (prot, cow) = match self.find_vma(va) {
+            Some(vm) => (vm.prot, !vm.flags.contains(MapFlag::SHARED)),
+            None => return Err("map: the addr is not lived in VMA"),
+        };
+        self.page_table
+            .map_pages(va, pa, PAGESIZE, PteFlag::READ | flag)?;
+        self.page_table.uvm_protect(va, 1, prot, cow);
+        Ok(())
+    }
+
+    /// tells whether `va`, which is not in any VMA, is in the guard page below a stack.
+    fn is_stack_guard(&self, va: usize) -> bool {
+        match self.vmas.range(va..).next() {
+            Some((_, vm)) => vm.flags.contains(MapFlag::GROWSDOWN) && vm.guarded_start() <= va,
+            None => false,
+        }
+    }
+
+    /// tells whether [start, start + len) can be mmap-ed: it must be page aligned, above the heap,
+    /// beneath the trapframes, and not overlap any VMA.
+    fn is_free(&self, start: usize, len: usize) -> bool {
+        let end = match start.checked_add(len) {
+            Some(end) => end,
+            None => return false,
+        };
+        // the regions that start up to a page above the end may keep a guard page in the range.
+        start % PAGESIZE == 0
+            && start >= align_up(self.sz, PAGESIZE)
+            && end <= MMAP_TOP
+            && !self
+                .vmas
+                .range(..end.saturating_add(PAGESIZE))
+                .rev()
+                .take_while(|(_, vm)| start < vm.addr_end)
+                .any(|(_, vm)| vm.guarded_start() < end)
+    }
+
+    /// tells whether the page at `va` may be swapped out: it is private to this address space,
+    /// part of the heap or of a private mmap-ed region, and no other thread uses it.
+    pub fn swappable(&self, va: usize) -> bool {
+        if self.users != 1 {
+            return false;
+        }
+        if self.heap_start <= va && va < self.sz {
+            return true;
+        }
+        matches!(self.find_vma(va), Some(vm) if vm.flags.contains(MapFlag::PRIVATE))
+    }
+
+    /// the VMA that `va` lives in.
+    fn find_vma(&self, va: usize) -> Option<&VMA> {
+        self.vmas
+            .range(..=va)
+            .next_back()
+            .map(|(_, vm)| vm)
+            .filter(|vm| va < vm.addr_end)
+    }
+
+    /// adds a region, which must not overlap any other, and merges it with its neighbours.
+    fn insert_vma(&mut self, vm: VMA) {
+        let (start, end) = (vm.addr_start, vm.addr_end);
+        self.vmas.insert(start, vm);
+        self.merge_vmas(end);
+        self.merge_vmas(start);
+    }
+
+    /// splits the VMA that `at` lives in strictly inside into [addr_start, at) and
+    /// [at, addr_end).
+    fn split_vma(&mut self, at: usize) {
+        let vm = match self.vmas.range_mut(..at).next_back() {
+            Some((_, vm)) if at < vm.addr_end => vm,
+            _ => return,
+        };
+        let upper = VMA {
+            addr_start: at,
+            addr_end: vm.addr_end,
+            prot: vm.prot,
+            flags: vm.flags,
+            file: vm.file.clone(),
+            offset: vm.offset + (at - vm.addr_start),
+            file_end: vm.file_end,
+        };
+        vm.addr_end = at;
+        self.vmas.insert(at, upper);
+    }
+
+    /// merges the VMA that ends at `at` with the one that starts there, if they map the same
+    /// thing with the same protection, contiguously. the lower one must be backed by the file up
+    /// to its end.
+    fn merge_vmas(&mut self, at: usize) {
+        let upper = match self.vmas.get(&at) {
+            Some(upper) => upper,
+            None => return,
+        };
+        let lower = match self.vmas.range(..at).next_back() {
+            Some((_, lower)) if lower.addr_end == at => lower,
+            _ => return,
+        };
+        let same_file = match (&lower.file, &upper.file) {
+            (Some(lf), Some(uf)) => {
+                Arc::ptr_eq(lf, uf)
+                    && lower.offset + (at - lower.addr_start) == upper.offset
+                    && lower.file_end >= at
+            }
+            (None, None) => true,
+            _ => false,
+        };
+        if !same_file || lower.prot != upper.prot || lower.flags != upper.flags {
+            return;
+        }
+        let lower_start = lower.addr_start;
+
+        // the lower one holds the file too, so dropping the upper one never closes it.
+        let upper = self.vmas.remove(&at).unwrap();
+        let lower = self.vmas.get_mut(&lower_start).unwrap();
+        lower.addr_end = upper.addr_end;
+        lower.file_end = upper.file_end;
+    }
+
+    /// takes [start, end) out of the mmap-ed regions, splitting the VMAs that straddle its ends.
+    /// the pages are unmapped, and out of the TLBs of the other threads, but not freed: they are
+    /// returned to be written back first. so are
+    /// the VMAs removed, since dropping the last reference to a file may sleep.
+    fn take_vmas(
+        &mut self,
+        start: usize,
+        end: usize,
+    ) -> Result<(Vec<MmapPage>, Vec<VMA>), &'static str> {
+        self.split_vma(start);
+        self.split_vma(end);
+        let starts: Vec<usize> = self.vmas.range(start..end).map(|(&s, _)| s).collect();
+
+        let mut pages = Vec::new();
+        let mut removed = Vec::new();
+        for s in starts {
+            let vm = self.vmas.remove(&s).unwrap();
+            for va in (vm.addr_start..vm.addr_end).step_by(PAGESIZE) {
+                let pa = match self.page_table.lookup(va) {
+                    Some((pa, _)) => pa,
+                    None => {
+                        if let Some(slot) = self.page_table.take_swapped(va) {
+                            swap::put(slot);
+                        }
+                        continue;
+                    }
+                };
+                let write_back = match &vm.file {
+                    Some(f)
+                        if vm.flags.contains(MapFlag::SHARED) && self.page_table.take_dirty(va) =>
+                    {
+                        Some((f.clone(), vm.offset + (va - vm.addr_start)))
+                    }
+                    _ => None,
+                };
+                self.page_table.unmap_pages(va, 1, false)?;
+                pages.push(MmapPage { pa, write_back });
+            }
+            removed.push(vm);
+        }
+
+        self.page_table.flush_tlb();
+        Ok((pages, removed))
+    }
+
+    /// changes the protection of [start, end), which must be covered by mmap-ed regions, to
+    /// `prot`. the regions partly covered are split, and the pages already mapped are updated.
+    fn protect_vmas(
+        &mut self,
+        start: usize,
+        end: usize,
+        prot: PteFlag,
+    ) -> Result<(), &'static str> {
+        let mut va = start;
+        while va < end {
+            let vm = self
+                .find_vma(va)
+                .ok_or("mprotect: the range is not mmap-ed")?;
+            if let Some(f) = &vm.file {
+                if vm.flags.contains(MapFlag::SHARED)
+                    && prot.contains(PteFlag::WRITE)
+                    && !f.writable
+                {
+                    return Err("mprotect: file is read-only, but the mapping is shared");
+                }
+            }
+            va = vm.addr_end;
+        }
+
+        self.split_vma(start);
+        self.split_vma(end);
+        for vm in self.vmas.range_mut(start..end).map(|(_, vm)| vm) {
+            vm.prot = prot;
+            self.page_table.uvm_protect(
+                vm.addr_start,
+                (vm.addr_end - vm.addr_start) / PAGESIZE,
+                prot,
+                !vm.flags.contains(MapFlag::SHARED),
+            );
+        }
+        self.page_table.flush_tlb();
+
+        // the regions in the range may now be merged, with each other and with the neighbours.
+        let starts: Vec<usize> = self.vmas.range(start..=end).map(|(&s, _)| s).collect();
+        for s in starts.into_iter().rev() {
+            self.merge_vmas(s);
+        }
+        Ok(())
+    }
+
+    /// gives a forked child's address space the mmap-ed regions. the pages of private regions are
+    /// shared copy-on-write, and those of MAP_SHARED regions are shared as they are.
+    fn dup_vmas(&mut self, child: &mut Mm) -> Result<(), &'static str> {
+        for vm in self.vmas.values() {
+            // inserted first, so the child frees the pages shared so far if this fails.
+            child.vmas.insert(vm.addr_start, vm.clone());
+            self.page_table
+                .uvm_share(
+                    &mut child.page_table,
+                    vm.addr_start,
+                    (vm.addr_end - vm.addr_start) / PAGESIZE,
+                    !vm.flags.contains(MapFlag::SHARED),
+                )
+                .or(Err("fork: cannot share the mmap-ed regions"))?;
+        }
+        Ok(())
+    }
+}
+
+/// a page taken out of an mmap-ed region. a dirty page of a shared file mapping carries the file
+/// and the offset to write it back to.
+struct MmapPage {
+    pa: usize,
+    write_back: Option<(Arc<File>, usize)>,
+}
+
+impl MmapPage {
+    /// writes the page back if needed, and frees it.
+    /// this may sleep, so the address space must not be locked.
+    fn release(self) -> Result<(), &'static str> {
+        let res = match self.write_back {
+            Some((f, offset)) => f.write_at(self.pa as *const u8, offset, PAGESIZE),
+            None => Ok(0),
+        };
+        // a forked child, or the page cache, may still share it.
+        put_page(self.pa);
+        res.map(|_| ())
+    }
+}
+
+/// unmaps [addr, addr + len) from the mmap-ed regions of `mm`. the dirty pages of shared file
+/// mappings are written back to the files.
+fn unmap_vmas(mm: &SpinLock<Mm>, addr: usize, len: usize) -> Result<(), &'static str> {
+    if addr % PAGESIZE != 0 {
+        return Err("munmap: addr not aligned");
+    }
+    let end = match addr.checked_add(len) {
+        Some(end) if len > 0 && end <= MAXVA => align_up(end, PAGESIZE),
+        _ => return Err("munmap: invalid length"),
+    };
+
+    let (pages, removed) = mm.lock().take_vmas(addr, end)?;
+
+    // release every page even if one fails to be written back.
+    let mut res = Ok(());
+    for page in pages {
+        res = res.and(page.release());
+    }
+    drop(removed);
+    res
+}
+
+/// leaves the address space `mm`. the last thread to leave frees the heap, so that a process
+/// killed for memory gives it back before it is reaped, and unmaps the mmap-ed regions, which
+/// writes them back to the files. it must not be called with any lock held.
+fn leave_mm(mm: &SpinLock<Mm>) {
+    let mut guard = mm.lock();
+    guard.users -= 1;
+    let last = guard.users == 0;
+    if last && guard.sz > guard.heap_start {
+        let (heap_start, n) = (
+            guard.heap_start,
+            (align_up(guard.sz, PAGESIZE) - guard.heap_start) / PAGESIZE,
+        );
+        guard
+            .page_table
+            .uvm_unmap(heap_start, n)
+            .expect("cannot unmap heap");
+        guard.sz = heap_start;
+    }
+    drop(guard);
+
+    if last {
+        if let Err(msg) = unmap_vmas(mm, 0, MMAP_TOP) {
+            println!("leave_mm: {}", msg);
+        }
+    }
+}
+
+impl Drop for Mm {
+    /// the last thread has gone. the trapframes are already unmapped by their owners.
+    fn drop(&mut self) {
+        let pgt = &mut self.page_table;
+        pgt.unmap_pages(TRAMPOLINE, 1, false)
+            .expect("cannot unmap trampoline");
+        if self.sz > self.heap_start {
+            let n = (align_up(self.sz, PAGESIZE) - self.heap_start) / PAGESIZE;
+            pgt.uvm_unmap(self.heap_start, n)
+                .expect("cannot unmap process");
+        }
+
+        // unmap all mmap-ed region.
+        for vm in self.vmas.values() {
+            for va in (vm.addr_start..vm.addr_end).step_by(PAGESIZE) {
+                if pgt.lookup(va).is_some() {
+                    pgt.unmap_pages(va, 1, true)
+                        .expect("cannot unmap in freeing");
+                } else if let Some(slot) = pgt.take_swapped(va) {
+                    swap::put(slot);
+                }
+            }
+        }
+    }
+}
+
+/// The open files and the current directory. The threads created by clone() share them.
+pub struct Files {
+    pub cwd: Option<Inode>,
+    pub o_files: [Option<Arc<File>>; NOFILE],
+}
+
+impl Files {
+    fn new(cwd: Inode) -> Self {
+        Self {
+            cwd: Some(cwd),
+            o_files: array![_ => None; NOFILE],
+        }
+    }
+
+    /// a copy for a forked child. the open files are shared with it, the table is not.
+    fn dup(&self) -> Self {
+        Self {
+            cwd: Some(INODE_TABLE.idup(self.cwd.as_ref().unwrap())),
+            o_files: array![i => self.o_files[i].clone(); NOFILE],
+        }
+    }
+}
+
+impl Drop for Files {
+    /// closes the open files when the last thread that shares them has gone.
+    fn drop(&mut self) {
+        for f in self.o_files.iter_mut() {
+            drop(f.take());
+        }
+
+        LOG.begin_op();
+        drop(self.cwd.take());
+        LOG.end_op();
+    }
+}
+
 /// Each VMA has a range of virtual addresses that shares the same permissions and is backed by the
 /// same resource (e.g. a file or anonymous memory).
+#[derive(Clone)]
 struct VMA {
     addr_start: usize,
     addr_end: usize,
-    size: usize,
     prot: PteFlag,
     flags: MapFlag,
-    fd: i32,
+    // the mapped file, or None for anonymous memory.
+    file: Option<Arc<File>>,
+    // the offset in the file that `addr_start` maps.
+    offset: usize,
+    // where the bytes from the file end. the pages past it are zero-filled, like the bss of a
+    // program.
+    file_end: usize,
+}
+
+impl VMA {
+    /// where the region starts, including the guard page below it if it is a stack.
+    fn guarded_start(&self) -> usize {
+        if self.flags.contains(MapFlag::GROWSDOWN) {
+            self.addr_start - PAGESIZE
+        } else {
+            self.addr_start
+        }
+    }
 }
 
 bitflags! {
     pub struct MapFlag: usize {
         const SHARED = 1 << 0;
         const PRIVATE = 1 << 1;
-        const ANONYMOUNS = 1 << 2;
+        const ANONYMOUS = 1 << 2;
+        // place the mapping exactly at the address, replacing what is mapped there.
+        const FIXED = 1 << 4;
+        // a stack, which keeps a guard page below it that nothing is mapped at.
+        const GROWSDOWN = 1 << 8;
     }
 }
 
@@ -190,20 +678,11 @@ impl ProcData {
     const fn new() -> Self {
         Self {
             kstack: 0,
-            sz: 0,
-            page_table: None,
             trapframe: ptr::null_mut(),
+            trapframe_va: TRAPFRAME,
             context: Context::new(),
-            cwd: None,
-            o_files: array![_ => None; NOFILE],
-            vm_area: array![_ => None; 100],
-            // the VMA list is allocated from top to bottom.
-            // the initial max virtual address of the VMA is 2 pages below the MAXVA.
-            // the reason behind is first 2 pages are trampoline and trapframe.
-            //
-            // the cur_max is adjusted after we create a new VMA. so next allocation's end address
-            // can be set to cur_max.
-            cur_max: MAXVA - 2 * PAGESIZE,
+            mm: None,
+            files: None,
             // Initialize environment variables map as None
             env_vars: None,
         }
@@ -213,15 +692,46 @@ impl ProcData {
         self.kstack = v;
     }
 
-    pub fn init_page_table(&mut self) -> Result<(), ()> {
-        self.trapframe =
-            unsafe { SinglePage::alloc_into_raw() }.or_else(|_| Err(()))? as *mut TrapFrame;
+    pub fn init_trapframe(&mut self) -> Result<(), &'static str> {
+        self.trapframe = unsafe { SinglePage::alloc_into_raw() }.or(Err(ENOMEM))? as *mut TrapFrame;
+        self.trapframe_va = TRAPFRAME;
+        Ok(())
+    }
 
-        let pgt = PageTable::alloc_user_page_table(self.trapframe as usize).ok_or_else(|| ())?;
-        self.page_table = Some(pgt);
+    /// gives the process an empty address space of its own, with the trampoline and the
+    /// trapframe mapped.
+    pub fn init_mm(&mut self) -> Result<(), &'static str> {
+        // the page table has the trapframe mapped, so it is allocated last to be kept.
+        let mut mm = Arc::try_new_uninit().or(Err(ENOMEM))?;
+        let pgt = PageTable::alloc_user_page_table(self.trapframe as usize)?;
+        Arc::get_mut(&mut mm)
+            .unwrap()
+            .write(SpinLock::new(Mm::new(pgt), "mm"));
+        self.mm = Some(unsafe { mm.assume_init() });
         Ok(())
     }
 
+    /// locks the address space.
+    #[inline]
+    pub fn mm(&self) -> SpinLockGuard<'_, Mm> {
+        self.mm.as_ref().unwrap().lock()
+    }
+
+    /// locks the open files. never sleep while holding the lock, take the file out first.
+    #[inline]
+    pub fn files(&self) -> SpinLockGuard<'_, Files> {
+        self.files.as_ref().unwrap().lock()
+    }
+
+    /// the open file of the descriptor `fd`.
+    #[inline]
+    pub fn file(&self, fd: usize) -> Option<Arc<File>> {
+        let files = self.files();
+        let f = files.o_files[fd].clone();
+        drop(files);
+        f
+    }
+
     pub fn init_context(&mut self) {
         self.context.clear();
         self.context.ra = forkret as usize;
@@ -235,21 +745,24 @@ impl ProcData {
 
     /// initialize the user first process
     pub fn user_init(&mut self) -> Result<(), &'static str> {
+        self.init_mm()?;
+
         // allocate one user page and copy init's instructions
         // and data into it.
-        self.page_table.as_mut().unwrap().uvm_init(&INITCODE)?;
-        self.sz = PAGESIZE;
+        let mut mm = self.mm();
+        mm.page_table.uvm_init(&INITCODE)?;
+        mm.sz = PAGESIZE;
+        drop(mm);
 
         // prepare for the very first "return" from kernel to user.
         let trapframe = unsafe { self.trapframe.as_mut().unwrap() };
         trapframe.epc = 0; // user program counter
         trapframe.sp = PAGESIZE; // user stack poiner
 
-        self.cwd = Some(
-            INODE_TABLE
-                .namei(&[b'/', 0])
-                .expect("cannot find root inode by b'/'"),
-        );
+        let cwd = INODE_TABLE
+            .namei(&[b'/', 0])
+            .expect("cannot find root inode by b'/'");
+        self.files = Some(Arc::new(SpinLock::new(Files::new(cwd), "files")));
         Ok(())
     }
 
@@ -267,6 +780,11 @@ impl ProcData {
         self.trapframe.as_mut().unwrap().epc = epc;
     }
 
+    #[inline]
+    pub fn get_trapframe_va(&self) -> usize {
+        self.trapframe_va
+    }
+
     pub unsafe fn setup_user_ret(&self) -> usize {
         let trapframe = self.trapframe.as_mut().unwrap();
         trapframe.kernel_satp = satp::read();
@@ -274,94 +792,274 @@ impl ProcData {
         trapframe.kernel_trap = usertrap as usize;
         trapframe.kernel_hartid = CpuTable::cpu_id();
 
-        self.page_table.as_ref().unwrap().as_satp()
+        self.mm().page_table.as_satp()
     }
 
+    /// copies from user space. fails with EFAULT if the process cannot read [srcva, srcva +
+    /// count), and with ENOMEM if memory runs out faulting it in.
     #[inline]
     pub fn copy_in(&self, dst: *mut u8, srcva: usize, count: usize) -> Result<(), &'static str> {
-        self.page_table.as_ref().unwrap().copy_in(dst, srcva, count)
+        loop {
+            self.fault_in(srcva, count, PteFlag::READ)
+                .map_err(bad_address)?;
+            // a page may have been swapped out again in between.
+            match self.mm().page_table.copy_in(dst, srcva, count) {
+                Err(msg) if msg == SWAPPED_OUT => continue,
+                res => return res.map_err(bad_address),
+            }
+        }
     }
 
+    /// copies to user space. fails with EFAULT if the process cannot write [dstva, dstva +
+    /// count), and with ENOMEM if memory runs out faulting it in.
     #[inline]
     pub fn copy_out(&self, dstva: usize, src: *const u8, count: usize) -> Result<(), &'static str> {
-        self.page_table
-            .as_ref()
-            .unwrap()
-            .copy_out(dstva, src, count)
+        loop {
+            self.fault_in(dstva, count, PteFlag::WRITE)
+                .map_err(bad_address)?;
+            match self.mm().page_table.copy_out(dstva, src, count) {
+                Err(msg) if msg == SWAPPED_OUT => continue,
+                res => return res.map_err(bad_address),
+            }
+        }
     }
 
-    pub fn unmmap(&mut self, addr: usize, size: usize) -> Result<(), &'static str> {
-        // find which VMA owns the VA.
-        let mut vm = self
-            .vm_area
-            .iter_mut()
-            .find(|vm| {
-                if vm.is_none() {
-                    return false;
+    /// makes the user pages in [va, va + len) present for the kernel to `access` them, faulting
+    /// in those not populated yet as an access from user space would. when memory runs out, pages
+    /// are swapped out to make room, and the syscall fails with ENOMEM only if none can be.
+    pub fn fault_in(&self, va: usize, len: usize, access: PteFlag) -> Result<(), &'static str> {
+        let end = va.checked_add(len).ok_or("fault_in: invalid length")?;
+        for page in (align_down(va, PAGESIZE)..end).step_by(PAGESIZE) {
+            loop {
+                match self.handle_page_fault(page, access) {
+                    Err(msg) if msg == ENOMEM && swap::reclaim(swap::BATCH) > 0 => continue,
+                    res => break res?,
                 }
-                let vm = vm.as_ref().unwrap();
-                return vm.addr_start <= addr && addr <= vm.addr_end;
-            })
-            .ok_or("lazy_mmap: the addr is not lived in VMA")?
-            .as_mut();
-
-        let addr_head = align_down(addr, PAGESIZE);
-        let pgt = self.page_table.as_mut().unwrap();
-        for va in (addr_head..=align_down(addr + size, PAGESIZE)).step_by(PAGESIZE) {
-            if let Ok(_) = pgt.walk_addr(va) {
-                pgt.unmap_pages(va, 1, true)?;
             }
         }
+        Ok(())
+    }
 
-        self.cur_max = vm.as_ref().unwrap().addr_end;
-        vm.take();
+    /// resolves a page fault at `va` from user space, caused by an `access` of READ, WRITE or
+    /// EXEC: copies a copy-on-write page on a store, or allocates the page if it is part of the
+    /// heap or of a mmap-ed region, such as the stack. an access that the page does not permit is
+    /// an error.
+    pub fn handle_page_fault(&self, va: usize, access: PteFlag) -> Result<(), &'static str> {
+        let mut mm = self.mm();
+        if access == PteFlag::WRITE && mm.page_table.resolve_cow(va)? {
+            return Ok(());
+        }
+        if let Some((_, flag)) = mm.page_table.lookup(va) {
+            if flag.contains(PteFlag::USER | access) {
+                // another thread sharing the address space has faulted on the page first.
+                return Ok(());
+            }
+            return Err("page fault: protection violation");
+        }
+        if let Some(slot) = mm.page_table.swapped(va) {
+            drop(mm);
+            return self.swap_in(align_down(va, PAGESIZE), slot);
+        }
+        let sz = mm.sz;
+        if mm.heap_start <= va && va < sz {
+            return mm.page_table.uvm_lazy_alloc(va, 1, sz);
+        }
+        let vm = match mm.find_vma(va) {
+            Some(vm) => vm,
+            None if mm.is_stack_guard(va) => return Err("stack overflow"),
+            None => return Err("page fault: the addr is not lived in VMA"),
+        };
+        if !vm.prot.contains(access) {
+            return Err("page fault: protection violation");
+        }
+        drop(mm);
+        self.lazy_mmap(va)
+    }
 
+    /// reads the page at `va` back in from `slot` of the swap area, and maps it again with the
+    /// permissions of the heap or of its VMA. the PTE keeps the slot until then, so that a thread
+    /// that faults on it meanwhile reads it in as well, and the loser frees its copy.
+    fn swap_in(&self, va: usize, slot: usize) -> Result<(), &'static str> {
+        let pa = unsafe { SinglePage::alloc_into_raw() }.or(Err(ENOMEM))? as usize;
+        swap::read(slot, pa);
+
+        let mut mm = self.mm();
+        if mm.page_table.swapped(va) != Some(slot) {
+            drop(mm);
+            put_page(pa);
+            return Ok(());
+        }
+        mm.page_table.take_swapped(va);
+        // the page is dirty, as it is only in memory now.
+        let flag = PteFlag::READ | PteFlag::ACCES | PteFlag::DIRTY;
+        if mm.heap_start <= va && va < mm.sz {
+            let flag = flag | PteFlag::WRITE | PteFlag::EXEC | PteFlag::USER;
+            mm.page_table
+                .map_pages(va, pa, PAGESIZE, flag)
+                .expect("swap_in: cannot map");
+        } else {
+            mm.map_vma_page(va, pa, flag).expect("swap_in: cannot map");
+        }
+        drop(mm);
+        swap::put(slot);
         Ok(())
     }
 
-    /// The reason to be lazy is to ensure that mmap-ing a large file is fast, and tha mmap-ing a
-    /// file larger than physical memory is possible.
-    pub fn lazy_mmap(&mut self, fault_addr: usize) -> Result<(), &'static str> {
-        // find which VMA owns the VA.
-        let vm = self
-            .vm_area
-            .iter()
-            .find(|vm| {
-                if vm.is_none() {
-                    return false;
+    /// maps `len` bytes of `file` from `offset`, or of anonymous memory if `file` is None, and
+    /// returns the address. `addr` is a hint followed if the range is free, unless `flags` has
+    /// FIXED, in which case the mapping is placed exactly there and replaces the regions mmap-ed
+    /// before.
+    pub fn mmap(
+        &self,
+        addr: usize,
+        len: usize,
+        prot: PteFlag,
+        flags: MapFlag,
+        file: Option<Arc<File>>,
+        offset: usize,
+    ) -> Result<usize, &'static str> {
+        let len = match len.checked_add(PAGESIZE - 1) {
+            Some(_) if len > 0 => align_up(len, PAGESIZE),
+            _ => return Err("mmap: invalid length"),
+        };
+        let fixed = flags.contains(MapFlag::FIXED);
+        if fixed {
+            if addr % PAGESIZE != 0 {
+                return Err("mmap: addr not aligned");
+            }
+            match addr.checked_add(len) {
+                Some(end) if end <= MMAP_TOP => {}
+                _ => return Err("mmap: the range is out of the mmap-able space"),
+            }
+            if addr < align_up(self.mm().sz, PAGESIZE) {
+                return Err("mmap: overlaps the heap");
+            }
+            unmap_vmas(self.mm.as_ref().unwrap(), addr, len)?;
+        }
+
+        let mut mm = self.mm();
+        let addr_start = if mm.is_free(addr, len) {
+            addr
+        } else if fixed {
+            // another thread has mapped the range again, or grown the heap into it.
+            drop(mm);
+            return Err("mmap: the range is in use");
+        } else {
+            // beneath the lowest region, which leaves room for the heap to grow.
+            match mm.mmap_base().checked_sub(len) {
+                Some(start) if mm.is_free(start, len) => start,
+                _ => {
+                    drop(mm);
+                    return Err("mmap: out of address space");
                 }
-                let vm = vm.as_ref().unwrap();
-                return vm.addr_start <= fault_addr && fault_addr <= vm.addr_end;
-            })
-            .ok_or("lazy_mmap: the addr is not lived in VMA")?
-            .as_ref()
-            .unwrap();
+            }
+        };
 
-        let fault_addr_head = align_down(fault_addr, PAGESIZE);
+        mm.insert_vma(VMA {
+            addr_start,
+            addr_end: addr_start + len,
+            prot,
+            flags,
+            file,
+            offset,
+            file_end: addr_start + len,
+        });
+        Ok(addr_start)
+    }
 
-        // map the page into the user address space, by installing to user page table.
-        let pgt = self.page_table.as_mut().unwrap();
-        // TODO: the physical page can be shared with mappings in other processes.
-        // we will need reference counts on physical pages.
-        // Right now, can only allocate a new physical page for each process.
-        let pa = unsafe {
-            SinglePage::alloc_into_raw()
-                .expect("lazy_mmap: unable to allocate a page of physical memory")
-        } as usize;
-        pgt.map_pages(
-            fault_addr_head,
-            pa,
-            PAGESIZE,
-            //  vm.prot | PteFlag::USER,
-            PteFlag::READ | PteFlag::WRITE | PteFlag::EXEC | PteFlag::USER,
-        )?;
+    /// unmaps [addr, addr + len) from the mmap-ed regions, splitting a region if it is unmapped
+    /// in the middle. the dirty pages of shared file mappings are written back to the files.
+    pub fn munmap(&self, addr: usize, len: usize) -> Result<(), &'static str> {
+        unmap_vmas(self.mm.as_ref().unwrap(), addr, len)
+    }
 
-        if vm.fd < 0 && (MapFlag::ANONYMOUNS.bits() & vm.flags.bits()) > 0 {
-            // anonymous mapping
+    /// changes the protection of the mmap-ed pages in [addr, addr + len) to `prot`, a subset of
+    /// READ, WRITE and EXEC. an access that is not permitted afterwards faults.
+    pub fn mprotect(&self, addr: usize, len: usize, prot: PteFlag) -> Result<(), &'static str> {
+        if addr % PAGESIZE != 0 {
+            return Err("mprotect: addr not aligned");
+        }
+        let end = match addr.checked_add(len) {
+            Some(end) if len > 0 && end <= MAXVA => align_up(end, PAGESIZE),
+            _ => return Err("mprotect: invalid length"),
+        };
+        self.mm().protect_vmas(addr, end, prot)
+    }
 
-            return Ok(());
-        } else if vm.fd < 0 {
+    /// writes the dirty pages of shared file mappings in [addr, addr + len) back to the files.
+    pub fn msync(&self, addr: usize, len: usize) -> Result<(), &'static str> {
+        if addr % PAGESIZE != 0 {
+            return Err("msync: addr not aligned");
+        }
+        let end = addr.checked_add(len).ok_or("msync: invalid length")?;
+
+        for va in (addr..end).step_by(PAGESIZE) {
+            let mut mm = self.mm();
+            let vm = mm
+                .find_vma(va)
+                .ok_or("msync: the addr is not lived in VMA")?;
+            let write_back = match &vm.file {
+                Some(f) if vm.flags.contains(MapFlag::SHARED) => {
+                    Some((f.clone(), vm.offset + (va - vm.addr_start)))
+                }
+                _ => None,
+            };
+            let (f, offset) = match write_back {
+                Some(write_back) if mm.page_table.take_dirty(va) => write_back,
+                _ => continue,
+            };
+            let (pa, _) = mm.page_table.lookup(va).unwrap();
+            drop(mm);
+
+            f.write_at(pa as *const u8, offset, PAGESIZE)?;
+        }
+
+        Ok(())
+    }
+
+    /// faults in the pages of the MAP_SHARED regions that are not mapped yet, so that a forked
+    /// child shares every page of them rather than getting its own when it touches one.
+    fn populate_shared(&mut self) -> Result<(), &'static str> {
+        let shared: Vec<(usize, usize)> = self
+            .mm()
+            .vmas
+            .values()
+            .filter(|vm| vm.flags.contains(MapFlag::SHARED))
+            .map(|vm| (vm.addr_start, vm.addr_end))
+            .collect();
+        for (start, end) in shared {
+            for va in (start..end).step_by(PAGESIZE) {
+                if self.mm().page_table.lookup(va).is_none() {
+                    self.lazy_mmap(va)?;
+                }
+            }
         }
+        Ok(())
+    }
+
+    /// leaves the address space on exit. the last thread to leave writes the mmap-ed files back.
+    pub fn leave_mm(&mut self) {
+        leave_mm(self.mm.as_ref().unwrap());
+    }
+
+    /// The reason to be lazy is to ensure that mmap-ing a large file is fast, and tha mmap-ing a
+    /// file larger than physical memory is possible.
+    pub fn lazy_mmap(&self, fault_addr: usize) -> Result<(), &'static str> {
+        let va = align_down(fault_addr, PAGESIZE);
+
+        // find which VMA owns the VA.
+        let mm = self.mm();
+        let vm = mm
+            .find_vma(va)
+            .ok_or("lazy_mmap: the addr is not lived in VMA")?;
+        let file = vm.file.clone();
+        let offset = vm.offset + (va - vm.addr_start);
+        let len = cmp::min(PAGESIZE, vm.file_end.saturating_sub(va));
+        // a page never written to through the mapping is shared with the other processes mapping
+        // it through the page cache, such as the text of a program. a store after mprotect()
+        // copies it.
+        let cached = vm.flags.contains(MapFlag::PRIVATE) && !vm.prot.contains(PteFlag::WRITE);
+        drop(mm);
 
         // TODO: even if the data is in kernel memory in the buffer cache, the current solution is
         // allocating a new physical page for each page read from mmap-ed file.
@@ -370,14 +1068,37 @@ impl ProcData {
         // and need to pin mmap-ed blocks into the buffer cache. We will need worry about reference
         // counts.
         //
-        //
-        // read 4096 bytes from the file to the page.
-        let f = self.o_files[vm.fd as usize].as_ref().unwrap().clone();
-        let offset = fault_addr_head - vm.addr_start;
-        f.seek(offset);
-        f.read(fault_addr_head, PAGESIZE)?;
+        // read the page in before it is mapped, so that no thread sees it half read. the part
+        // past the end of the file stays zero.
+        let pa = match file {
+            Some(f) if len > 0 && cached => f.read_cached(offset, len)?,
+            file => {
+                let pa = unsafe { SinglePage::alloc_into_raw() }.or(Err(ENOMEM))? as usize;
+                if let Some(f) = file.filter(|_| len > 0) {
+                    if let Err(msg) = f.read_at(pa as *mut u8, offset, len) {
+                        put_page(pa);
+                        return Err(msg);
+                    }
+                }
+                pa
+            }
+        };
 
-        Ok(())
+        // map the page into the user address space, by installing to user page table.
+        let mut mm = self.mm();
+        if mm.find_vma(va).is_none() || mm.page_table.lookup(va).is_some() {
+            // another thread sharing the address space has faulted on the page first, or has
+            // unmapped it.
+            drop(mm);
+            put_page(pa);
+            return Ok(());
+        }
+        let res = mm.map_vma_page(va, pa, PteFlag::empty());
+        drop(mm);
+        if res.is_err() {
+            put_page(pa);
+        }
+        res
     }
 }
 
@@ -385,6 +1106,11 @@ pub struct Proc {
     pub index: usize,
     pub inner: SpinLock<ProcInner>,
     pub data: UnsafeCell<ProcData>,
+    // odd while the process runs in user space on `hart`, where the TLB may hold entries of the
+    // page table at `satp`. it changes on every trap, which flushes them.
+    epoch: AtomicUsize,
+    satp: AtomicUsize,
+    hart: AtomicUsize,
 }
 
 impl Proc {
@@ -393,19 +1119,68 @@ impl Proc {
             index,
             inner: SpinLock::new(ProcInner::new(), "proc"),
             data: UnsafeCell::new(ProcData::new()),
+            epoch: AtomicUsize::new(0),
+            satp: AtomicUsize::new(0),
+            hart: AtomicUsize::new(0),
+        }
+    }
+
+    /// records a trap from user space into the kernel.
+    pub fn enter_kernel(&self) {
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// records a return to user space with the page table at `satp`.
+    pub fn enter_user(&self, satp: usize) {
+        self.satp.store(satp, Ordering::SeqCst);
+        self.hart.store(CpuTable::cpu_id(), Ordering::SeqCst);
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// waits until the TLB of the hart running the process holds no entry of the page table at
+    /// `satp` made before the call, that is until the hart flushes it on being asked, or traps, if
+    /// it runs in user space with it. the hart flushes it in machine mode, so the wait is bounded
+    /// by the delivery of an interrupt, even if both harts run with interrupts off.
+    pub fn wait_tlb_flush(&self, satp: usize) {
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        if epoch % 2 == 0 || self.satp.load(Ordering::SeqCst) != satp {
+            return;
         }
+        let hart = self.hart.load(Ordering::SeqCst);
+        let ticket = start::remote_sfence_vma(hart);
+        while self.epoch.load(Ordering::SeqCst) == epoch && !start::tlb_flushed(hart, ticket) {}
     }
 
     pub unsafe fn yield_process(&mut self) {
         let mut guard = self.inner.lock();
         if guard.state == ProcState::Running {
             let ctx = &mut self.data.get_mut().context;
-            guard.state = ProcState::Runnable;
+            sched::make_runnable(self.index, &mut guard);
             guard = CPU_TABLE.my_cpu_mut().sched(guard, ctx);
         }
         drop(guard);
     }
 
+    /// charges a clock tick to the process, and gives up the CPU if the scheduler says so.
+    /// `is_user` tells whether the tick interrupted user code or the kernel.
+    pub unsafe fn tick(&mut self, is_user: bool) {
+        let mut guard = self.inner.lock();
+        if is_user {
+            guard.usage.utime += 1;
+        } else {
+            guard.usage.stime += 1;
+        }
+        let expired = guard.state == ProcState::Running && sched::tick(&mut guard);
+        drop(guard);
+        if expired {
+            self.yield_process();
+        }
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.inner.lock().killed
+    }
+
     /// Atomically release lock and sleep on chan.
     /// The passed-in guard must not be the proc's guard to avoid deadlock.
     pub fn sleep<'a, T>(&mut self, chan: usize, lk: SpinLockGuard<'a, T>) -> SpinLockGuard<'a, T> {
@@ -432,27 +1207,52 @@ impl Proc {
     /// process.
     /// this function returns the new process's pid in the calling process, and returns zero in the child process.
     pub fn fork(&mut self) -> Result<usize, &'static str> {
-        let child =
-            unsafe { PROCESS_TABLE.alloc_proc() }.ok_or_else(|| "cannot allocate new process")?;
+        let pguard = self.inner.lock();
+        let psig = pguard.sig;
+        let psched = pguard.sched;
+        let paffinity = pguard.affinity;
+        let psid = pguard.sid;
+        drop(pguard);
+
+        self.data.get_mut().populate_shared()?;
 
-        let cguard = child.inner.lock();
+        let child = unsafe { PROCESS_TABLE.alloc_proc() }?;
+
+        let mut cguard = child.inner.lock();
 
         // copy user memory from parent to child.
         let pdata = self.data.get_mut();
         let cdata = child.data.get_mut();
-        let cpgt = cdata.page_table.as_mut().unwrap();
-        let sz = pdata.sz;
-        if pdata
+        if let Err(msg) = cdata.init_mm() {
+            Self::free(cdata, cguard);
+            return Err(msg);
+        }
+        let mut pmm = pdata.mm();
+        let mut cmm = cdata.mm();
+        let (heap_start, sz) = (pmm.heap_start, pmm.sz);
+        if pmm
             .page_table
-            .as_mut()
-            .unwrap()
-            .uvm_copy(cpgt, sz)
+            .uvm_copy(&mut cmm.page_table, heap_start, sz)
             .is_err()
         {
+            drop(cmm);
+            drop(pmm);
             Self::free(cdata, cguard);
-            return Err("fork: cannot uvm_copy");
+            return Err(ENOMEM);
         };
-        cdata.sz = sz;
+        cmm.heap_start = heap_start;
+        cmm.sz = sz;
+        cmm.mmap_top = pmm.mmap_top;
+        if let Err(msg) = pmm.dup_vmas(&mut cmm) {
+            drop(cmm);
+            drop(pmm);
+            Self::free(cdata, cguard);
+            return Err(msg);
+        }
+        // the other threads must not go on storing to the pages now shared with the child.
+        pmm.page_table.flush_tlb();
+        drop(cmm);
+        drop(pmm);
 
         // copy saved user registers.
         unsafe { ptr::copy_nonoverlapping(pdata.trapframe, cdata.trapframe, 1) };
@@ -461,12 +1261,14 @@ impl Proc {
         unsafe { cdata.trapframe.as_mut() }.unwrap().a0 = 0;
 
         // incremenet reference counts on open file descriptors.
-        for i in 0..pdata.o_files.len() {
-            if let Some(ref f) = pdata.o_files[i] {
-                cdata.o_files[i].replace(f.clone());
+        let files = pdata.files().dup();
+        match Arc::try_new(SpinLock::new(files, "files")) {
+            Ok(files) => cdata.files = Some(files),
+            Err(_) => {
+                Self::free(cdata, cguard);
+                return Err(ENOMEM);
             }
         }
-        cdata.cwd = Some(INODE_TABLE.idup(&pdata.cwd.as_ref().unwrap()));
         
         // Copy environment variables from parent to child
         if let Some(parent_env_vars) = &pdata.env_vars {
@@ -482,6 +1284,12 @@ impl Proc {
                 }
             }
         }
+
+        // the child inherits the signal handlers and the blocked mask.
+        cguard.sig.inherit(&psig);
+        cguard.sched = psched;
+        cguard.affinity = paffinity;
+        cguard.sid = psid;
         drop(cguard);
 
         // set parent
@@ -490,45 +1298,109 @@ impl Proc {
         drop(parents);
 
         let mut cguard = child.inner.lock();
-        cguard.state = ProcState::Runnable;
+        sched::make_runnable(child.index, &mut cguard);
+        let pid = cguard.pid;
+        drop(cguard);
+
+        Ok(pid)
+    }
+
+    /// creates a thread: a new process that shares the address space, the open files and the
+    /// current directory with the calling process.
+    /// the thread starts at `entry` with `arg` as its argument and `stack` as its stack pointer,
+    /// and returns to `ret` when `entry` returns. returns the thread's pid, which join() takes.
+    pub fn clone(
+        &mut self,
+        entry: usize,
+        arg: usize,
+        stack: usize,
+        ret: usize,
+    ) -> Result<usize, &'static str> {
+        let pguard = self.inner.lock();
+        let psig = pguard.sig;
+        let psched = pguard.sched;
+        let paffinity = pguard.affinity;
+        let psid = pguard.sid;
+        drop(pguard);
+
+        let child = unsafe { PROCESS_TABLE.alloc_proc() }?;
+
+        let mut cguard = child.inner.lock();
+
+        // every thread has its own trapframe, which the trampoline finds in the shared address
+        // space. the slots beneath TRAPFRAME are indexed by process, so they never collide.
+        let pdata = self.data.get_mut();
+        let cdata = child.data.get_mut();
+        let va = TRAPFRAME - (child.index + 1) * PAGESIZE;
+        let mut pmm = pdata.mm();
+        if pmm
+            .page_table
+            .map_pages(
+                va,
+                cdata.trapframe as usize,
+                PAGESIZE,
+                PteFlag::READ | PteFlag::WRITE,
+            )
+            .is_err()
+        {
+            drop(pmm);
+            Self::free(cdata, cguard);
+            return Err("clone: cannot map trapframe");
+        }
+        pmm.users += 1;
+        drop(pmm);
+        cdata.trapframe_va = va;
+        cdata.mm = pdata.mm.clone();
+        cdata.files = pdata.files.clone();
+        cdata.env_vars = pdata.env_vars.clone();
+
+        // start at `entry` with the parent's registers otherwise.
+        unsafe { ptr::copy_nonoverlapping(pdata.trapframe, cdata.trapframe, 1) };
+        let tf = unsafe { cdata.trapframe.as_mut() }.unwrap();
+        tf.epc = entry;
+        tf.a0 = arg;
+        tf.sp = stack;
+        tf.ra = ret;
+
+        cguard.thread = true;
+        cguard.sig.inherit(&psig);
+        cguard.sched = psched;
+        cguard.affinity = paffinity;
+        cguard.sid = psid;
+        drop(cguard);
+
+        let mut parents = unsafe { PROCESS_TABLE.parents.lock() };
+        parents[child.index] = Some(self.index);
+        drop(parents);
+
+        let mut cguard = child.inner.lock();
+        sched::make_runnable(child.index, &mut cguard);
         let pid = cguard.pid;
         drop(cguard);
 
         Ok(pid)
     }
 
-    /// free the page of trapframe and user code, clear the process's inner state.
+    /// free the page of trapframe and let go of the address space, clear the process's inner state.
+    /// the address space is torn down when the last thread that shares it is freed. the open files
+    /// must have been released by exit().
     ///
     /// free() must be called inside the process's critical section.
     pub fn free(pdata: &mut ProcData, mut inner: SpinLockGuard<ProcInner>) {
+        if let Some(mm) = pdata.mm.take() {
+            // the other threads keep running in the address space, so take only our trapframe out.
+            mm.lock()
+                .page_table
+                .unmap_pages(pdata.trapframe_va, 1, false)
+                .expect("cannot unmap trapframe");
+            drop(mm);
+        }
+        pdata.trapframe_va = TRAPFRAME;
+
         if !pdata.trapframe.is_null() {
             unsafe { SinglePage::free_from_raw(pdata.trapframe as *mut _) };
             pdata.trapframe = ptr::null_mut();
         }
-
-        if pdata.page_table.is_some() {
-            let pgt = pdata.page_table.as_mut().unwrap();
-
-            // the first, unmap trampoline, trapframe and the user code.
-            pgt.unmap_user_page_table(pdata.sz);
-
-            // the second, unmap all mmap-ed region.
-            for vm in pdata.vm_area.iter_mut() {
-                if vm.is_some() {
-                    let vm = vm.as_ref().unwrap();
-                    for va in (vm.addr_start..vm.addr_end).step_by(PAGESIZE) {
-                        if let Ok(_) = pgt.walk_addr(va) {
-                            pgt.unmap_pages(va, 1, true)
-                                .expect("cannot unmap in freeing");
-                        }
-                    }
-                }
-                vm.take();
-            }
-        }
-        drop(pdata.page_table.take());
-        pdata.cur_max = MAXVA - 2 * PAGESIZE;
-        pdata.sz = 0;
         
         // Clear environment variables
         if let Some(env_vars) = &mut pdata.env_vars {
@@ -540,6 +1412,15 @@ impl Proc {
         inner.pid = 0;
         inner.killed = false;
         inner.exit_status = 0;
+        inner.thread = false;
+        inner.timeout = 0;
+        inner.sig = SigState::new();
+        inner.sched = SchedInfo::new();
+        inner.usage = Rusage::new();
+        inner.child_usage = Rusage::new();
+        inner.affinity = ALL_CPUS;
+        inner.sid = 0;
+        inner.last_cpu = 0;
     }
 
     pub fn syscall(&mut self) {
@@ -556,11 +1437,15 @@ impl Proc {
             3 => self.sys_wait(),
             4 => self.sys_pipe(),
             5 => self.sys_read(),
+            6 => self.sys_kill(),
             7 => self.sys_exec(),
             8 => self.sys_fstat(),
             9 => self.sys_chdir(),
             10 => self.sys_dup(),
+            11 => self.sys_getpid(),
             12 => self.sys_sbrk(),
+            13 => self.sys_sleep(),
+            14 => self.sys_uptime(),
             15 => self.sys_open(),
             16 => self.sys_write(),
             17 => self.sys_mknod(),
@@ -575,12 +1460,45 @@ impl Proc {
             29 => self.sys_setenv(),
             30 => self.sys_unsetenv(),
             31 => self.sys_listenv(),
+            32 => self.sys_sigaction(),
+            33 => self.sys_sigprocmask(),
+            34 => self.sys_sigreturn(),
+            35 => self.sys_alarm(),
+            36 => self.sys_clock_gettime(),
+            37 => self.sys_getppid(),
+            38 => self.sys_waitpid(),
+            39 => self.sys_setpriority(),
+            40 => self.sys_getpriority(),
+            41 => self.sys_sched_setaffinity(),
+            42 => self.sys_sched_getaffinity(),
+            43 => self.sys_clone(),
+            44 => self.sys_join(),
+            45 => self.sys_futex_wait(),
+            46 => self.sys_futex_wake(),
+            47 => self.sys_getrusage(),
+            48 => self.sys_munmap(),
+            49 => self.sys_msync(),
+            50 => self.sys_mprotect(),
+            51 => self.sys_meminfo(),
+            52 => self.sys_setsid(),
             _ => {
                 panic!("unknown syscall: {}", num);
             }
         };
         trapframe.a0 = match ret {
             Ok(ret) => ret,
+            Err(msg) if msg == ENOMEM => {
+                println!("syscall error: no={} {}", num, msg);
+                -ENOMEM_ERRNO as usize
+            }
+            Err(msg) if msg == EFAULT => {
+                println!("syscall error: no={} {}", num, msg);
+                -EFAULT_ERRNO as usize
+            }
+            Err(msg) if msg == EINTR => {
+                println!("syscall error: no={} {}", num, msg);
+                -EINTR_ERRNO as usize
+            }
             Err(msg) => {
                 println!("syscall error: no={} {}", num, msg);
                 -1isize as usize
@@ -596,12 +1514,24 @@ impl Proc {
 
     #[inline]
     fn fetch_str(&mut self, addr: usize, dst: &mut [u8]) -> Result<usize, &'static str> {
-        self.data
-            .get_mut()
-            .page_table
-            .as_ref()
-            .unwrap()
-            .copy_in_str(dst, addr)
+        let pdata = self.data.get_mut();
+        // the string may end before the pages that cannot be faulted in.
+        let end = addr.saturating_add(dst.len());
+        loop {
+            for va in (align_down(addr, PAGESIZE)..end).step_by(PAGESIZE) {
+                if let Err(msg) = pdata.handle_page_fault(va, PteFlag::READ) {
+                    if va <= addr {
+                        return Err(bad_address(msg));
+                    }
+                    break;
+                }
+            }
+            // a page may have been swapped out again in between.
+            match pdata.mm().page_table.copy_in_str(dst, addr) {
+                Err(msg) if msg == SWAPPED_OUT => continue,
+                res => return res,
+            }
+        }
     }
 
     #[inline]
@@ -634,7 +1564,7 @@ impl Proc {
             return Err("file descriptor must be less than NOFILE");
         }
 
-        if self.data.get_mut().o_files[fd as usize].is_none() {
+        if self.data.get_mut().files().o_files[fd as usize].is_none() {
             return Err("file descriptor not allocated");
         }
 
@@ -643,22 +1573,16 @@ impl Proc {
 
     #[inline]
     fn alloc_fd(&mut self) -> Result<usize, ()> {
-        for (i, f) in self.data.get_mut().o_files.iter().enumerate() {
-            if f.is_none() {
-                return Ok(i);
-            }
-        }
-        Err(())
+        let files = self.data.get_mut().files();
+        let fd = files.o_files.iter().position(|f| f.is_none());
+        drop(files);
+        fd.ok_or(())
     }
 
     #[inline]
     fn fetch_addr(&mut self, addr: usize) -> Result<usize, &'static str> {
-        if addr >= self.data.get_mut().sz || addr + mem::size_of::<usize>() > self.data.get_mut().sz
-        {
-            return Err("fetch_addr size");
-        }
         let mut dst: usize = 0;
-        self.data.get_mut().page_table.as_ref().unwrap().copy_in(
+        self.data.get_mut().copy_in(
             &mut dst as *mut usize as *mut u8,
             addr,
             mem::size_of::<usize>(),
@@ -667,29 +1591,58 @@ impl Proc {
     }
 }
 
-pub fn either_copy_out(is_user: bool, dst: *mut u8, src: *const u8, count: usize) {
+/// the error of a syscall given a user address that the process cannot access as it asks, such
+/// as a buffer to read into that is not writable. the syscall returns -EFAULT_ERRNO rather than -1.
+pub const EFAULT: &str = "bad address";
+pub const EFAULT_ERRNO: isize = 14;
+
+/// the error of a syscall that gives up sleeping because the process is killed or has a signal
+/// to handle. the syscall returns -EINTR_ERRNO rather than -1.
+pub const EINTR: &str = "interrupted";
+pub const EINTR_ERRNO: isize = 4;
+
+/// the error of an access to user memory: ENOMEM if it ran out of memory, EFAULT otherwise.
+fn bad_address(msg: &'static str) -> &'static str {
+    if msg == ENOMEM {
+        msg
+    } else {
+        EFAULT
+    }
+}
+
+/// copies to either a user address, or a kernel address. copying to user space fails if the
+/// address is not writable by the process, or memory runs out faulting it in.
+pub fn either_copy_out(
+    is_user: bool,
+    dst: *mut u8,
+    src: *const u8,
+    count: usize,
+) -> Result<(), &'static str> {
     if is_user {
         // copy from kernel to user
         let p = unsafe { CPU_TABLE.my_proc() };
-        p.data
-            .get_mut()
-            .copy_out(dst as usize, src, count)
-            .expect("either_copy_out");
+        p.data.get_mut().copy_out(dst as usize, src, count)
     } else {
         unsafe { ptr::copy(src, dst, count) };
+        Ok(())
     }
 }
 
-pub fn either_copy_in(is_user: bool, src: *const u8, dst: *mut u8, count: usize) {
+/// copies from either a user address, or a kernel address. copying from user space fails if the
+/// address is not readable by the process, or memory runs out faulting it in.
+pub fn either_copy_in(
+    is_user: bool,
+    src: *const u8,
+    dst: *mut u8,
+    count: usize,
+) -> Result<(), &'static str> {
     if is_user {
         // copy from user to kernel
         let p = unsafe { CPU_TABLE.my_proc() };
-        p.data
-            .get_mut()
-            .copy_in(dst, src as usize, count)
-            .expect("either_copy_in");
+        p.data.get_mut().copy_in(dst, src as usize, count)
     } else {
         unsafe { ptr::copy(src, dst, count) };
+        Ok(())
     }
 }
 
@@ -729,14 +1682,15 @@ static USERTESTS_INITCODE: [u8; 40] = [
 #[cfg(test)]
 pub fn usertests() {
     let p = unsafe { CPU_TABLE.my_proc() };
-    let pdata = p.data.get_mut();
-    let pgt = pdata.page_table.as_mut().unwrap();
+    let mut mm = p.data.get_mut().mm();
+    let pgt = &mut mm.page_table;
 
     // clear the first page in the pagetable and then map new code.
     // it will be executed as a child process by forking.
     pgt.unmap_pages(0, 1, true).expect("cannot unmap initcode");
     pgt.uvm_init(&USERTESTS_INITCODE)
         .expect("cannot map the test code into the page");
+    drop(mm);
 
     // the child process would be scheduled on cpu_id=1, then runs the code in user space.
     let child_pid = p.fork().expect("fork failed");
@@ -746,8 +1700,8 @@ pub fn usertests() {
     assert_eq!(child_pid, waited_pid);
 
     // check reported exit status
-    let pdata = p.data.get_mut();
-    let pgt = pdata.page_table.as_mut().unwrap();
+    let mut mm = p.data.get_mut().mm();
+    let pgt = &mut mm.page_table;
     let pa = pgt.walk_addr(0).expect("cannot walk") as *const u8;
     let reported_status = unsafe { (pa.offset(1) as *const i32).as_ref().unwrap() };
     assert_eq!(0i32, *reported_status);
@@ -765,8 +1719,67 @@ mod tests {
     #[test_case]
     fn test_forkret() {
         let pdata = unsafe { CPU_TABLE.my_proc() }.data.get_mut();
-        assert_eq!(PAGESIZE, pdata.sz);
+        assert_eq!(PAGESIZE, pdata.mm().sz);
         let tf = unsafe { pdata.trapframe.as_ref() }.unwrap();
         assert_eq!(PAGESIZE, tf.sp);
     }
+
+    #[test_case]
+    fn vmas_split_and_merge() {
+        let trapframe = unsafe { SinglePage::alloc_into_raw() }.expect("trapframe");
+        let pgt = PageTable::alloc_user_page_table(trapframe as usize)
+            .expect("cannot alloc user page table");
+        let mut mm = Mm::new(pgt);
+        let rw = PteFlag::READ | PteFlag::WRITE;
+        let anon = |addr_start, addr_end| VMA {
+            addr_start,
+            addr_end,
+            prot: rw,
+            flags: MapFlag::PRIVATE | MapFlag::ANONYMOUS,
+            file: None,
+            offset: 0,
+            file_end: addr_end,
+        };
+
+        // adjacent regions alike are merged.
+        let base = MMAP_TOP - 4 * PAGESIZE;
+        mm.insert_vma(anon(base, base + PAGESIZE));
+        mm.insert_vma(anon(base + PAGESIZE, base + 3 * PAGESIZE));
+        assert_eq!(1, mm.vmas.len());
+        assert_eq!(base, mm.mmap_base());
+        assert!(!mm.is_free(base + 2 * PAGESIZE, 2 * PAGESIZE));
+        assert!(mm.is_free(base + 3 * PAGESIZE, PAGESIZE));
+
+        // protecting the middle splits it in three, and restoring merges them back.
+        mm.protect_vmas(base + PAGESIZE, base + 2 * PAGESIZE, PteFlag::READ)
+            .expect("protect_vmas");
+        assert_eq!(3, mm.vmas.len());
+        assert_eq!(PteFlag::READ, mm.find_vma(base + PAGESIZE).unwrap().prot);
+        assert_eq!(rw, mm.find_vma(base + 2 * PAGESIZE).unwrap().prot);
+        mm.protect_vmas(base + PAGESIZE, base + 2 * PAGESIZE, rw)
+            .expect("protect_vmas");
+        assert_eq!(1, mm.vmas.len());
+        assert!(mm
+            .protect_vmas(base, base + 4 * PAGESIZE, PteFlag::READ)
+            .is_err());
+
+        // unmapping the middle leaves two.
+        let (pages, removed) = mm
+            .take_vmas(base + PAGESIZE, base + 2 * PAGESIZE)
+            .expect("take_vmas");
+        assert!(pages.is_empty());
+        assert_eq!(1, removed.len());
+        assert_eq!(2, mm.vmas.len());
+        assert!(mm.find_vma(base + PAGESIZE).is_none());
+        assert_eq!(
+            base + 2 * PAGESIZE,
+            mm.find_vma(base + 2 * PAGESIZE).unwrap().addr_start
+        );
+
+        mm.page_table
+            .unmap_pages(TRAPFRAME, 1, false)
+            .expect("cannot unmap trapframe");
+        drop(mm);
+        unsafe { SinglePage::free_from_raw(trapframe) };
+    }
 }
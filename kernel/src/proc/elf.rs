@@ -1,26 +1,46 @@
-use core::{cmp, mem};
+use core::{cmp, mem, ptr};
 
-use alloc::boxed::Box;
+use alloc::{boxed::Box, sync::Arc};
 
 use crate::{
+    file::File,
     fs::{InodeData, INODE_TABLE},
+    kalloc::ENOMEM,
     log::LOG,
-    page_table::{align_up, PageTable},
-    param::PAGESIZE,
-    proc::ProcData,
+    page_table::{align_down, align_up, Page, PageTable, PteFlag, SinglePage},
+    param::{
+        ASLR_LOAD_PAGES, ASLR_MMAP_PAGES, ASLR_STACK_PAGES, PAGESIZE, PIE_BASE, TRAPFRAME,
+        USTACK_SIZE,
+    },
+    proc::{leave_mm, MapFlag, Mm, ProcData, MMAP_TOP, VMA},
+    random,
     sleeplock::SleepLockGuard,
+    spinlock::SpinLock,
 };
 
 use super::{MAXARG, MAXARGLEN};
 
 const MAGIC: u32 = 0x464C457F;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3; // position-independent
 const PROG_LOAD: u32 = 1;
+const PROG_DYNAMIC: u32 = 2;
+const PROG_INTERP: u32 = 3;
+// the permissions of a segment.
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+const PF_R: u32 = 1 << 2;
 
 pub fn load(
     p: &mut ProcData,
     path: &[u8],
     argv: &[Option<Box<[u8; MAXARGLEN]>>; MAXARG],
 ) -> Result<usize, &'static str> {
+    // the other threads would be left running in an address space that is gone.
+    if Arc::strong_count(p.mm.as_ref().unwrap()) > 1 {
+        return Err("exec: other threads share the address space");
+    }
+
     LOG.begin_op();
 
     let inode = match INODE_TABLE.namei(&path) {
@@ -54,93 +74,70 @@ pub fn load(
         return Err("elf magic invalid");
     }
 
-    // Allocate a new user page table with 2 pages (trampoline and trapframe).
-    let mut pgt = match PageTable::alloc_user_page_table(p.trapframe as usize) {
-        None => {
-            drop(idata);
-            drop(inode);
-            LOG.end_op();
-            return Err("cannot alloc new user page table");
-        }
-        Some(pgt) => pgt,
-    };
+    if elfhdr.phentsize as usize != mem::size_of::<ProgHeader>() {
+        drop(idata);
+        drop(inode);
+        LOG.end_op();
+        return Err("exec: unexpected program header size");
+    }
 
-    let mut size = 0usize;
+    if elfhdr.typ != ET_EXEC && elfhdr.typ != ET_DYN {
+        drop(idata);
+        drop(inode);
+        LOG.end_op();
+        return Err("exec: not an executable");
+    }
 
-    // Load program into memory.
-    let off_start = elfhdr.phoff as usize;
-    let ph_size = mem::size_of::<ProgHeader>();
-    let off_end = off_start + elfhdr.phnum as usize * ph_size;
-    for off in (off_start..off_end).step_by(ph_size) {
-        // read program header section
-        let mut ph = mem::MaybeUninit::<ProgHeader>::uninit();
-        let ph_ptr = ph.as_mut_ptr() as *mut u8;
-        if idata.readi(false, ph_ptr, off, ph_size).is_err() {
-            pgt.unmap_user_page_table(size);
+    // Allocate a new user page table with 2 pages (trampoline and trapframe).
+    let pgt = match PageTable::alloc_user_page_table(p.trapframe as usize) {
+        Err(msg) => {
             drop(idata);
             drop(inode);
             LOG.end_op();
-            return Err("cannot read the program section");
-        };
-        let ph = unsafe { ph.assume_init() };
-        if ph.typ != PROG_LOAD {
-            continue;
-        }
-        if ph.memsz == 0 {
-            continue;
+            return Err(msg);
         }
+        Ok(pgt) => pgt,
+    };
+    let mut mm = Mm::new(pgt);
 
-        size = match pgt.uvm_alloc(size, (ph.vaddr + ph.memsz) as usize) {
-            Err(msg) => {
-                pgt.unmap_user_page_table(size);
-                drop(idata);
-                drop(inode);
-                LOG.end_op();
-                return Err(msg);
-            }
-            Ok(size) => size,
-        };
-
-        if ph.vaddr as usize % PAGESIZE != 0 {
-            pgt.unmap_user_page_table(size);
-            drop(idata);
-            drop(inode);
-            LOG.end_op();
-            return Err("program header vaddr not aligned page size");
-        }
+    // the layout is randomized on each exec: the top of the stack, the gap between it and the
+    // mmap-ed regions, and the base a position-independent program is loaded at.
+    let stack_top = MMAP_TOP - random::below(ASLR_STACK_PAGES) * PAGESIZE;
+    mm.mmap_top = stack_top - USTACK_SIZE - PAGESIZE - random::below(ASLR_MMAP_PAGES) * PAGESIZE;
+    let base = match elfhdr.typ {
+        ET_DYN => PIE_BASE + random::below(ASLR_LOAD_PAGES) * PAGESIZE,
+        _ => 0,
+    };
 
-        if let Err(msg) = load_segment(
-            &mut pgt,
-            &mut idata,
-            ph.vaddr as usize,
-            ph.off as usize,
-            ph.filesz as usize,
-        ) {
-            pgt.unmap_user_page_table(size);
+    // the stack lives apart from the image and the heap, and grows on demand. its top page is
+    // there for the arguments. the segments are paged in from the file when they are first
+    // touched, so they keep it open, and the inode with it, while they are mapped.
+    let file = match File::from_inode(&inode) {
+        Err(msg) => {
             drop(idata);
             drop(inode);
             LOG.end_op();
+            discard(mm);
             return Err(msg);
-        };
-    }
+        }
+        Ok(file) => file,
+    };
+    let res = mm
+        .map_stack(stack_top)
+        .and_then(|top| map_segments(&mut mm, &mut idata, &file, &elfhdr, base).map(|_| top));
 
     drop(idata);
     drop(inode);
     LOG.end_op();
+    drop(file);
 
-    size = align_up(size, PAGESIZE); // must be aligned
-
-    // Allocate two pages.
-    // Use the second as the user stack.
-    size = match pgt.uvm_alloc(size, size + PAGESIZE * 2) {
+    let mut sp = match res {
         Err(msg) => {
-            pgt.unmap_user_page_table(size);
+            discard(mm);
             return Err(msg);
         }
-        Ok(size) => size,
+        Ok(top) => top,
     };
-    pgt.uvm_clear(size - 2 * PAGESIZE);
-    let mut sp = size;
     let stackbase = sp - PAGESIZE;
 
     // the arguments laid out ...
@@ -162,12 +159,12 @@ pub fn load(
         sp -= arg_size;
         sp -= sp % 16; // riscv sp must be 16-byte aligned.
         if sp < stackbase {
-            pgt.unmap_user_page_table(size);
+            discard(mm);
             return Err("pushing arguments causes stack over flow");
         }
         // copy out argv[i]'s data to the virtual address pointed to by `sp`.
-        if let Err(msg) = pgt.copy_out(sp, arg.as_ptr(), arg_size) {
-            pgt.unmap_user_page_table(size);
+        if let Err(msg) = mm.page_table.copy_out(sp, arg.as_ptr(), arg_size) {
+            discard(mm);
             return Err(msg);
         };
         ustack[i] = sp;
@@ -178,11 +175,14 @@ pub fn load(
     sp -= ustack_size;
     sp -= sp % 16;
     if sp < stackbase {
-        pgt.unmap_user_page_table(size);
+        discard(mm);
         return Err("pushing arguments causes stack over flow");
     }
-    if let Err(msg) = pgt.copy_out(sp, ustack.as_ptr() as *const u8, ustack_size) {
-        pgt.unmap_user_page_table(size);
+    if let Err(msg) = mm
+        .page_table
+        .copy_out(sp, ustack.as_ptr() as *const u8, ustack_size)
+    {
+        discard(mm);
         return Err(msg);
     }
 
@@ -192,35 +192,259 @@ pub fn load(
     tf.a1 = sp;
 
     // comit to the user image
-    let mut oldpgt = p.page_table.replace(pgt).unwrap();
-    let oldsz = p.sz;
-    p.sz = size;
-    tf.epc = elfhdr.entry as usize;
+    let mut newmm = match Arc::try_new_uninit() {
+        Err(_) => {
+            discard(mm);
+            return Err(ENOMEM);
+        }
+        Ok(newmm) => newmm,
+    };
+    Arc::get_mut(&mut newmm)
+        .unwrap()
+        .write(SpinLock::new(mm, "mm"));
+    let oldmm = p.mm.replace(unsafe { newmm.assume_init() }).unwrap();
+    tf.epc = base + elfhdr.entry as usize;
     tf.sp = sp;
-    oldpgt.unmap_user_page_table(oldsz);
+
+    // the trapframe is mapped at TRAPFRAME in the new image. the old one goes with the last
+    // reference to it.
+    oldmm
+        .lock()
+        .page_table
+        .unmap_pages(p.trapframe_va, 1, false)
+        .expect("cannot unmap trapframe");
+    p.trapframe_va = TRAPFRAME;
+    leave_mm(&oldmm);
+    drop(oldmm);
 
     // pass the `argc` as the first argument in user space
     Ok(argc)
 }
 
-fn load_segment(
-    pgt: &mut PageTable,
+/// frees a new image that is not committed to. its trapframe belongs to the process.
+fn discard(mut mm: Mm) {
+    mm.page_table
+        .unmap_pages(TRAPFRAME, 1, false)
+        .expect("cannot unmap trapframe");
+}
+
+/// mmaps the loadable segments of the program at `base`, private to the process, and relocates
+/// them if it is position-independent. the heap starts right above them.
+fn map_segments(
+    mm: &mut Mm,
     idata: &mut SleepLockGuard<'_, InodeData>,
-    va: usize,
-    offset: usize,
-    sz: usize,
+    file: &Arc<File>,
+    elfhdr: &ELFHeader,
+    base: usize,
 ) -> Result<(), &'static str> {
-    for i in (0..sz).step_by(PAGESIZE) {
-        let pa = pgt.walk_addr(va + i)?;
-        let n = cmp::min(sz - i, PAGESIZE);
-        if idata.readi(false, pa as *mut u8, offset + i, n).is_err() {
-            return Err("load_segment: cannot read the program segment");
+    let off_start = elfhdr.phoff as usize;
+    let ph_size = mem::size_of::<ProgHeader>();
+    let off_end = off_start + elfhdr.phnum as usize * ph_size;
+    // (offset, size) of the dynamic section, which locates the relocations.
+    let mut dynamic = None;
+    for off in (off_start..off_end).step_by(ph_size) {
+        let ph: ProgHeader = read_struct(idata, off)?;
+        match ph.typ {
+            PROG_INTERP => return Err("exec: dynamically linked programs are unsupported"),
+            PROG_DYNAMIC if elfhdr.typ == ET_DYN => {
+                dynamic = Some((ph.off as usize, ph.filesz as usize));
+                continue;
+            }
+            PROG_LOAD if ph.memsz > 0 => {}
+            _ => continue,
+        }
+
+        let prot = segment_prot(&ph)?;
+        let (memsz, filesz) = (ph.memsz as usize, ph.filesz as usize);
+        let offset = ph.off as usize;
+        let start = base
+            .checked_add(ph.vaddr as usize)
+            .ok_or("exec: segment out of the address space")?;
+        if start % PAGESIZE != 0 {
+            return Err("exec: segment vaddr not aligned to the page size");
+        }
+        if ph.align > 1 && (!ph.align.is_power_of_two() || ph.vaddr % ph.align != ph.off % ph.align)
+        {
+            return Err("exec: segment vaddr and offset disagree with its alignment");
+        }
+        if filesz > memsz {
+            return Err("exec: segment filesz is larger than memsz");
+        }
+        match offset.checked_add(filesz) {
+            Some(file_end) if file_end <= idata.get_size() => {}
+            _ => return Err("exec: segment out of the file"),
+        }
+        let end = match start.checked_add(memsz) {
+            Some(end) if end <= mm.mmap_top => end,
+            _ => return Err("exec: segment out of the address space"),
         };
+        // each segment starts above the ones before it, which are below `mm.sz`.
+        if !mm.is_free(start, align_up(end, PAGESIZE) - start) {
+            return Err("exec: segments overlap");
+        }
+
+        // the part not in the file is the bss, zero-filled.
+        let (flags, file) = if filesz > 0 {
+            (MapFlag::PRIVATE, Some(file.clone()))
+        } else {
+            (MapFlag::PRIVATE | MapFlag::ANONYMOUS, None)
+        };
+        mm.insert_vma(VMA {
+            addr_start: start,
+            addr_end: align_up(end, PAGESIZE),
+            prot,
+            flags,
+            file,
+            offset,
+            file_end: start + filesz,
+        });
+        mm.sz = align_up(end, PAGESIZE);
+    }
+
+    mm.heap_start = mm.sz;
+    match dynamic {
+        Some((offset, size)) => relocate(mm, idata, base, offset, size),
+        None => Ok(()),
     }
+}
 
+/// applies the relocations of a position-independent program loaded at `base`, whose dynamic
+/// section is `size` bytes at `offset` of the file. they must all be relative to the base. the
+/// pages relocated are read in now, private to the process.
+fn relocate(
+    mm: &mut Mm,
+    idata: &mut SleepLockGuard<'_, InodeData>,
+    base: usize,
+    offset: usize,
+    size: usize,
+) -> Result<(), &'static str> {
+    let (mut rela, mut relasz, mut relaent) = (0, 0, mem::size_of::<Rela>());
+    let dyn_size = mem::size_of::<Dyn>();
+    for off in (offset..offset + size / dyn_size * dyn_size).step_by(dyn_size) {
+        let d: Dyn = read_struct(idata, off)?;
+        match d.tag {
+            DT_NULL => break,
+            DT_RELA => rela = d.val as usize,
+            DT_RELASZ => relasz = d.val as usize,
+            DT_RELAENT => relaent = d.val as usize,
+            DT_REL => return Err("exec: REL relocations are unsupported"),
+            _ => {}
+        }
+    }
+    if relasz == 0 {
+        return Ok(());
+    }
+    if relaent != mem::size_of::<Rela>() {
+        return Err("exec: unexpected relocation entry size");
+    }
+
+    let table = base
+        .checked_add(rela)
+        .ok_or("exec: relocations out of the file")?;
+    let table_off = file_offset(mm, table, relasz)?;
+    for off in (table_off..table_off + relasz / relaent * relaent).step_by(relaent) {
+        let r: Rela = read_struct(idata, off)?;
+        match (r.info & 0xffff_ffff) as u32 {
+            R_RISCV_NONE => {}
+            R_RISCV_RELATIVE => {
+                let va = base
+                    .checked_add(r.offset as usize)
+                    .filter(|va| va % mem::size_of::<u64>() == 0)
+                    .ok_or("exec: misaligned relocation")?;
+                let pa = load_page(mm, idata, align_down(va, PAGESIZE))?;
+                let value = (base as u64).wrapping_add(r.addend as u64);
+                unsafe { ptr::write((pa + va % PAGESIZE) as *mut u64, value) };
+            }
+            _ => return Err("exec: unsupported relocation"),
+        }
+    }
     Ok(())
 }
 
+/// the offset in the file of the `len` bytes at `va` of the new image.
+fn file_offset(mm: &Mm, va: usize, len: usize) -> Result<usize, &'static str> {
+    match mm.find_vma(va) {
+        Some(vm)
+            if vm.file.is_some() && va.checked_add(len).map_or(false, |end| end <= vm.file_end) =>
+        {
+            Ok(vm.offset + (va - vm.addr_start))
+        }
+        _ => Err("exec: relocations out of the file"),
+    }
+}
+
+/// the physical address of the page at `va` of the new image, which is read in from the file if
+/// it is not yet. it is private to the process, not from the page cache, so it may be written to.
+fn load_page(
+    mm: &mut Mm,
+    idata: &mut SleepLockGuard<'_, InodeData>,
+    va: usize,
+) -> Result<usize, &'static str> {
+    if let Some((pa, _)) = mm.page_table.lookup(va) {
+        return Ok(pa);
+    }
+    let vm = mm
+        .find_vma(va)
+        .ok_or("exec: relocation out of the segments")?;
+    let offset = vm.offset + (va - vm.addr_start);
+    let len = match vm.file {
+        Some(_) => cmp::min(PAGESIZE, vm.file_end.saturating_sub(va)),
+        None => 0,
+    };
+
+    let pa = unsafe { SinglePage::alloc_into_raw() }.or(Err(ENOMEM))? as usize;
+    if len > 0 && idata.readi(false, pa as *mut u8, offset, len).is_err() {
+        unsafe { SinglePage::free_from_raw(pa as *mut SinglePage) };
+        return Err("exec: cannot read the program segment");
+    }
+    // it is dirty, as the relocated page cannot be read in from the file again once swapped out.
+    if let Err(msg) = mm.map_vma_page(va, pa, PteFlag::DIRTY) {
+        unsafe { SinglePage::free_from_raw(pa as *mut SinglePage) };
+        return Err(msg);
+    }
+    Ok(pa)
+}
+
+/// reads a `T` at `off` of the file.
+fn read_struct<T>(
+    idata: &mut SleepLockGuard<'_, InodeData>,
+    off: usize,
+) -> Result<T, &'static str> {
+    let size = mem::size_of::<T>();
+    match off.checked_add(size) {
+        Some(end) if end <= idata.get_size() => {}
+        _ => return Err("exec: elf file truncated"),
+    }
+    let mut v = mem::MaybeUninit::<T>::uninit();
+    if idata
+        .readi(false, v.as_mut_ptr() as *mut u8, off, size)
+        .is_err()
+    {
+        return Err("cannot read the elf file");
+    }
+    Ok(unsafe { v.assume_init() })
+}
+
+/// the protection of a segment, from the permissions it asks for. a segment both writable and
+/// executable is refused, so that no page of the program is ever both.
+fn segment_prot(ph: &ProgHeader) -> Result<PteFlag, &'static str> {
+    if ph.flags & PF_W != 0 && ph.flags & PF_X != 0 {
+        return Err("exec: segment is both writable and executable");
+    }
+    let mut prot = PteFlag::empty();
+    if ph.flags & PF_R != 0 {
+        prot |= PteFlag::READ;
+    }
+    // a page writable but not readable is reserved on RISC-V.
+    if ph.flags & PF_W != 0 {
+        prot |= PteFlag::READ | PteFlag::WRITE;
+    }
+    if ph.flags & PF_X != 0 {
+        prot |= PteFlag::EXEC;
+    }
+    Ok(prot)
+}
+
 /// File header
 #[derive(Debug)]
 #[repr(C)]
@@ -247,6 +471,31 @@ struct ELFHeader {
     shstrndx: u16,
 }
 
+/// Dynamic section entry
+#[repr(C)]
+struct Dyn {
+    tag: u64,
+    val: u64,
+}
+
+// the tags of the dynamic entries that locate the relocations.
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_REL: u64 = 17;
+
+/// Relocation entry with an addend
+#[repr(C)]
+struct Rela {
+    offset: u64,
+    info: u64, // the symbol in the upper 32 bits, and the type in the lower.
+    addend: i64,
+}
+
+const R_RISCV_NONE: u32 = 0;
+const R_RISCV_RELATIVE: u32 = 3; // base + addend
+
 /// Program section header
 #[derive(Debug)]
 #[repr(C)]
@@ -260,3 +509,41 @@ struct ProgHeader {
     memsz: u64,
     align: u64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(flags: u32) -> ProgHeader {
+        ProgHeader {
+            typ: PROG_LOAD,
+            flags,
+            off: 0,
+            vaddr: 0,
+            paddr: 0,
+            filesz: 0,
+            memsz: PAGESIZE as u64,
+            align: PAGESIZE as u64,
+        }
+    }
+
+    #[test_case]
+    fn segment_permissions() {
+        let text = segment(PF_R | PF_X);
+        assert_eq!(Ok(PteFlag::READ | PteFlag::EXEC), segment_prot(&text));
+        assert_eq!(Ok(PteFlag::READ), segment_prot(&segment(PF_R)));
+        // writable implies readable.
+        let data = segment(PF_W);
+        assert_eq!(Ok(PteFlag::READ | PteFlag::WRITE), segment_prot(&data));
+    }
+
+    #[test_case]
+    fn writable_and_executable_segment() {
+        let rwx = segment(PF_R | PF_W | PF_X);
+        assert_eq!(
+            Err("exec: segment is both writable and executable"),
+            segment_prot(&rwx)
+        );
+        assert!(segment_prot(&segment(PF_W | PF_X)).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! Fast user-space locking.
+//!
+//! A futex is a 32-bit word in user memory. User space does the uncontended work with atomic
+//! instructions on the word, and only calls into the kernel to sleep until the word changes or to
+//! wake up the sleepers.
+//!
+//! Sleepers are keyed on the physical address of the word, so threads sharing an address space
+//! and processes sharing the page find each other whatever virtual address they use. The address
+//! is the channel passed to `Proc::sleep()` and `ProcessTable::wakeup_n()`.
+//!
+//! `FUTEX` serializes the value check in `wait()` against `wake()`: a waker that changes the word
+//! and then calls `wake()` either comes before the check, which sees the new value, or finds the
+//! waiter asleep.
+//!
+//! The key each process sleeps on is recorded in `KEYS`, so that a page with sleepers is not
+//! swapped out, which would move the word under them. It is set with the address space held, as
+//! it is when the swapper looks.
+
+use core::{
+    mem, ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use array_macro::array;
+
+use crate::{
+    page_table::{align_down, PteFlag},
+    param::PAGESIZE,
+    process::{NPROC, PROCESS_TABLE},
+    spinlock::SpinLock,
+    trap,
+};
+
+use super::{signal, Proc, EINTR};
+
+static FUTEX: SpinLock<()> = SpinLock::new((), "futex");
+
+// the key of each process sleeping in `wait()`, or 0.
+static KEYS: [AtomicUsize; NPROC] = array![_ => AtomicUsize::new(0); NPROC];
+
+/// the physical address of the futex word at `addr` in the address space of `p`.
+fn key(p: &mut Proc, addr: usize) -> Result<usize, &'static str> {
+    if addr % mem::size_of::<u32>() != 0 {
+        return Err("futex: address must be 4-byte aligned");
+    }
+    let va = align_down(addr, PAGESIZE);
+    let pdata = p.data.get_mut();
+    // the word is going to be stored to anyway, and a page shared copy-on-write would move under
+    // the sleepers when it is.
+    pdata.fault_in(addr, mem::size_of::<u32>(), PteFlag::WRITE)?;
+    let mm = pdata.mm();
+    let pa = mm.page_table.walk_addr(va)?;
+    drop(mm);
+    Ok(pa + (addr - va))
+}
+
+/// sleeps until woken by `wake()` if the word at `addr` still holds `expected`.
+/// gives up after `timeout` clock ticks, unless `timeout` is 0, and with EINTR if the process is
+/// killed or has a signal pending, before or after it sleeps.
+pub fn wait(
+    p: &mut Proc,
+    addr: usize,
+    expected: u32,
+    timeout: usize,
+) -> Result<usize, &'static str> {
+    let key = key(p, addr)?;
+    let deadline = if timeout == 0 {
+        0
+    } else {
+        trap::ticks() + timeout
+    };
+
+    // the page may have been swapped out since the key was taken, and the address space is held
+    // until the key is recorded so that it is not meanwhile.
+    let guard = FUTEX.lock();
+    let mm = p.data.get_mut().mm();
+    let va = align_down(addr, PAGESIZE);
+    let moved = mm.page_table.lookup(va).map(|(pa, _)| pa + (addr - va)) != Some(key);
+    if moved || unsafe { ptr::read_volatile(key as *const u32) } != expected {
+        drop(mm);
+        drop(guard);
+        return Err("futex_wait: value has changed");
+    }
+    if is_interrupted(p) {
+        drop(mm);
+        drop(guard);
+        return Err(EINTR);
+    }
+    KEYS[p.index].store(key, Ordering::Relaxed);
+    drop(mm);
+
+    p.inner.lock().timeout = deadline;
+    let guard = p.sleep(key, guard);
+    drop(guard);
+    KEYS[p.index].store(0, Ordering::Relaxed);
+    p.inner.lock().timeout = 0;
+
+    if is_interrupted(p) {
+        return Err(EINTR);
+    }
+    if deadline != 0 && trap::ticks() >= deadline {
+        return Err("futex_wait: timed out");
+    }
+    Ok(0)
+}
+
+fn is_interrupted(p: &Proc) -> bool {
+    let guard = p.inner.lock();
+    let interrupted = guard.killed || signal::has_pending(&guard);
+    drop(guard);
+    interrupted
+}
+
+/// wakes up at most `n` of the processes sleeping on the word at `addr`.
+/// returns how many were woken.
+pub fn wake(p: &mut Proc, addr: usize, n: usize) -> Result<usize, &'static str> {
+    let key = key(p, addr)?;
+
+    let guard = FUTEX.lock();
+    let woken = unsafe { PROCESS_TABLE.wakeup_n(key, n) };
+    drop(guard);
+
+    Ok(woken)
+}
+
+/// tells whether the process at `index` sleeps in `wait()`.
+pub fn is_waiting(index: usize) -> bool {
+    KEYS[index].load(Ordering::Relaxed) != 0
+}
+
+/// tells whether a process sleeps on a word of the page at `pa`. the caller holds the lock of the
+/// address space that maps the page.
+pub fn is_waited(pa: usize) -> bool {
+    KEYS.iter().any(|key| {
+        let key = key.load(Ordering::Relaxed);
+        pa <= key && key < pa + PAGESIZE
+    })
+}
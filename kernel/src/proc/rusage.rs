@@ -0,0 +1,46 @@
+//! Resource usage accounting.
+//!
+//! Every process counts the clock ticks it spends in user and kernel mode, its context switches
+//! and its page faults. The counters live in `ProcInner` and are updated under the process's lock.
+//! When a child is reaped, its usage and the usage of the children it has reaped in turn are added
+//! to its parent's `child_usage`.
+
+/// getrusage() who: the calling process.
+pub const RUSAGE_SELF: i32 = 0;
+/// getrusage() who: the children that have been waited for.
+pub const RUSAGE_CHILDREN: i32 = -1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Rusage {
+    /// clock ticks in user mode.
+    pub utime: usize,
+    /// clock ticks in kernel mode.
+    pub stime: usize,
+    /// gave up the cpu to sleep or exit.
+    pub nvcsw: usize,
+    /// preempted when the time slice ran out.
+    pub nivcsw: usize,
+    /// page faults taken in user mode.
+    pub nfault: usize,
+}
+
+impl Rusage {
+    pub const fn new() -> Self {
+        Self {
+            utime: 0,
+            stime: 0,
+            nvcsw: 0,
+            nivcsw: 0,
+            nfault: 0,
+        }
+    }
+
+    pub fn add(&mut self, other: &Rusage) {
+        self.utime += other.utime;
+        self.stime += other.stime;
+        self.nvcsw += other.nvcsw;
+        self.nivcsw += other.nivcsw;
+        self.nfault += other.nfault;
+    }
+}
@@ -0,0 +1,377 @@
+//! POSIX-style signals.
+//!
+//! A signal is posted to a process by setting a bit in its pending mask, under the process's lock.
+//! It is delivered the next time the process is about to return to user space (`user_trap_ret()`),
+//! where the kernel either takes the default action (terminate, ignore or stop) or invokes the
+//! user's handler.
+//!
+//! To invoke a handler, the kernel saves the interrupted user registers and the blocked mask in a
+//! `SigFrame` on the user stack, then redirects `epc` to the handler with the signal number in `a0`.
+//! The handler returns to the `restorer` registered with `sigaction()`, which calls `sigreturn()`
+//! to restore the saved registers.
+//!
+//! Only SIGKILL and default-terminating signals interrupt a sleeping process, by setting `killed`,
+//! but for `futex_wait()`, which any signal to handle interrupts.
+
+use core::{mem, ptr};
+
+use crate::{cpu::CPU_TABLE, sched};
+
+use super::{futex, Proc, ProcData, ProcInner, ProcState, TrapFrame};
+
+pub const NSIG: usize = 32;
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGWINCH: usize = 28;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+pub const SIG_BLOCK: i32 = 0;
+pub const SIG_UNBLOCK: i32 = 1;
+pub const SIG_SETMASK: i32 = 2;
+
+/// SIGKILL and SIGSTOP can be neither caught, blocked nor ignored.
+const UNBLOCKABLE: u32 = (1 << SIGKILL) | (1 << SIGSTOP);
+const STOP_MASK: u32 = (1 << SIGSTOP) | (1 << SIGTSTP) | (1 << SIGTTIN) | (1 << SIGTTOU);
+
+/// The layout shared with user space by `sigaction()`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SigAction {
+    pub handler: usize,
+    // signals blocked while the handler runs, in addition to the signal itself.
+    pub mask: u32,
+    pub flags: u32,
+    // where the handler returns to. it must call sigreturn().
+    pub restorer: usize,
+}
+
+impl SigAction {
+    const fn new() -> Self {
+        Self {
+            handler: SIG_DFL,
+            mask: 0,
+            flags: 0,
+            restorer: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct SigState {
+    pub pending: u32,
+    pub blocked: u32,
+    pub actions: [SigAction; NSIG],
+    pub stopped: bool,
+    // the tick at which SIGALRM is posted, or 0 if no alarm is set.
+    pub alarm: usize,
+}
+
+impl SigState {
+    pub const fn new() -> Self {
+        Self {
+            pending: 0,
+            blocked: 0,
+            actions: [SigAction::new(); NSIG],
+            stopped: false,
+            alarm: 0,
+        }
+    }
+
+    /// a child created by fork() inherits the handlers and the blocked mask, but no pending signals.
+    pub fn inherit(&mut self, parent: &SigState) {
+        *self = Self::new();
+        self.actions = parent.actions;
+        self.blocked = parent.blocked;
+    }
+
+    /// exec() discards the old program's handlers. ignored signals stay ignored.
+    pub fn reset_on_exec(&mut self) {
+        for action in self.actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SigAction::new();
+            }
+        }
+    }
+
+    /// the channel a stopped process sleeps on until SIGCONT.
+    fn stop_chan(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+#[derive(PartialEq)]
+enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+fn default_action(sig: usize) -> DefaultAction {
+    match sig {
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGCONT => DefaultAction::Continue,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+pub fn is_valid(sig: usize) -> bool {
+    sig > 0 && sig < NSIG
+}
+
+/// tells whether `sig` terminates the process whose locked state is `inner`, now or once it is
+/// unblocked.
+pub fn is_fatal(inner: &ProcInner, sig: usize) -> bool {
+    sig == SIGKILL
+        || (inner.sig.actions[sig].handler == SIG_DFL
+            && default_action(sig) == DefaultAction::Terminate)
+}
+
+/// tells whether the process whose locked state is `inner` has a signal pending that it is not
+/// blocking nor ignoring, which a sleep that can be given up is interrupted by.
+pub fn has_pending(inner: &ProcInner) -> bool {
+    let deliverable = inner.sig.pending & !inner.sig.blocked;
+    (1..NSIG).any(|sig| {
+        let handler = inner.sig.actions[sig].handler;
+        deliverable & (1 << sig) != 0
+            && handler != SIG_IGN
+            && (handler != SIG_DFL || default_action(sig) != DefaultAction::Ignore)
+    })
+}
+
+/// the signal that reports an exception caused by user code.
+pub fn from_scause(scause: usize) -> usize {
+    match scause {
+        0 | 4 | 6 => SIGBUS, // misaligned instruction, load, store
+        2 => SIGILL,
+        3 => SIGTRAP,
+        _ => SIGSEGV, // access faults and page faults
+    }
+}
+
+/// post `sig` to the process `p`, whose locked state is `inner`.
+/// must be called with the process's lock held.
+pub fn post(p: &Proc, inner: &mut ProcInner, sig: usize) {
+    let bit = 1u32 << sig;
+    match sig {
+        SIGKILL => {
+            inner.killed = true;
+        }
+        SIGCONT => {
+            inner.sig.pending &= !STOP_MASK;
+            inner.sig.stopped = false;
+        }
+        _ if bit & STOP_MASK > 0 => {
+            inner.sig.pending &= !(1 << SIGCONT);
+        }
+        _ => {}
+    }
+    if sig != SIGKILL {
+        inner.sig.pending |= bit;
+    }
+
+    // a signal that will terminate the process must also end any sleep it is in.
+    if inner.sig.blocked & bit == 0
+        && inner.sig.actions[sig].handler == SIG_DFL
+        && default_action(sig) == DefaultAction::Terminate
+    {
+        inner.killed = true;
+    }
+
+    // a futex wait gives up on any signal to handle, the other sleeps only once killed.
+    if inner.state == ProcState::Sleeping
+        && (inner.killed
+            || (sig == SIGCONT && inner.chan == inner.sig.stop_chan())
+            || (futex::is_waiting(p.index) && has_pending(inner)))
+    {
+        sched::make_runnable(p.index, inner);
+    }
+}
+
+/// post a signal for an exception the process caused itself.
+/// it can't be blocked or ignored, because returning to the faulting instruction would just
+/// fault again.
+pub fn force(p: &Proc, inner: &mut ProcInner, sig: usize) {
+    let bit = 1u32 << sig;
+    inner.sig.blocked &= !bit;
+    if inner.sig.actions[sig].handler == SIG_IGN {
+        inner.sig.actions[sig] = SigAction::new();
+    }
+    post(p, inner, sig);
+}
+
+/// take the action for the pending signals of the current process.
+/// called on the way back to user space. at most one handler is set up per return, and further
+/// pending signals are delivered after it calls sigreturn().
+pub fn deliver(p: &mut Proc) {
+    let mut guard = p.inner.lock();
+    loop {
+        let deliverable = guard.sig.pending & !guard.sig.blocked;
+        if deliverable == 0 || guard.killed {
+            break;
+        }
+        let sig = deliverable.trailing_zeros() as usize;
+        guard.sig.pending &= !(1 << sig);
+
+        let action = guard.sig.actions[sig];
+        match action.handler {
+            SIG_IGN => {}
+            SIG_DFL => match default_action(sig) {
+                DefaultAction::Terminate => {
+                    guard.killed = true;
+                }
+                DefaultAction::Ignore | DefaultAction::Continue => {}
+                DefaultAction::Stop => {
+                    guard.sig.stopped = true;
+                    while guard.sig.stopped && !guard.killed {
+                        guard.chan = guard.sig.stop_chan();
+                        guard.state = ProcState::Sleeping;
+                        unsafe {
+                            let cpu = CPU_TABLE.my_cpu_mut();
+                            guard = cpu.sched(guard, &mut p.data.get_mut().context);
+                        }
+                        guard.chan = 0;
+                    }
+                }
+            },
+            handler => {
+                let saved_blocked = guard.sig.blocked;
+                guard.sig.blocked |= (action.mask | (1 << sig)) & !UNBLOCKABLE;
+                drop(guard);
+
+                if setup_frame(p.data.get_mut(), sig, handler, action.restorer, saved_blocked)
+                    .is_err()
+                {
+                    // the user stack is unusable, there is no way to run the handler.
+                    p.inner.lock().killed = true;
+                }
+                return;
+            }
+        }
+    }
+    drop(guard);
+}
+
+/// saved on the user stack while a handler runs.
+#[repr(C)]
+struct SigFrame {
+    tf: TrapFrame,
+    blocked: u32,
+}
+
+fn setup_frame(
+    pdata: &mut ProcData,
+    sig: usize,
+    handler: usize,
+    restorer: usize,
+    blocked: u32,
+) -> Result<(), &'static str> {
+    let tf = unsafe { pdata.trapframe.as_mut().unwrap() };
+
+    let mut frame = SigFrame {
+        tf: unsafe { ptr::read(tf) },
+        blocked,
+    };
+    // the kernel half of the trapframe is none of user space's business.
+    // user_trap_ret() sets it up again anyway.
+    frame.tf.kernel_satp = 0;
+    frame.tf.kernel_sp = 0;
+    frame.tf.kernel_trap = 0;
+    frame.tf.kernel_hartid = 0;
+
+    let mut sp = tf.sp - mem::size_of::<SigFrame>();
+    sp -= sp % 16; // riscv sp must be 16-byte aligned.
+    pdata.copy_out(
+        sp,
+        &frame as *const SigFrame as *const u8,
+        mem::size_of::<SigFrame>(),
+    )?;
+
+    tf.epc = handler;
+    tf.a0 = sig;
+    tf.ra = restorer;
+    tf.sp = sp;
+
+    Ok(())
+}
+
+/// restore the registers and the blocked mask saved by setup_frame().
+/// returns the interrupted `a0`, since the syscall return value overwrites it.
+pub fn sigreturn(p: &mut Proc) -> Result<usize, &'static str> {
+    let pdata = p.data.get_mut();
+    let tf = unsafe { pdata.trapframe.as_mut().unwrap() };
+
+    // the handler has returned, so sp is back where setup_frame() left it.
+    let mut frame = mem::MaybeUninit::<SigFrame>::uninit();
+    pdata.copy_in(
+        frame.as_mut_ptr() as *mut u8,
+        tf.sp,
+        mem::size_of::<SigFrame>(),
+    )?;
+    let frame = unsafe { frame.assume_init() };
+
+    unsafe { ptr::copy_nonoverlapping(&frame.tf, tf as *mut TrapFrame, 1) };
+    p.inner.lock().sig.blocked = frame.blocked & !UNBLOCKABLE;
+
+    Ok(tf.a0)
+}
+
+/// update the blocked mask as sigprocmask() does. returns the old mask.
+pub fn set_blocked(inner: &mut ProcInner, how: i32, set: u32) -> Result<u32, &'static str> {
+    let old = inner.sig.blocked;
+    inner.sig.blocked = match how {
+        SIG_BLOCK => old | set,
+        SIG_UNBLOCK => old & !set,
+        SIG_SETMASK => set,
+        _ => return Err("sigprocmask: invalid how"),
+    } & !UNBLOCKABLE;
+    Ok(old)
+}
+
+/// install a new action as sigaction() does. returns the old action.
+pub fn set_action(
+    inner: &mut ProcInner,
+    sig: usize,
+    action: SigAction,
+) -> Result<SigAction, &'static str> {
+    if !is_valid(sig) {
+        return Err("sigaction: invalid signal");
+    }
+    if (1 << sig) & UNBLOCKABLE > 0 {
+        return Err("sigaction: SIGKILL and SIGSTOP cannot be caught or ignored");
+    }
+
+    let old = inner.sig.actions[sig];
+    inner.sig.actions[sig] = action;
+    if action.handler == SIG_IGN
+        || (action.handler == SIG_DFL && default_action(sig) == DefaultAction::Ignore)
+    {
+        // discard what is pending, it would be ignored anyway.
+        inner.sig.pending &= !(1 << sig);
+    }
+    Ok(old)
+}
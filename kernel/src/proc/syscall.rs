@@ -4,16 +4,24 @@ use alloc::{boxed::Box, collections::BTreeMap, format, string::ToString};
 use array_macro::array;
 
 use crate::{
+    cpu::CpuTable,
     file::File,
     fs::{FileStat, InodeType, INODE_TABLE},
+    kalloc::{self, MemInfo, MEMINFO_SITES},
     log::LOG,
     net::SockAddr,
-    page_table::{align_down, PteFlag},
+    page_table::{align_up, PteFlag},
     param::PAGESIZE,
     process::PROCESS_TABLE,
+    trap::{self, TimeSpec, CLOCK_MONOTONIC, CLOCK_REALTIME},
 };
 
-use super::{elf, MapFlag, Proc, MAXARG, MAXARGLEN, VMA};
+use super::{
+    elf, futex,
+    rusage::{Rusage, RUSAGE_CHILDREN, RUSAGE_SELF},
+    signal::{self, SigAction},
+    MapFlag, Proc, MAXARG, MAXARGLEN,
+};
 
 type SysResult = Result<usize, &'static str>;
 
@@ -59,10 +67,9 @@ pub trait Syscall {
     /// Read n bytes into buf; returns number read; or 0 if end of file.
     fn sys_read(&mut self) -> SysResult; // 5
 
-    /// TODO
-    /// int kill(int pid)
-    /// Terminate process PID. Returns 0, or -1 for error.
-    // 6
+    /// int kill(int pid, int sig)
+    /// Send signal sig to process PID. Returns 0, or -1 for error.
+    fn sys_kill(&mut self) -> SysResult; // 6
 
     /// int exec(char *file, char *argv[])
     /// Load a file and execute it with arguments; only returns if error.
@@ -80,24 +87,21 @@ pub trait Syscall {
     /// Return a new file descriptor referring to the same file as fd.
     fn sys_dup(&mut self) -> SysResult; // 10
 
-    /// TODO
     /// int getpid()
     /// Return the current process's PID.
-    // 11
+    fn sys_getpid(&mut self) -> SysResult; // 11
 
     /// char *sbrk(int n)
     /// Grow process's memory by n bytes. Returns start of new memory.
     fn sys_sbrk(&mut self) -> SysResult; // 12
 
-    /// TODO
     /// int sleep(int n)
     /// Pause for n clock ticks.
-    // 13
+    fn sys_sleep(&mut self) -> SysResult; // 13
 
-    /// TODO
     /// int uptime()
     /// Return how many clock tick interrupts have occurred since start.
-    // 14
+    fn sys_uptime(&mut self) -> SysResult; // 14
 
     /// int open(char *file, int flags)
     /// Open a file; flags indicate read/write; returns an fd(file descriptor).
@@ -157,7 +161,103 @@ pub trait Syscall {
     /// A file mapping maps a region of a file directly into the calling process's virtual memory.
     /// Once a file is mapped, its contents can be accessed by operations on the bytes in the
     /// corresponding memory region.
+    ///
+    /// ADDR is a hint, taken if the range is free. With MAP_FIXED the mapping is placed exactly
+    /// at ADDR, replacing the mappings there, and fails if it would overlap the heap.
     fn sys_mmap(&mut self) -> SysResult; // 27
+
+    /// int sigaction(int sig, const struct sigaction *act, struct sigaction *oldact)
+    /// Examine and change the action taken on delivery of signal sig.
+    fn sys_sigaction(&mut self) -> SysResult; // 32
+
+    /// int sigprocmask(int how, const sigset_t *set, sigset_t *oldset)
+    /// Examine and change the set of blocked signals.
+    fn sys_sigprocmask(&mut self) -> SysResult; // 33
+
+    /// int sigreturn()
+    /// Return from a signal handler, restoring the interrupted context. No return to the caller.
+    fn sys_sigreturn(&mut self) -> SysResult; // 34
+
+    /// int alarm(int n)
+    /// Post SIGALRM after n clock ticks, or cancel the alarm if n is 0. Returns the ticks
+    /// remaining of the previous alarm.
+    fn sys_alarm(&mut self) -> SysResult; // 35
+
+    /// int clock_gettime(int clockid, struct timespec *tp)
+    /// Retrieve the time of the clock clockid, with nanosecond resolution.
+    fn sys_clock_gettime(&mut self) -> SysResult; // 36
+
+    /// int getppid()
+    /// Return the parent's PID. Orphans are adopted by init.
+    fn sys_getppid(&mut self) -> SysResult; // 37
+
+    /// int waitpid(int pid, int *status, int options)
+    /// Wait for the child PID to exit, or any child if pid is -1; exit status in *status.
+    /// Returns the child's PID, or 0 if options has WNOHANG and the child is still running.
+    fn sys_waitpid(&mut self) -> SysResult; // 38
+
+    /// int setpriority(int pid, int prio)
+    /// Set the scheduling priority of process PID. 0 is the highest priority.
+    fn sys_setpriority(&mut self) -> SysResult; // 39
+
+    /// int getpriority(int pid)
+    /// Return the scheduling priority of process PID.
+    fn sys_getpriority(&mut self) -> SysResult; // 40
+
+    /// int sched_setaffinity(int pid, int mask)
+    /// Restrict process PID to the harts in the bit mask.
+    fn sys_sched_setaffinity(&mut self) -> SysResult; // 41
+
+    /// int sched_getaffinity(int pid)
+    /// Return the bit mask of the harts process PID may run on.
+    fn sys_sched_getaffinity(&mut self) -> SysResult; // 42
+
+    /// int clone(int (*fn)(void *), void *arg, void *stack, void (*ret)(int))
+    /// Create a thread sharing the address space and the open files, return its PID.
+    /// The thread calls fn(arg) on the given stack, then returns into ret.
+    fn sys_clone(&mut self) -> SysResult; // 43
+
+    /// int join(int tid, int *status)
+    /// Wait for the thread TID to exit, or any thread if TID is -1, return its PID.
+    fn sys_join(&mut self) -> SysResult; // 44
+
+    /// int futex_wait(int *addr, int expected, int timeout)
+    /// Sleep until futex_wake() on ADDR if *ADDR is EXPECTED, for at most TIMEOUT ticks
+    /// unless TIMEOUT is 0. A signal to handle ends the sleep with EINTR.
+    fn sys_futex_wait(&mut self) -> SysResult; // 45
+
+    /// int futex_wake(int *addr, int n)
+    /// Wake up at most N processes waiting on ADDR, return how many were woken.
+    fn sys_futex_wake(&mut self) -> SysResult; // 46
+
+    /// int getrusage(int who, struct rusage *usage)
+    /// Report the resources used by the calling process, or by its reaped children if WHO is
+    /// RUSAGE_CHILDREN.
+    fn sys_getrusage(&mut self) -> SysResult; // 47
+
+    /// int munmap(void *addr, size_t length)
+    /// Remove the mappings in the range. Dirty pages of MAP_SHARED file mappings are written back
+    /// to the file.
+    fn sys_munmap(&mut self) -> SysResult; // 48
+
+    /// int msync(void *addr, size_t length, int flags)
+    /// Write the dirty pages of MAP_SHARED file mappings in the range back to the file.
+    fn sys_msync(&mut self) -> SysResult; // 49
+
+    /// int mprotect(void *addr, size_t length, int prot)
+    /// Change the protection of the mmap-ed pages in [addr, addr + length) to PROT. An access
+    /// that is not permitted afterwards kills the process with SIGSEGV.
+    fn sys_mprotect(&mut self) -> SysResult; // 50
+
+    /// int meminfo(struct meminfo *info, int flags)
+    /// Report the memory usage of the kernel. With MEMINFO_SITES, also print the live tagged
+    /// objects on the console by the site that created them, if the kernel records them.
+    fn sys_meminfo(&mut self) -> SysResult; // 51
+
+    /// int setsid()
+    /// Make the calling process the leader of a new session, which ^C on the console spares.
+    /// Return the session id, the pid of the process.
+    fn sys_setsid(&mut self) -> SysResult; // 52
 }
 
 impl Syscall for Proc {
@@ -375,17 +475,17 @@ impl Syscall for Proc {
         // array of two integers.
         let addr = self.arg_raw(0)?;
 
-        let (rf, wf) = File::alloc_pipe();
+        let (rf, wf) = File::alloc_pipe()?;
 
         let rfd = self
             .alloc_fd()
             .or_else(|_| Err("sys_pipe: cannot allocate fd to read the pipe"))?;
-        self.data.get_mut().o_files[rfd].replace(rf);
+        self.data.get_mut().files().o_files[rfd].replace(rf);
 
         let wfd = self
             .alloc_fd()
             .or_else(|_| Err("sys_pipe: cannot allocate fd to write the pipe"))?;
-        self.data.get_mut().o_files[wfd].replace(wf);
+        self.data.get_mut().files().o_files[wfd].replace(wf);
 
         let pdata = self.data.get_mut();
 
@@ -409,12 +509,26 @@ impl Syscall for Proc {
         let addr = self.arg_raw(1)?;
         let n = self.arg_i32(2)?;
 
-        match self.data.get_mut().o_files[fd as usize].as_ref() {
+        match self.data.get_mut().file(fd as usize) {
             None => Err("sys_read"),
             Some(f) => f.read(addr, n as usize),
         }
     }
 
+    /// 6
+    fn sys_kill(&mut self) -> SysResult {
+        let pid = self.arg_i32(0)?;
+        if pid < 0 {
+            return Err("sys_kill: pid must be greater than or equal to 0");
+        }
+        let sig = self.arg_i32(1)? as usize;
+        if sig != 0 && !signal::is_valid(sig) {
+            return Err("sys_kill: invalid signal");
+        }
+        unsafe { PROCESS_TABLE.kill(pid as usize, sig) }?;
+        Ok(0)
+    }
+
     /// 7
     fn sys_exec(&mut self) -> SysResult {
         let mut path: [u8; 128] = unsafe { mem::MaybeUninit::uninit().assume_init() };
@@ -440,7 +554,9 @@ impl Syscall for Proc {
             self.fetch_str(uarg, argv[i].as_deref_mut().unwrap())?;
         }
 
-        elf::load(self.data.get_mut(), &path, &argv)
+        let argc = elf::load(self.data.get_mut(), &path, &argv)?;
+        self.inner.lock().sig.reset_on_exec();
+        Ok(argc)
     }
 
     /// 8
@@ -450,8 +566,10 @@ impl Syscall for Proc {
         let addr = self.arg_raw(1)?;
 
         // lookup the open file.
-        let f = self.data.get_mut().o_files[fd as usize]
-            .as_ref()
+        let f = self
+            .data
+            .get_mut()
+            .file(fd as usize)
             .ok_or_else(|| "file not found")?;
 
         let mut st = FileStat::uninit();
@@ -490,7 +608,7 @@ impl Syscall for Proc {
         }
 
         drop(idata);
-        let old = self.data.get_mut().cwd.replace(inode).unwrap();
+        let old = self.data.get_mut().files().cwd.replace(inode).unwrap();
         drop(old);
         LOG.end_op();
 
@@ -504,36 +622,64 @@ impl Syscall for Proc {
             .alloc_fd()
             .or_else(|_| Err("sys_dup: cannot allocate new fd"))?;
 
-        let old_f = self.data.get_mut().o_files[old_fd as usize]
-            .as_ref()
-            .unwrap();
-        let new_f = old_f.clone();
-        self.data.get_mut().o_files[new_fd].replace(new_f);
+        let mut files = self.data.get_mut().files();
+        let new_f = files.o_files[old_fd as usize].clone().unwrap();
+        files.o_files[new_fd].replace(new_f);
+        drop(files);
 
         Ok(new_fd)
     }
 
+    /// 11
+    fn sys_getpid(&mut self) -> SysResult {
+        Ok(self.inner.lock().pid)
+    }
+
     /// 12
+    /// growing only raises the size. the pages are allocated when they are first touched.
     fn sys_sbrk(&mut self) -> SysResult {
         let n = self.arg_i32(0)?;
-        let pdata = self.data.get_mut();
-        let old_sz = pdata.sz; // Save the old size
+        let mut mm = self.data.get_mut().mm();
+        let old_sz = mm.sz; // Save the old size
         if n > 0 {
-            pdata.sz = pdata
-                .page_table
-                .as_mut()
-                .unwrap()
-                .uvm_alloc(old_sz, old_sz + n as usize)?;
+            let new_sz = old_sz + n as usize;
+            if new_sz > mm.mmap_base() {
+                return Err("sys_sbrk: overlaps the mmap-ed regions");
+            }
+            mm.sz = new_sz;
         } else if n < 0 {
-            pdata.sz = pdata
-                .page_table
-                .as_mut()
-                .unwrap()
-                .uvm_dealloc(old_sz, old_sz + n as usize)?;
+            let new_sz = old_sz
+                .checked_sub(n.unsigned_abs() as usize)
+                .filter(|&new_sz| new_sz >= mm.heap_start)
+                .ok_or("sys_sbrk: shrinks below the heap")?;
+            if mm.users > 1 {
+                // the other threads must have let go of the pages before they are freed.
+                let start = align_up(new_sz, PAGESIZE);
+                let n = (align_up(old_sz, PAGESIZE) - start) / PAGESIZE;
+                mm.page_table.uvm_protect(start, n, PteFlag::empty(), false);
+                mm.page_table.flush_tlb();
+            }
+            mm.sz = mm.page_table.uvm_dealloc(old_sz, new_sz)?;
         }
+        drop(mm);
         Ok(old_sz) // Return the old size (start address of the new memory)
     }
 
+    /// 13
+    fn sys_sleep(&mut self) -> SysResult {
+        let n = self.arg_i32(0)?;
+        if n < 0 {
+            return Err("sys_sleep: ticks must be greater than or equal to 0");
+        }
+        trap::sleep_ticks(self, n as usize)?;
+        Ok(0)
+    }
+
+    /// 14
+    fn sys_uptime(&mut self) -> SysResult {
+        Ok(trap::ticks())
+    }
+
     /// 15
     fn sys_open(&mut self) -> SysResult {
         let mut path: [u8; 128] = unsafe { mem::MaybeUninit::uninit().assume_init() };
@@ -541,11 +687,11 @@ impl Syscall for Proc {
         let path = &path[0..=null_pos];
         let o_mode = self.arg_i32(1)?;
 
-        let f = File::open(&path, o_mode).ok_or_else(|| "sys_open: cannot open file")?;
+        let f = File::open(&path, o_mode)?;
         let fd = self
             .alloc_fd()
             .or_else(|_| Err("sys_open: cannot allocate fd"))?;
-        self.data.get_mut().o_files[fd].replace(f);
+        self.data.get_mut().files().o_files[fd].replace(f);
 
         Ok(fd)
     }
@@ -556,7 +702,7 @@ impl Syscall for Proc {
         let addr = self.arg_raw(1)?;
         let n = self.arg_i32(2)?;
 
-        match self.data.get_mut().o_files[fd as usize].as_ref() {
+        match self.data.get_mut().file(fd as usize) {
             None => Err("sys_write"),
             Some(f) => f.write(addr, n as usize),
         }
@@ -628,7 +774,9 @@ impl Syscall for Proc {
     /// 21
     fn sys_close(&mut self) -> SysResult {
         let fd = self.arg_fd(0)?;
-        drop(self.data.get_mut().o_files[fd as usize].take());
+        // closing may sleep, so not with the table locked.
+        let f = self.data.get_mut().files().o_files[fd as usize].take();
+        drop(f);
         Ok(0)
     }
 
@@ -644,7 +792,7 @@ impl Syscall for Proc {
 
         let f = File::alloc_socket(domain, typ, protocol)?;
 
-        self.data.get_mut().o_files[fd].replace(f);
+        self.data.get_mut().files().o_files[fd].replace(f);
 
         Ok(fd)
     }
@@ -662,8 +810,10 @@ impl Syscall for Proc {
             .get_mut()
             .copy_in(&mut sock_addr as *mut _ as *mut u8, addr, addr_len)?;
 
-        let f = self.data.get_mut().o_files[fd as usize]
-            .as_ref()
+        let f = self
+            .data
+            .get_mut()
+            .file(fd as usize)
             .ok_or("sys_bind: file not found")?;
         let soc = f.get_socket().ok_or("sys_bind: file type must be socket")?;
         soc.bind(&sock_addr)?;
@@ -694,8 +844,10 @@ impl Syscall for Proc {
             .get_mut()
             .copy_in(&mut sock_addr as *mut _ as *mut u8, addr, addr_len)?;
 
-        let f = self.data.get_mut().o_files[fd as usize]
-            .as_ref()
+        let f = self
+            .data
+            .get_mut()
+            .file(fd as usize)
             .ok_or("sys_connect: file not found")?;
         let soc = f
             .get_socket()
@@ -709,47 +861,333 @@ impl Syscall for Proc {
     /// This syscall func does not allocate physical memory or read the file, just add new VMA
     /// entry. Instead, do that in page fault handler.
     fn sys_mmap(&mut self) -> SysResult {
-        // args
-        // arg 0 `addr`
+        let addr = self.arg_raw(0)?;
         let size = self.arg_i32(1)? as usize;
         let prot = self.arg_i32(2)? as usize;
-        let prot = PteFlag::from_bits(prot).ok_or("sys_mmap: cannot parse prot")?;
+        let prot = PteFlag::from_bits(prot)
+            .filter(|prot| (PteFlag::READ | PteFlag::WRITE | PteFlag::EXEC).contains(*prot))
+            .ok_or("sys_mmap: cannot parse prot")?;
         let flags = self.arg_i32(3)? as usize;
-        let flags = MapFlag::from_bits(flags).ok_or("sys_mmap: cannot parse flags")?;
+        // GROWSDOWN marks the stack, which is the kernel's to map.
+        let flags = MapFlag::from_bits(flags)
+            .filter(|flags| {
+                (MapFlag::SHARED | MapFlag::PRIVATE | MapFlag::ANONYMOUS | MapFlag::FIXED)
+                    .contains(*flags)
+            })
+            .ok_or("sys_mmap: cannot parse flags")?;
         let fd = self.arg_i32(4)?;
+        let offset = self.arg_raw(5)?;
+        if size == 0 {
+            return Err("sys_mmap: length must be greater than 0");
+        }
+        if offset % PAGESIZE != 0 {
+            return Err("sys_mmap: offset not aligned");
+        }
 
         let pdata = unsafe { &mut *self.data.get() };
 
-        if fd != -1 {
-            let f = pdata.o_files[fd as usize]
-                .as_ref()
-                .ok_or("sys_mmap: file not found")?;
+        // the VMA holds on to the file, so the mapping outlives the file descriptor.
+        let file = if fd != -1 {
+            let f = pdata.file(fd as usize).ok_or("sys_mmap: file not found")?;
 
             if (PteFlag::WRITE.bits() & prot.bits() > 0) && !f.writable {
                 return Err("sys_mmap: file is read-only, but mmap has write permission and flag");
             }
+            Some(f)
+        } else {
+            None
+        };
+
+        pdata.mmap(addr, size, prot, flags, file, offset)
+    }
+
+    /// 32
+    fn sys_sigaction(&mut self) -> SysResult {
+        let sig = self.arg_i32(0)? as usize;
+        let act_addr = self.arg_raw(1)?;
+        let old_addr = self.arg_raw(2)?;
+        if !signal::is_valid(sig) {
+            return Err("sys_sigaction: invalid signal");
         }
 
-        let addr_end = pdata.cur_max;
-        let addr_start = align_down(addr_end - size, PAGESIZE);
+        let mut old = self.inner.lock().sig.actions[sig];
+        if act_addr != 0 {
+            let mut act = mem::MaybeUninit::<SigAction>::uninit();
+            self.data.get_mut().copy_in(
+                act.as_mut_ptr() as *mut u8,
+                act_addr,
+                mem::size_of::<SigAction>(),
+            )?;
+            let act = unsafe { act.assume_init() };
+            old = signal::set_action(&mut self.inner.lock(), sig, act)?;
+        }
 
-        pdata
-            .vm_area
-            .iter_mut()
-            .find(|vm| {
-                return vm.is_none();
-            })
-            .ok_or("cannot find unused vma")?
-            .replace(VMA {
-                addr_start,
-                addr_end,
-                size,
-                prot,
-                flags,
-                fd,
-            });
-        pdata.cur_max = addr_start;
-
-        return Ok(addr_start);
+        if old_addr != 0 {
+            self.data.get_mut().copy_out(
+                old_addr,
+                &old as *const SigAction as *const u8,
+                mem::size_of::<SigAction>(),
+            )?;
+        }
+
+        Ok(0)
+    }
+
+    /// 33
+    fn sys_sigprocmask(&mut self) -> SysResult {
+        let how = self.arg_i32(0)?;
+        let set_addr = self.arg_raw(1)?;
+        let old_addr = self.arg_raw(2)?;
+
+        let mut old = self.inner.lock().sig.blocked;
+        if set_addr != 0 {
+            let mut set = 0u32;
+            self.data.get_mut().copy_in(
+                &mut set as *mut u32 as *mut u8,
+                set_addr,
+                mem::size_of::<u32>(),
+            )?;
+            old = signal::set_blocked(&mut self.inner.lock(), how, set)?;
+        }
+
+        if old_addr != 0 {
+            self.data.get_mut().copy_out(
+                old_addr,
+                &old as *const u32 as *const u8,
+                mem::size_of::<u32>(),
+            )?;
+        }
+
+        Ok(0)
+    }
+
+    /// 34
+    fn sys_sigreturn(&mut self) -> SysResult {
+        signal::sigreturn(self)
+    }
+
+    /// 35
+    fn sys_alarm(&mut self) -> SysResult {
+        let n = self.arg_i32(0)?;
+        if n < 0 {
+            return Err("sys_alarm: ticks must be greater than or equal to 0");
+        }
+
+        let now = trap::ticks();
+        let mut guard = self.inner.lock();
+        let remaining = guard.sig.alarm.saturating_sub(now);
+        guard.sig.alarm = if n == 0 { 0 } else { now + n as usize };
+        drop(guard);
+
+        Ok(remaining)
+    }
+
+    /// 36
+    fn sys_clock_gettime(&mut self) -> SysResult {
+        let clockid = self.arg_i32(0)?;
+        let addr = self.arg_raw(1)?;
+        if clockid != CLOCK_REALTIME && clockid != CLOCK_MONOTONIC {
+            return Err("sys_clock_gettime: unsupported clock");
+        }
+
+        let ts = TimeSpec::now();
+        self.data.get_mut().copy_out(
+            addr,
+            &ts as *const TimeSpec as *const u8,
+            mem::size_of::<TimeSpec>(),
+        )?;
+
+        Ok(0)
+    }
+
+    /// 37
+    fn sys_getppid(&mut self) -> SysResult {
+        Ok(unsafe { PROCESS_TABLE.parent_pid(self) })
+    }
+
+    /// 38
+    fn sys_waitpid(&mut self) -> SysResult {
+        let pid = self.arg_i32(0)?;
+        let addr = self.arg_raw(1)?;
+        let options = self.arg_i32(2)?;
+        unsafe { PROCESS_TABLE.waitpid(self, pid as isize, addr, options) }
+    }
+
+    /// 39
+    fn sys_setpriority(&mut self) -> SysResult {
+        let pid = self.arg_i32(0)?;
+        let prio = self.arg_i32(1)?;
+        if pid < 0 || prio < 0 {
+            return Err("sys_setpriority: pid and priority must be greater than or equal to 0");
+        }
+        unsafe { PROCESS_TABLE.set_priority(pid as usize, prio as usize) }?;
+        Ok(0)
+    }
+
+    /// 40
+    fn sys_getpriority(&mut self) -> SysResult {
+        let pid = self.arg_i32(0)?;
+        if pid < 0 {
+            return Err("sys_getpriority: pid must be greater than or equal to 0");
+        }
+        unsafe { PROCESS_TABLE.priority(pid as usize) }
+    }
+
+    /// 41
+    fn sys_sched_setaffinity(&mut self) -> SysResult {
+        let pid = self.arg_i32(0)?;
+        if pid < 0 {
+            return Err("sys_sched_setaffinity: pid must be greater than or equal to 0");
+        }
+        let mask = self.arg_raw(1)?;
+        unsafe { PROCESS_TABLE.set_affinity(pid as usize, mask) }?;
+
+        // move off this hart at once if it is no longer allowed.
+        let guard = self.inner.lock();
+        let migrate = guard.pid == pid as usize && guard.affinity & (1 << CpuTable::cpu_id()) == 0;
+        drop(guard);
+        if migrate {
+            unsafe { self.yield_process() };
+        }
+
+        Ok(0)
+    }
+
+    /// 42
+    fn sys_sched_getaffinity(&mut self) -> SysResult {
+        let pid = self.arg_i32(0)?;
+        if pid < 0 {
+            return Err("sys_sched_getaffinity: pid must be greater than or equal to 0");
+        }
+        unsafe { PROCESS_TABLE.affinity(pid as usize) }
+    }
+
+    /// 43
+    fn sys_clone(&mut self) -> SysResult {
+        let entry = self.arg_raw(0)?;
+        let arg = self.arg_raw(1)?;
+        let stack = self.arg_raw(2)?;
+        let ret = self.arg_raw(3)?;
+        if stack % 16 != 0 {
+            return Err("sys_clone: stack must be 16-byte aligned");
+        }
+        self.clone(entry, arg, stack, ret)
+    }
+
+    /// 44
+    fn sys_join(&mut self) -> SysResult {
+        let tid = self.arg_i32(0)?;
+        let addr = self.arg_raw(1)?;
+        unsafe { PROCESS_TABLE.join(self, tid as isize, addr) }
+    }
+
+    /// 45
+    fn sys_futex_wait(&mut self) -> SysResult {
+        let addr = self.arg_raw(0)?;
+        let expected = self.arg_i32(1)? as u32;
+        let timeout = self.arg_i32(2)?;
+        if timeout < 0 {
+            return Err("sys_futex_wait: timeout must be greater than or equal to 0");
+        }
+        futex::wait(self, addr, expected, timeout as usize)
+    }
+
+    /// 46
+    fn sys_futex_wake(&mut self) -> SysResult {
+        let addr = self.arg_raw(0)?;
+        let n = self.arg_i32(1)?;
+        if n < 0 {
+            return Err("sys_futex_wake: n must be greater than or equal to 0");
+        }
+        futex::wake(self, addr, n as usize)
+    }
+
+    /// 47
+    fn sys_getrusage(&mut self) -> SysResult {
+        let who = self.arg_i32(0)?;
+        let addr = self.arg_raw(1)?;
+
+        let guard = self.inner.lock();
+        let usage = match who {
+            RUSAGE_SELF => guard.usage,
+            RUSAGE_CHILDREN => guard.child_usage,
+            _ => {
+                drop(guard);
+                return Err("sys_getrusage: invalid who");
+            }
+        };
+        drop(guard);
+
+        self.data.get_mut().copy_out(
+            addr,
+            &usage as *const Rusage as *const u8,
+            mem::size_of::<Rusage>(),
+        )?;
+
+        Ok(0)
+    }
+
+    /// 48
+    fn sys_munmap(&mut self) -> SysResult {
+        let addr = self.arg_raw(0)?;
+        let len = self.arg_raw(1)?;
+        self.data.get_mut().munmap(addr, len)?;
+        Ok(0)
+    }
+
+    /// 49
+    fn sys_msync(&mut self) -> SysResult {
+        let addr = self.arg_raw(0)?;
+        let len = self.arg_raw(1)?;
+        // the pages are always written back before returning, so MS_SYNC and MS_ASYNC are the same.
+        let _flags = self.arg_i32(2)?;
+        self.data.get_mut().msync(addr, len)?;
+        Ok(0)
+    }
+
+    /// 50
+    fn sys_mprotect(&mut self) -> SysResult {
+        let addr = self.arg_raw(0)?;
+        let len = self.arg_raw(1)?;
+        let prot = self.arg_i32(2)? as usize;
+        let prot = PteFlag::from_bits(prot)
+            .filter(|prot| (PteFlag::READ | PteFlag::WRITE | PteFlag::EXEC).contains(*prot))
+            .ok_or("sys_mprotect: cannot parse prot")?;
+        self.data.get_mut().mprotect(addr, len, prot)?;
+        Ok(0)
+    }
+
+    /// 51
+    fn sys_meminfo(&mut self) -> SysResult {
+        let addr = self.arg_raw(0)?;
+        let flags = self.arg_i32(1)?;
+        if flags & !MEMINFO_SITES != 0 {
+            return Err("sys_meminfo: invalid flags");
+        }
+
+        let info = kalloc::meminfo();
+        self.data.get_mut().copy_out(
+            addr,
+            &info as *const MemInfo as *const u8,
+            mem::size_of::<MemInfo>(),
+        )?;
+        if flags & MEMINFO_SITES != 0 {
+            kalloc::dump_sites()?;
+        }
+
+        Ok(0)
+    }
+
+    /// 52
+    fn sys_setsid(&mut self) -> SysResult {
+        let mut guard = self.inner.lock();
+        if guard.sid == guard.pid {
+            drop(guard);
+            return Err("setsid: already a session leader");
+        }
+        guard.sid = guard.pid;
+        let sid = guard.sid;
+        drop(guard);
+        Ok(sid)
     }
 }
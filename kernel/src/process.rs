@@ -1,19 +1,28 @@
-use core::{mem, ptr};
+use core::{
+    mem, ptr,
+    sync::atomic::{fence, Ordering},
+};
 
 use array_macro::array;
 
 use crate::{
-    cpu::CPU_TABLE,
+    cpu::{CpuTable, CPU_TABLE},
     kvm::kvm_map,
-    log::LOG,
     page_table::{Page, PteFlag, QuadPage},
     param::{KSTACK_SIZE, PAGESIZE, TRAMPOLINE},
-    proc::{Proc, ProcState},
+    proc::{
+        signal::{self, SIGALRM, SIGCHLD},
+        Mm, Proc, ProcState,
+    },
+    sched,
     spinlock::SpinLock,
 };
 
 pub const NPROC: usize = 64;
 
+/// waitpid() option: return immediately if no child has exited.
+pub const WNOHANG: i32 = 1;
+
 pub struct ProcessTable {
     tables: [Proc; NPROC],
     pid: SpinLock<usize>,
@@ -54,25 +63,28 @@ impl ProcessTable {
         ret
     }
 
-    pub fn alloc_proc(&mut self) -> Option<&mut Proc> {
+    pub fn alloc_proc(&mut self) -> Result<&mut Proc, &'static str> {
         let pid = self.alloc_pid();
         for p in self.tables.iter_mut() {
             let mut guard = p.inner.lock();
             if guard.state == ProcState::Unused {
                 // found an used process
                 let pdata = p.data.get_mut();
-                pdata.init_page_table().ok()?;
+                if let Err(msg) = pdata.init_trapframe() {
+                    drop(guard);
+                    return Err(msg);
+                }
                 pdata.init_context();
 
                 guard.pid = pid;
                 guard.state = ProcState::Allocated;
 
                 drop(guard);
-                return Some(p);
+                return Ok(p);
             }
             drop(guard);
         }
-        None
+        Err("alloc_proc: no free process")
     }
 
     pub fn user_init(&mut self) {
@@ -83,25 +95,35 @@ impl ProcessTable {
             .user_init()
             .expect("user_init: failed process's initilization");
 
-        p.inner.lock().state = ProcState::Runnable;
+        sched::make_runnable(p.index, &mut p.inner.lock());
     }
 
+    /// takes the process chosen by the scheduler, if any.
     pub fn find_runnable(&mut self) -> Option<&mut Proc> {
-        for p in self.tables.iter_mut() {
-            let mut guard = p.inner.lock();
+        loop {
+            let i = sched::pick_next(CpuTable::cpu_id())?;
+            let mut guard = self.tables[i].inner.lock();
             if guard.state == ProcState::Runnable {
-                // found a runnable process
+                // claim it, so no other cpu runs it.
                 guard.state = ProcState::Allocated;
                 drop(guard);
-                return Some(p);
+                return Some(&mut self.tables[i]);
             }
             drop(guard);
         }
-        None
     }
 
     pub fn wakeup(&self, chan: usize) {
+        self.wakeup_n(chan, NPROC);
+    }
+
+    /// wakes up at most `n` processes sleeping on `chan`. returns how many were woken.
+    pub fn wakeup_n(&self, chan: usize, n: usize) -> usize {
+        let mut woken = 0;
         for p in self.tables.iter() {
+            if woken == n {
+                break;
+            }
             unsafe {
                 if ptr::eq(p, CPU_TABLE.my_proc()) {
                     continue;
@@ -109,7 +131,184 @@ impl ProcessTable {
             }
             let mut guard = p.inner.lock();
             if guard.state == ProcState::Sleeping && guard.chan == chan {
-                guard.state = ProcState::Runnable;
+                sched::make_runnable(p.index, &mut guard);
+                woken += 1;
+            }
+            drop(guard);
+        }
+        woken
+    }
+
+    /// sends the signal `sig` to the process with the given pid. `sig` 0 only checks that the
+    /// process exists.
+    /// SIGKILL, or any other signal that terminates the victim, marks it as killed. the victim
+    /// won't exit until it tries to return to user space, so a sleeping victim is woken up to
+    /// notice it. init is not sent the signals that would terminate it, as the kernel cannot go on
+    /// without it.
+    pub fn kill(&self, pid: usize, sig: usize) -> Result<(), &'static str> {
+        for p in self.tables.iter() {
+            let mut guard = p.inner.lock();
+            if guard.pid == pid && guard.state != ProcState::Unused {
+                if sig != 0 && p.index == 0 && signal::is_fatal(&guard, sig) {
+                    drop(guard);
+                    return Err("kill: cannot signal init");
+                }
+                if sig != 0 {
+                    signal::post(p, &mut guard, sig);
+                }
+                drop(guard);
+                return Ok(());
+            }
+            drop(guard);
+        }
+        Err("kill: no such process")
+    }
+
+    /// returns the scheduling priority of the process with the given pid.
+    pub fn priority(&self, pid: usize) -> Result<usize, &'static str> {
+        for p in self.tables.iter() {
+            let guard = p.inner.lock();
+            if guard.pid == pid && guard.state != ProcState::Unused {
+                let prio = sched::priority(&guard);
+                drop(guard);
+                return Ok(prio);
+            }
+            drop(guard);
+        }
+        Err("priority: no such process")
+    }
+
+    /// changes the scheduling priority of the process with the given pid.
+    pub fn set_priority(&self, pid: usize, prio: usize) -> Result<(), &'static str> {
+        for p in self.tables.iter() {
+            let mut guard = p.inner.lock();
+            if guard.pid == pid && guard.state != ProcState::Unused {
+                let ret = sched::set_priority(&mut guard, prio);
+                drop(guard);
+                return ret;
+            }
+            drop(guard);
+        }
+        Err("set_priority: no such process")
+    }
+
+    /// returns the cpus the process with the given pid may run on.
+    pub fn affinity(&self, pid: usize) -> Result<usize, &'static str> {
+        for p in self.tables.iter() {
+            let guard = p.inner.lock();
+            if guard.pid == pid && guard.state != ProcState::Unused {
+                let mask = guard.affinity;
+                drop(guard);
+                return Ok(mask);
+            }
+            drop(guard);
+        }
+        Err("affinity: no such process")
+    }
+
+    /// restricts the process with the given pid to the cpus in `mask`.
+    pub fn set_affinity(&self, pid: usize, mask: usize) -> Result<(), &'static str> {
+        for p in self.tables.iter() {
+            let mut guard = p.inner.lock();
+            if guard.pid == pid && guard.state != ProcState::Unused {
+                let ret = sched::set_affinity(&mut guard, mask);
+                drop(guard);
+                return ret;
+            }
+            drop(guard);
+        }
+        Err("set_affinity: no such process")
+    }
+
+    /// sends the signal `sig` to the processes in the foreground of the console: every process
+    /// but init and the session leaders, such as the shell, which wait for the others.
+    pub fn kill_foreground(&self, sig: usize) {
+        for p in self.tables.iter().skip(1) {
+            let mut guard = p.inner.lock();
+            if guard.state != ProcState::Unused
+                && guard.state != ProcState::Zombie
+                && guard.sid != guard.pid
+            {
+                signal::post(p, &mut guard, sig);
+            }
+            drop(guard);
+        }
+    }
+
+    /// chooses the process to kill when `p` finds no memory left: the one with the most user
+    /// pages, but init. a process killed already that has yet to exit is chosen again instead, as
+    /// it is about to give its memory back.
+    pub fn oom_victim(&self, p: &Proc) -> Option<&Proc> {
+        let mut victim = None;
+        let mut most = 0;
+        for q in self.tables.iter().skip(1) {
+            let guard = q.inner.lock();
+            if matches!(guard.state, ProcState::Unused | ProcState::Zombie) || guard.thread {
+                drop(guard);
+                continue;
+            }
+            if guard.killed {
+                drop(guard);
+                return Some(q);
+            }
+            // the address space of another process is only taken while it cannot run, so that it
+            // doesn't exec or exit meanwhile.
+            let mm = match guard.state {
+                _ if q.index == p.index => unsafe { &*q.data.get() }.mm.clone(),
+                ProcState::Runnable | ProcState::Sleeping => unsafe { &*q.data.get() }.mm.clone(),
+                _ => None,
+            };
+            drop(guard);
+
+            let pages = mm.map_or(0, |mm| mm.lock().page_table.user_pages());
+            if pages > most {
+                most = pages;
+                victim = Some(q);
+            }
+        }
+        victim
+    }
+
+    /// runs `f` on the locked address space of the process at `index`, unless the process is a
+    /// thread, has none, or may be running on another CPU, where its TLB would still map the pages
+    /// `f` unmaps. the process is kept from being scheduled meanwhile, unless it is `p`.
+    pub fn with_mm<R>(&self, index: usize, p: &Proc, f: impl FnOnce(&mut Mm) -> R) -> Option<R> {
+        let q = &self.tables[index];
+        let guard = q.inner.lock();
+        let stopped = matches!(guard.state, ProcState::Runnable | ProcState::Sleeping);
+        if guard.thread || !(stopped || q.index == p.index) {
+            drop(guard);
+            return None;
+        }
+        let mm = unsafe { &*q.data.get() }.mm.clone()?;
+        let res = f(&mut mm.lock());
+        drop(guard);
+        Some(res)
+    }
+
+    /// waits until no other hart may hold a TLB entry of the page table at `satp` made before the
+    /// call, by asking the harts running the threads sharing it in user space to flush their TLBs.
+    /// they do it even with interrupts off, so it may be called with spinlocks held. it must be
+    /// called after a page is unmapped or loses permissions, before the page is freed or relied on.
+    pub fn flush_tlb(&self, satp: usize) {
+        // the PTEs changed must be seen by the harts that are seen to be in user space later.
+        fence(Ordering::SeqCst);
+        for p in self.tables.iter() {
+            p.wait_tlb_flush(satp);
+        }
+    }
+
+    /// posts SIGALRM to the processes whose alarm has gone off at the tick `now`, and wakes up the
+    /// sleepers whose timeout has passed.
+    pub fn expire_timers(&self, now: usize) {
+        for p in self.tables.iter() {
+            let mut guard = p.inner.lock();
+            if guard.sig.alarm != 0 && guard.sig.alarm <= now {
+                guard.sig.alarm = 0;
+                signal::post(p, &mut guard, SIGALRM);
+            }
+            if guard.state == ProcState::Sleeping && guard.timeout != 0 && guard.timeout <= now {
+                sched::make_runnable(p.index, &mut guard);
             }
             drop(guard);
         }
@@ -117,6 +316,38 @@ impl ProcessTable {
 
     /// waits for a child of the given process `p` to exit. copies exit status into `addr`.
     pub fn wait(&mut self, p: &mut Proc, addr: usize) -> Result<usize, &'static str> {
+        self.waitpid(p, -1, addr, 0)
+    }
+
+    /// waits for the child `pid` of the given process `p` to exit, or for any child if `pid` is
+    /// negative. copies exit status into `addr`.
+    /// returns the pid of the child, or 0 if WNOHANG is given and the child hasn't exited yet.
+    pub fn waitpid(
+        &mut self,
+        p: &mut Proc,
+        pid: isize,
+        addr: usize,
+        options: i32,
+    ) -> Result<usize, &'static str> {
+        self.wait_child(p, pid, addr, options, false)
+    }
+
+    /// waits for the thread `tid` created by the given process `p` to exit, or for any of its
+    /// threads if `tid` is negative. copies exit status into `addr`.
+    pub fn join(&mut self, p: &mut Proc, tid: isize, addr: usize) -> Result<usize, &'static str> {
+        self.wait_child(p, tid, addr, 0, true)
+    }
+
+    /// waits for a child that is a thread if `thread` is true, or a process otherwise.
+    /// the init proc reaps both, since orphaned threads are passed to it as well.
+    fn wait_child(
+        &mut self,
+        p: &mut Proc,
+        pid: isize,
+        addr: usize,
+        options: i32,
+        thread: bool,
+    ) -> Result<usize, &'static str> {
         let mut parents = self.parents.lock();
 
         loop {
@@ -130,6 +361,16 @@ impl ProcessTable {
                 let child = &mut self.tables[i];
                 let cguard = child.inner.lock();
 
+                if pid >= 0 && cguard.pid != pid as usize {
+                    drop(cguard);
+                    continue;
+                }
+
+                if cguard.thread != thread && p.index != 0 {
+                    drop(cguard);
+                    continue;
+                }
+
                 have_kids = true;
 
                 if cguard.state != ProcState::Zombie {
@@ -138,57 +379,78 @@ impl ProcessTable {
                     continue;
                 }
 
-                if addr != 0 {
-                    // copy exit status into `addr`
-                    p.data.get_mut().copy_out(
-                        addr,
-                        &cguard.exit_status as *const _ as *const u8,
-                        mem::size_of::<i32>(),
-                    )?;
-                }
-
                 // take pid for ret
                 let child_pid = cguard.pid;
+                let exit_status = cguard.exit_status;
+                let mut usage = cguard.usage;
+                usage.add(&cguard.child_usage);
 
                 // tidy up
                 let cdata = child.data.get_mut();
                 Proc::free(cdata, cguard);
                 parents[child.index].take();
 
+                // the child's time is now the parent's children's time.
+                p.inner.lock().child_usage.add(&usage);
+                drop(parents);
+
+                if addr != 0 {
+                    // copy exit status into `addr`, with no lock held, since the copy may fault
+                    // the page in and sleep.
+                    p.data.get_mut().copy_out(
+                        addr,
+                        &exit_status as *const _ as *const u8,
+                        mem::size_of::<i32>(),
+                    )?;
+                }
+
                 return Ok(child_pid);
             }
 
-            let killed: bool;
-            let pguard = p.inner.lock();
-            killed = pguard.killed;
-            drop(pguard);
-
             // No point waiting if we don't have any children
-            if !have_kids || killed {
+            if !have_kids || p.is_killed() {
                 drop(parents);
                 return Err("children not found");
             }
 
+            if options & WNOHANG != 0 {
+                drop(parents);
+                return Ok(0);
+            }
+
             // wait for a child to exit, use the parent's pointer as chan
             parents = p.sleep(p as *const Proc as usize, parents);
         }
     }
 
+    /// returns the pid of the parent of the given process `p`.
+    /// the init proc has no parent, and its pid is reported for orphans it has adopted.
+    pub fn parent_pid(&self, p: &Proc) -> usize {
+        let parents = self.parents.lock();
+        let ppid = match parents[p.index] {
+            Some(parent) => {
+                let guard = self.tables[parent].inner.lock();
+                let ppid = guard.pid;
+                drop(guard);
+                ppid
+            }
+            None => 0,
+        };
+        drop(parents);
+        ppid
+    }
+
     /// terminates the given process `p`. status reported to wait(). no returns.
     pub fn exit(&self, p: &mut Proc, status: i32) {
         if ptr::eq(&self.tables[0] as *const _, p) {
             panic!("init exiting");
         }
 
-        // close all open files
+        // only the calling thread ends. the open files are closed once no thread shares them.
         let pdata = p.data.get_mut();
-        for i in 0..pdata.o_files.len() {
-            drop(pdata.o_files[i].take());
-        }
-
-        LOG.begin_op();
-        drop(pdata.cwd.take());
-        LOG.end_op();
+        pdata.leave_mm();
+        drop(pdata.files.take());
+        let thread = p.inner.lock().thread;
 
         let mut parents = self.parents.lock();
 
@@ -204,6 +466,10 @@ impl ProcessTable {
         // processes other than the init proc must have own parent because their are always created by
         // fork().
         let parent = *parents[p.index].as_ref().unwrap();
+        let pp = &self.tables[parent];
+        if !thread {
+            signal::post(pp, &mut pp.inner.lock(), SIGCHLD);
+        }
         // its parent might be sleeping in wait().
         self.wakeup(&self.tables[parent] as *const Proc as usize);
 
@@ -0,0 +1,54 @@
+//! Random numbers for the kernel, such as to randomize the layout of the user address spaces.
+//!
+//! The kernel drives no random number generator on qemu's virt machine, so the pool is stirred
+//! with the time of each device interrupt, which jitters, and the numbers are drawn from it with
+//! SplitMix64. they are good enough to randomize layouts, but not for cryptography.
+
+use crate::{register::clint, spinlock::SpinLock};
+
+static POOL: SpinLock<u64> = SpinLock::new(0, "random");
+
+/// SplitMix64's finalizer, which spreads every bit of `x` over the result.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// mixes the current time into the pool.
+pub fn stir() {
+    let now = unsafe { clint::read_mtime() };
+    let mut pool = POOL.lock();
+    *pool = mix(*pool ^ now);
+    drop(pool);
+}
+
+/// a random number.
+pub fn next() -> u64 {
+    let now = unsafe { clint::read_mtime() };
+    let mut pool = POOL.lock();
+    *pool = pool.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let x = mix(*pool ^ now);
+    drop(pool);
+    x
+}
+
+/// a random number in [0, n), which must not be empty.
+pub fn below(n: usize) -> usize {
+    (next() % n as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn numbers_differ_and_stay_below() {
+        let a = next();
+        let b = next();
+        assert_ne!(a, b);
+        for _ in 0..100 {
+            assert!(below(7) < 7);
+        }
+    }
+}
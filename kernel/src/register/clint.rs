@@ -1,10 +1,14 @@
 use core::ptr;
 
+pub const CLINT_MSIP: usize = 0x2000000;
 const CLINT_MTIME: usize = 0x200bff8;
 pub const CLINT_MTIMECMP: usize = 0x2004000;
 
+// mtime ticks at 10MHz on qemu's virt machine.
+pub const MTIME_FREQ: u64 = 10_000_000;
+
 #[inline]
-unsafe fn read_mtime() -> u64 {
+pub unsafe fn read_mtime() -> u64 {
     ptr::read_volatile(CLINT_MTIME as *const u64)
 }
 
@@ -18,3 +22,9 @@ pub unsafe fn add_mtimecmp(mhartid: usize, interval: u64) {
     let v = read_mtime();
     write_mtimecmp(mhartid, v + interval);
 }
+
+/// raises a machine software interrupt on `hartid`.
+pub unsafe fn send_ipi(hartid: usize) {
+    let offset = CLINT_MSIP + 4 * hartid;
+    ptr::write_volatile(offset as *mut u32, 1);
+}
@@ -17,3 +17,9 @@ pub unsafe fn enable_machine_timer_interrupt() {
     mie |= 1 << 7;
     write(mie);
 }
+
+pub unsafe fn enable_machine_software_interrupt() {
+    let mut mie = read();
+    mie |= 1 << 3;
+    write(mie);
+}
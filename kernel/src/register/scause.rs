@@ -1,7 +1,7 @@
 use core::arch::asm;
 
 #[inline]
-unsafe fn read() -> usize {
+pub unsafe fn read() -> usize {
     let ret: usize;
     asm!("csrr {}, scause", out(reg) ret);
     ret
@@ -12,6 +12,7 @@ const INTERRUPT_SUPERVISOR_SOFTWARE: usize = INTERRUPT + 1;
 const INTERRUPT_SUPERVISOR_EXTERNAL: usize = INTERRUPT + 9;
 const EXCEPTION: usize = 0x0;
 const EXCEPTION_ENVIRONMENT_CALL: usize = EXCEPTION + 8;
+const EXCEPTION_PAGE_ACCESS_INSTRUCTION: usize = EXCEPTION + 12;
 const EXCEPTION_PAGE_ACCESS_LOAD: usize = EXCEPTION + 13;
 const EXCEPTION_PAGE_ACCESS_STORE_ATOMIC: usize = EXCEPTION + 15;
 
@@ -20,6 +21,7 @@ pub enum ScauseType {
     IntSSoft,
     IntSExt,
     ExcEcall,
+    ExcPageInstruction,
     ExcPageLoad,
     ExcPageStoreAtomic,
     Unknown(usize),
@@ -32,6 +34,7 @@ pub unsafe fn get_type() -> ScauseType {
         INTERRUPT_SUPERVISOR_SOFTWARE => ScauseType::IntSSoft,
         INTERRUPT_SUPERVISOR_EXTERNAL => ScauseType::IntSExt,
         EXCEPTION_ENVIRONMENT_CALL => ScauseType::ExcEcall,
+        EXCEPTION_PAGE_ACCESS_INSTRUCTION => ScauseType::ExcPageInstruction,
         EXCEPTION_PAGE_ACCESS_LOAD => ScauseType::ExcPageLoad,
         EXCEPTION_PAGE_ACCESS_STORE_ATOMIC => ScauseType::ExcPageStoreAtomic,
         v => ScauseType::Unknown(v),
@@ -0,0 +1,213 @@
+//! Scheduling policies.
+//!
+//! A policy decides which runnable process a CPU switches to next, and how long it may run.
+//! Each CPU has its own run queue, an instance of the policy. Every process that becomes
+//! `Runnable` is put on the least loaded CPU it is allowed to run on with `make_runnable()`, and
+//! the scheduler loop in `CpuTable::scheduler()` takes processes back out with `pick_next()`. A CPU
+//! whose queue is empty steals work from the others.
+//!
+//! `enqueue()` and `tick()` are called with the process's lock held, so a policy must never lock a
+//! process while holding its own locks.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use array_macro::array;
+
+use crate::{
+    cpu::CpuTable,
+    param::NCPU,
+    proc::{ProcInner, ProcState},
+    process::NPROC,
+};
+
+#[cfg(not(feature = "sched-rr"))]
+mod mlfq;
+#[cfg(feature = "sched-rr")]
+mod round_robin;
+
+#[cfg(not(feature = "sched-rr"))]
+pub use self::mlfq::{Mlfq as Policy, SchedInfo};
+#[cfg(feature = "sched-rr")]
+pub use self::round_robin::{RoundRobin as Policy, SchedInfo};
+
+static RUNQUEUES: [Policy; NCPU] = array![_ => Policy::new(); NCPU];
+
+/// the cpus that have entered the scheduler loop.
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+pub const ALL_CPUS: usize = (1 << NCPU) - 1;
+
+pub trait Scheduler {
+    /// makes the process `index` eligible to run.
+    fn enqueue(&self, index: usize, inner: &mut ProcInner);
+
+    /// removes the process that should run next.
+    fn pick_next(&self) -> Option<usize>;
+
+    /// removes a process that is allowed to run on `cpu`, for another cpu that has run out of work.
+    fn steal(&self, cpu: usize) -> Option<usize>;
+
+    /// the number of queued processes.
+    fn load(&self) -> usize;
+
+    /// charges a clock tick to the running process.
+    /// returns true if the process has used up its time slice and should yield.
+    fn tick(&self, inner: &mut ProcInner) -> bool;
+
+    /// the priority of a process. 0 is the highest.
+    fn priority(inner: &ProcInner) -> usize;
+
+    /// changes the priority of a process.
+    fn set_priority(inner: &mut ProcInner, prio: usize) -> Result<(), &'static str>;
+}
+
+/// the cpu's scheduler loop is ready to run processes.
+pub fn online(cpu: usize) {
+    ONLINE.fetch_or(1 << cpu, Ordering::Release);
+}
+
+/// picks the queue for a process: the least loaded cpu it may run on, favoring the one it last ran
+/// on. before any scheduler loop is up, the only choice is the current cpu.
+fn select_cpu(inner: &ProcInner) -> usize {
+    let allowed = inner.affinity & ONLINE.load(Ordering::Acquire);
+    let mut best: Option<(usize, usize)> = None;
+    for cpu in 0..NCPU {
+        if allowed & (1 << cpu) == 0 {
+            continue;
+        }
+        let load = RUNQUEUES[cpu].load();
+        best = match best {
+            Some((_, min)) if load > min || (load == min && cpu != inner.last_cpu) => best,
+            _ => Some((cpu, load)),
+        };
+    }
+    best.map_or(CpuTable::cpu_id(), |(cpu, _)| cpu)
+}
+
+/// marks the process `index` runnable and hands it to a cpu's queue.
+/// must be called with the process's lock held.
+pub fn make_runnable(index: usize, inner: &mut ProcInner) {
+    inner.state = ProcState::Runnable;
+    RUNQUEUES[select_cpu(inner)].enqueue(index, inner);
+}
+
+/// removes the process that `cpu` should run next, stealing one from another cpu if it has
+/// nothing to do.
+pub fn pick_next(cpu: usize) -> Option<usize> {
+    RUNQUEUES[cpu].pick_next().or_else(|| {
+        (1..NCPU)
+            .map(|i| (cpu + i) % NCPU)
+            .find_map(|victim| RUNQUEUES[victim].steal(cpu))
+    })
+}
+
+/// charges a clock tick to the process running on this cpu.
+/// must be called with the process's lock held.
+pub fn tick(inner: &mut ProcInner) -> bool {
+    RUNQUEUES[CpuTable::cpu_id()].tick(inner)
+}
+
+pub fn priority(inner: &ProcInner) -> usize {
+    Policy::priority(inner)
+}
+
+pub fn set_priority(inner: &mut ProcInner, prio: usize) -> Result<(), &'static str> {
+    Policy::set_priority(inner, prio)
+}
+
+/// restricts the process to the cpus in `mask`. the process moves when it is next queued.
+pub fn set_affinity(inner: &mut ProcInner, mask: usize) -> Result<(), &'static str> {
+    let mask = mask & ALL_CPUS;
+    if mask & ONLINE.load(Ordering::Acquire) == 0 {
+        return Err("set_affinity: no online cpu in mask");
+    }
+    inner.affinity = mask;
+    Ok(())
+}
+
+/// a FIFO of process indices. a process is in at most one queue at a time, so NPROC slots are
+/// enough.
+struct RunQueue {
+    procs: [usize; NPROC],
+    head: usize,
+    len: usize,
+    // the cpus each queued process may run on, by process index.
+    affinity: [usize; NPROC],
+}
+
+impl RunQueue {
+    const fn new() -> Self {
+        Self {
+            procs: [0; NPROC],
+            head: 0,
+            len: 0,
+            affinity: [0; NPROC],
+        }
+    }
+
+    fn push(&mut self, index: usize, affinity: usize) {
+        if self.len == NPROC {
+            panic!("runqueue: full");
+        }
+        self.procs[(self.head + self.len) % NPROC] = index;
+        self.affinity[index] = affinity;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        let index = self.procs[self.head];
+        self.head = (self.head + 1) % NPROC;
+        self.len -= 1;
+        Some(index)
+    }
+
+    /// removes the first process that may run on `cpu`. the others keep their order.
+    fn steal(&mut self, cpu: usize) -> Option<usize> {
+        let mut stolen = None;
+        for _ in 0..self.len {
+            let index = self.pop().unwrap();
+            if stolen.is_none() && self.affinity[index] & (1 << cpu) != 0 {
+                stolen = Some(index);
+            } else {
+                self.push(index, self.affinity[index]);
+            }
+        }
+        stolen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn runqueue_is_fifo() {
+        let mut q = RunQueue::new();
+        assert_eq!(None, q.pop());
+        for i in 0..NPROC {
+            q.push(i, ALL_CPUS);
+        }
+        assert_eq!(Some(0), q.pop());
+        q.push(0, ALL_CPUS);
+        for i in 1..NPROC {
+            assert_eq!(Some(i), q.pop());
+        }
+        assert_eq!(Some(0), q.pop());
+        assert_eq!(None, q.pop());
+    }
+
+    #[test_case]
+    fn runqueue_steal_honors_affinity() {
+        let mut q = RunQueue::new();
+        q.push(1, 1 << 0);
+        q.push(2, 1 << 1);
+        q.push(3, ALL_CPUS);
+        assert_eq!(Some(2), q.steal(1));
+        assert_eq!(Some(3), q.steal(1));
+        assert_eq!(None, q.steal(1));
+        assert_eq!(Some(1), q.pop());
+    }
+}
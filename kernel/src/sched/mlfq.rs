@@ -0,0 +1,247 @@
+//! Multi-level feedback queue.
+//!
+//! - a process runs in the queue of its `level`, and higher queues always run first.
+//! - a process that uses up the time slice of its level is demoted to the next level. slices
+//!   double at each level, so CPU-bound processes sink and run less often but for longer, while
+//!   interactive ones that sleep before their slice runs out stay on top.
+//! - every BOOST_PERIOD ticks of CPU time, all processes are moved back to the level of their
+//!   priority, so that demoted processes don't starve.
+//!
+//! Each cpu has its own `Mlfq`, but boosts are global: a boost bumps `EPOCH`, and every queue and
+//! process catches up with it the next time it is used.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use array_macro::array;
+
+use crate::{
+    proc::ProcInner,
+    process::NPROC,
+    spinlock::{SpinLock, SpinLockGuard},
+};
+
+use super::{RunQueue, Scheduler};
+
+pub const NQUEUE: usize = 3;
+const BOOST_PERIOD: usize = 50;
+
+// ticks charged to processes on all cpus.
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+// the number of boosts so far.
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+fn time_slice(level: usize) -> usize {
+    1 << level
+}
+
+fn boost() {
+    EPOCH.fetch_add(1, Ordering::Release);
+}
+
+#[derive(Clone, Copy)]
+pub struct SchedInfo {
+    // the level the process starts at and is boosted back to.
+    priority: usize,
+    level: usize,
+    // ticks used at the current level.
+    ticks: usize,
+    // the boost the process was last accounted in.
+    epoch: usize,
+}
+
+impl SchedInfo {
+    pub const fn new() -> Self {
+        Self {
+            priority: 0,
+            level: 0,
+            ticks: 0,
+            epoch: 0,
+        }
+    }
+
+    /// brings a process that missed a boost back to its priority.
+    fn refresh(&mut self) {
+        let epoch = EPOCH.load(Ordering::Acquire);
+        if self.epoch != epoch {
+            self.epoch = epoch;
+            self.level = self.priority;
+            self.ticks = 0;
+        }
+    }
+
+    /// charges a tick. returns true if it used up the time slice, demoting the process.
+    fn charge(&mut self) -> bool {
+        self.refresh();
+        self.ticks += 1;
+        if self.ticks < time_slice(self.level) {
+            return false;
+        }
+        if self.level < NQUEUE - 1 {
+            self.level += 1;
+        }
+        self.ticks = 0;
+        true
+    }
+}
+
+struct Queues {
+    levels: [RunQueue; NQUEUE],
+    // the priority of each queued process, so a boost doesn't have to lock them.
+    priority: [usize; NPROC],
+    // the boost the queues were last accounted in.
+    epoch: usize,
+}
+
+impl Queues {
+    /// moves every queued process back to the level of its priority.
+    fn refresh(&mut self) {
+        let epoch = EPOCH.load(Ordering::Acquire);
+        if self.epoch == epoch {
+            return;
+        }
+        self.epoch = epoch;
+        for level in 1..NQUEUE {
+            for _ in 0..self.levels[level].len {
+                let index = self.levels[level].pop().unwrap();
+                let affinity = self.levels[level].affinity[index];
+                self.levels[self.priority[index]].push(index, affinity);
+            }
+        }
+    }
+}
+
+pub struct Mlfq {
+    queues: SpinLock<Queues>,
+}
+
+impl Mlfq {
+    pub const fn new() -> Self {
+        Self {
+            queues: SpinLock::new(
+                Queues {
+                    levels: array![_ => RunQueue::new(); NQUEUE],
+                    priority: [0; NPROC],
+                    epoch: 0,
+                },
+                "mlfq",
+            ),
+        }
+    }
+
+    fn lock(&self) -> SpinLockGuard<Queues> {
+        let mut guard = self.queues.lock();
+        guard.refresh();
+        guard
+    }
+}
+
+impl Scheduler for Mlfq {
+    fn enqueue(&self, index: usize, inner: &mut ProcInner) {
+        inner.sched.refresh();
+        let mut guard = self.lock();
+        guard.priority[index] = inner.sched.priority;
+        guard.levels[inner.sched.level].push(index, inner.affinity);
+        drop(guard);
+    }
+
+    fn pick_next(&self) -> Option<usize> {
+        let mut guard = self.lock();
+        let index = guard.levels.iter_mut().find_map(|q| q.pop());
+        drop(guard);
+        index
+    }
+
+    fn steal(&self, cpu: usize) -> Option<usize> {
+        let mut guard = self.lock();
+        let index = guard.levels.iter_mut().find_map(|q| q.steal(cpu));
+        drop(guard);
+        index
+    }
+
+    fn load(&self) -> usize {
+        let guard = self.queues.lock();
+        let load = guard.levels.iter().map(|q| q.len).sum();
+        drop(guard);
+        load
+    }
+
+    fn tick(&self, inner: &mut ProcInner) -> bool {
+        if (TICKS.fetch_add(1, Ordering::Relaxed) + 1) % BOOST_PERIOD == 0 {
+            boost();
+        }
+
+        if inner.sched.charge() {
+            return true;
+        }
+
+        // give way to a process waiting at a higher level.
+        let guard = self.lock();
+        let preempt = guard.levels[..inner.sched.level]
+            .iter()
+            .any(|q| q.len > 0);
+        drop(guard);
+        preempt
+    }
+
+    fn priority(inner: &ProcInner) -> usize {
+        inner.sched.priority
+    }
+
+    fn set_priority(inner: &mut ProcInner, prio: usize) -> Result<(), &'static str> {
+        if prio >= NQUEUE {
+            return Err("set_priority: priority out of range");
+        }
+        inner.sched.priority = prio;
+        inner.sched.level = prio;
+        inner.sched.ticks = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn demotes_after_full_slice() {
+        let mut info = SchedInfo::new();
+        info.refresh();
+        for level in 0..NQUEUE {
+            assert_eq!(level, info.level);
+            for _ in 1..time_slice(level) {
+                assert!(!info.charge());
+            }
+            assert!(info.charge());
+        }
+        assert_eq!(NQUEUE - 1, info.level);
+    }
+
+    #[test_case]
+    fn picks_higher_levels_first() {
+        let mlfq = Mlfq::new();
+        let mut low = ProcInner::new();
+        Mlfq::set_priority(&mut low, NQUEUE - 1).unwrap();
+        let mut high = ProcInner::new();
+        mlfq.enqueue(1, &mut low);
+        mlfq.enqueue(2, &mut high);
+        assert_eq!(2, mlfq.load());
+        assert_eq!(Some(2), mlfq.pick_next());
+        assert_eq!(Some(1), mlfq.pick_next());
+        assert_eq!(None, mlfq.pick_next());
+    }
+
+    #[test_case]
+    fn boost_restores_priority() {
+        let mlfq = Mlfq::new();
+        let mut inner = ProcInner::new();
+        inner.sched.refresh();
+        inner.sched.level = NQUEUE - 1;
+        mlfq.enqueue(1, &mut inner);
+        boost();
+        let mut high = ProcInner::new();
+        mlfq.enqueue(2, &mut high);
+        // both are at the top level now, in the order they were queued.
+        assert_eq!(Some(1), mlfq.pick_next());
+        assert_eq!(Some(2), mlfq.pick_next());
+    }
+}
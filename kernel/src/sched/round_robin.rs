@@ -0,0 +1,58 @@
+use crate::{proc::ProcInner, spinlock::SpinLock};
+
+use super::{RunQueue, Scheduler};
+
+/// runs the processes in turn, each for one tick. priorities are recorded but have no effect.
+pub struct RoundRobin {
+    queue: SpinLock<RunQueue>,
+}
+
+#[derive(Clone, Copy)]
+pub struct SchedInfo {
+    priority: usize,
+}
+
+impl SchedInfo {
+    pub const fn new() -> Self {
+        Self { priority: 0 }
+    }
+}
+
+impl RoundRobin {
+    pub const fn new() -> Self {
+        Self {
+            queue: SpinLock::new(RunQueue::new(), "runqueue"),
+        }
+    }
+}
+
+impl Scheduler for RoundRobin {
+    fn enqueue(&self, index: usize, inner: &mut ProcInner) {
+        self.queue.lock().push(index, inner.affinity);
+    }
+
+    fn pick_next(&self) -> Option<usize> {
+        self.queue.lock().pop()
+    }
+
+    fn steal(&self, cpu: usize) -> Option<usize> {
+        self.queue.lock().steal(cpu)
+    }
+
+    fn load(&self) -> usize {
+        self.queue.lock().len
+    }
+
+    fn tick(&self, _inner: &mut ProcInner) -> bool {
+        true
+    }
+
+    fn priority(inner: &ProcInner) -> usize {
+        inner.sched.priority
+    }
+
+    fn set_priority(inner: &mut ProcInner, prio: usize) -> Result<(), &'static str> {
+        inner.sched.priority = prio;
+        Ok(())
+    }
+}
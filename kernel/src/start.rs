@@ -1,4 +1,7 @@
-use core::arch::asm;
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use crate::{param::NCPU, register};
 
@@ -6,7 +9,28 @@ use crate::{param::NCPU, register};
 static STACK0: [u8; 4096 * NCPU] = [0; 4096 * NCPU];
 
 #[no_mangle]
-static TIMER_SCRATCH: [[usize; 5]; NCPU] = [[0; 5]; NCPU];
+static mut TIMER_SCRATCH: [[usize; 7]; NCPU] = [[0; 7]; NCPU];
+
+// the TLB flushes each hart has been asked for by the others, bumped by timervec in kernelvec.S
+// once before the sfence.vma and once after, so that it is odd while one is under way.
+static TLB_SHOOTDOWNS: [AtomicUsize; NCPU] = {
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; NCPU]
+};
+
+/// asks `hart` to flush its TLB, which it does in machine mode even if it runs with interrupts
+/// off, within the time an interrupt takes. returns the ticket to pass to `tlb_flushed()`.
+pub fn remote_sfence_vma(hart: usize) -> usize {
+    let n = TLB_SHOOTDOWNS[hart].load(Ordering::SeqCst);
+    unsafe { register::clint::send_ipi(hart) };
+    // a flush under way may have begun before the caller changed the PTEs, so wait for the next.
+    (n + 1) / 2 * 2 + 2
+}
+
+/// tells whether `hart` has flushed its TLB since `remote_sfence_vma()` returned `ticket`.
+pub fn tlb_flushed(hart: usize, ticket: usize) -> bool {
+    TLB_SHOOTDOWNS[hart].load(Ordering::SeqCst) >= ticket
+}
 
 #[no_mangle]
 unsafe fn start() -> ! {
@@ -51,9 +75,11 @@ unsafe fn timerinit() {
     let interval = 1000000; // cycles; about 1/10th second in qemu.
     register::clint::add_mtimecmp(id, interval);
 
-    let mut arr = TIMER_SCRATCH[id];
+    let arr = &mut TIMER_SCRATCH[id];
     arr[3] = register::clint::CLINT_MTIMECMP + 8 * id;
     arr[4] = interval as usize;
+    arr[5] = register::clint::CLINT_MSIP + 4 * id;
+    arr[6] = &TLB_SHOOTDOWNS[id] as *const AtomicUsize as usize;
     register::mscratch::write(arr.as_ptr() as u64);
 
     // Set the machine-mode trap handler.
@@ -67,4 +93,7 @@ unsafe fn timerinit() {
 
     // Enable machine-mode timer interrupt.
     register::mie::enable_machine_timer_interrupt();
+
+    // and the software one, by which the other harts ask for TLB flushes.
+    register::mie::enable_machine_software_interrupt();
 }
@@ -0,0 +1,270 @@
+//! Swapping out user memory to the second virtio disk.
+//!
+//! When memory runs out, the user pages not used lately are taken from the processes so that
+//! more of them fit than there is physical memory. A dirty page is written out to a slot of the
+//! swap area, a page-sized part of the disk, and its PTE holds the slot instead of the page until
+//! a page fault reads it back in. A clean page is only dropped: it is still all zero, or as read
+//! from its file, and the page fault allocates it again the way it did the first time.
+//!
+//! The victims are chosen by a clock algorithm. The hand goes over the user pages of each process
+//! in turn; a page accessed since the hand last passed has its accessed bit cleared and is given
+//! a second chance, one that has not is taken. Only the pages private to a single-threaded
+//! process are taken, those of its heap and of its private mmap-ed regions, and not the pages it
+//! shares with others, by fork() or through the page cache, nor those with futex sleepers.
+//!
+//! The slots are reference counted, since fork() shares the slots of the pages swapped out with
+//! the child, each of which reads in its own copy. The swap I/O is serialized by `IO`, so a page
+//! is not read in before it is written out.
+
+use crate::{
+    cpu::CPU_TABLE,
+    page_table::{is_shared_page, put_page, PteFlag},
+    param::{MAXVA, PAGESIZE, SWAPPAGES},
+    println,
+    proc::{futex, Mm},
+    process::{NPROC, PROCESS_TABLE},
+    sleeplock::SleepLock,
+    spinlock::SpinLock,
+    virtio::SWAP_DISK,
+};
+
+/// the pages to free in a go when memory runs out.
+pub const BATCH: usize = 32;
+
+// the pages the hand passes over with the address space of a process held.
+const SCAN: usize = 64;
+
+const SECTORS: usize = PAGESIZE / 512;
+
+struct Slots {
+    // the references to each slot, 0 if it is free.
+    refs: [u8; SWAPPAGES],
+    // the slots on the disk, and those in use.
+    nslot: usize,
+    nused: usize,
+    // where to look for a free slot next.
+    next: usize,
+}
+
+static SLOTS: SpinLock<Slots> = SpinLock::new(
+    Slots {
+        refs: [0; SWAPPAGES],
+        nslot: 0,
+        nused: 0,
+        next: 0,
+    },
+    "swap",
+);
+
+/// the clock hand: the process, and the address in it, to look at next.
+struct Hand {
+    index: usize,
+    va: usize,
+}
+
+static IO: SleepLock<Hand> = SleepLock::new(Hand { index: 0, va: 0 }, "swap_io");
+
+pub fn init() {
+    let mut disk = SWAP_DISK.lock();
+    if unsafe { disk.init() }.is_err() {
+        println!("swap: no swap disk");
+        return;
+    }
+    let nslot = (disk.capacity() / SECTORS).min(SWAPPAGES);
+    drop(disk);
+    SLOTS.lock().nslot = nslot;
+}
+
+/// takes a free slot.
+fn alloc() -> Option<usize> {
+    let mut slots = SLOTS.lock();
+    let nslot = slots.nslot;
+    let slot = (0..nslot)
+        .map(|i| (slots.next + i) % nslot)
+        .find(|&slot| slots.refs[slot] == 0)?;
+    slots.refs[slot] = 1;
+    slots.nused += 1;
+    slots.next = (slot + 1) % nslot;
+    Some(slot)
+}
+
+/// takes another reference to `slot`.
+pub fn share(slot: usize) {
+    let mut slots = SLOTS.lock();
+    slots.refs[slot] = slots.refs[slot]
+        .checked_add(1)
+        .expect("swap share: too many references");
+}
+
+/// drops a reference to `slot`, which is free once the last one is.
+pub fn put(slot: usize) {
+    let mut slots = SLOTS.lock();
+    if slots.refs[slot] == 0 {
+        panic!("swap put: slot {} is free", slot);
+    }
+    slots.refs[slot] -= 1;
+    if slots.refs[slot] == 0 {
+        slots.nused -= 1;
+    }
+}
+
+/// the slots of the swap area, and those in use.
+pub fn stats() -> (usize, usize) {
+    let slots = SLOTS.lock();
+    let stats = (slots.nslot, slots.nused);
+    drop(slots);
+    stats
+}
+
+/// reads the page in `slot` into the page at `pa`.
+pub fn read(slot: usize, pa: usize) {
+    let guard = IO.lock();
+    SWAP_DISK.read_at(slot * SECTORS, pa, PAGESIZE);
+    drop(guard);
+}
+
+/// what the hand did in an address space.
+enum Scan {
+    // dropped a clean page.
+    Dropped,
+    // took the dirty page at the physical address, which is to be written out to the slot.
+    Evicted(usize, usize),
+    // passed SCAN pages without taking any.
+    Passed,
+    // came to its end.
+    End,
+}
+
+/// frees up to `target` pages of user memory, taking them from the processes as the hand moves.
+/// gives up once the hand has gone around twice, which gives each page its second chance.
+/// returns the number of pages freed.
+pub fn reclaim(target: usize) -> usize {
+    let p = unsafe { CPU_TABLE.my_proc() };
+    let mut hand = IO.lock();
+    let mut freed = 0;
+    let mut passed = 0;
+    while freed < target && passed <= 2 * NPROC {
+        let index = hand.index;
+        let done = unsafe { PROCESS_TABLE.with_mm(index, p, |mm| scan(mm, &mut hand.va)) };
+        match done.unwrap_or(Scan::End) {
+            Scan::Dropped => freed += 1,
+            Scan::Evicted(pa, slot) => {
+                // nobody maps the page anymore, and a fault on it waits for `IO` to read it in.
+                SWAP_DISK.write_at(slot * SECTORS, pa, PAGESIZE);
+                put_page(pa);
+                freed += 1;
+            }
+            Scan::Passed => {}
+            Scan::End => {
+                hand.index = (index + 1) % NPROC;
+                hand.va = 0;
+                passed += 1;
+            }
+        }
+    }
+    drop(hand);
+    freed
+}
+
+/// moves the hand from `va` over the user pages of `mm`, until it takes one or has passed SCAN.
+fn scan(mm: &mut Mm, va: &mut usize) -> Scan {
+    for _ in 0..SCAN {
+        let page = match mm.page_table.next_user_page(*va, MAXVA) {
+            Some(page) => page,
+            None => return Scan::End,
+        };
+        *va = page + PAGESIZE;
+        if !mm.swappable(page) || mm.page_table.take_accessed(page) {
+            continue;
+        }
+        let (pa, flag) = mm.page_table.lookup(page).unwrap();
+        if is_shared_page(pa) || futex::is_waited(pa) {
+            continue;
+        }
+        if !flag.contains(PteFlag::DIRTY) {
+            mm.page_table
+                .unmap_pages(page, 1, true)
+                .expect("swap: cannot unmap");
+            return Scan::Dropped;
+        }
+        if let Some(slot) = alloc() {
+            return Scan::Evicted(mm.page_table.swap_out(page, slot), slot);
+        }
+    }
+    Scan::Passed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        page_table::{Page, PageTable, SinglePage},
+        param::TRAPFRAME,
+    };
+
+    use super::*;
+
+    /// an address space of its own, with a page of heap at 0. returns it with its trapframe.
+    fn heap_mm() -> (Mm, usize) {
+        let tf = unsafe { SinglePage::alloc_into_raw() }.expect("trapframe") as usize;
+        let pgt = PageTable::alloc_user_page_table(tf).expect("cannot alloc user page table");
+        let mut mm = Mm::new(pgt);
+        mm.sz = PAGESIZE;
+        (mm, tf)
+    }
+
+    fn free_mm(mut mm: Mm, tf: usize) {
+        mm.page_table
+            .unmap_pages(TRAPFRAME, 1, false)
+            .expect("cannot unmap trapframe");
+        drop(mm);
+        unsafe { SinglePage::free_from_raw(tf as *mut SinglePage) };
+    }
+
+    #[test_case]
+    fn dirty_page_round_trip() {
+        let (mut mm, tf) = heap_mm();
+        let code = [b's', b'w', b'a', b'p'];
+        mm.page_table.uvm_init(&code).expect("uvm_init");
+
+        let mut va = 0;
+        let (pa, slot) = match scan(&mut mm, &mut va) {
+            Scan::Evicted(pa, slot) => (pa, slot),
+            _ => panic!("the dirty page is not taken"),
+        };
+        assert_eq!(PAGESIZE, va);
+        assert_eq!(Some(slot), mm.page_table.swapped(0));
+        assert!(matches!(scan(&mut mm, &mut va), Scan::End));
+
+        // written out and freed as reclaim() does, then read back in as a fault does.
+        SWAP_DISK.write_at(slot * SECTORS, pa, PAGESIZE);
+        put_page(pa);
+        let pa = unsafe { SinglePage::alloc_into_raw() }.expect("page") as usize;
+        read(slot, pa);
+        assert_eq!(&code, unsafe { &*(pa as *const [u8; 4]) });
+        put_page(pa);
+
+        // the slot is freed with the address space.
+        let (_, used) = stats();
+        free_mm(mm, tf);
+        assert_eq!(used - 1, stats().1);
+    }
+
+    #[test_case]
+    fn clean_page_is_dropped() {
+        let (mut mm, tf) = heap_mm();
+        mm.page_table
+            .uvm_lazy_alloc(0, PAGESIZE, PAGESIZE)
+            .expect("uvm_lazy_alloc");
+        let (_, used) = stats();
+
+        // still zero, so it is faulted in again rather than written out.
+        let mut va = 0;
+        assert!(matches!(scan(&mut mm, &mut va), Scan::Dropped));
+        assert_eq!(None, mm.page_table.lookup(0));
+        assert_eq!(None, mm.page_table.swapped(0));
+        assert_eq!(used, stats().1);
+        assert!(matches!(scan(&mut mm, &mut va), Scan::End));
+
+        free_mm(mm, tf);
+    }
+}
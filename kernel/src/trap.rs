@@ -3,12 +3,22 @@ use core::mem;
 use crate::{
     cpu::{CpuTable, CPU_TABLE},
     e1000::E1000,
-    param::{E1000_IRQ, TRAMPOLINE, TRAPFRAME, UART0_IRQ, VIRTIO0_IRQ},
-    plic,
-    register::{self, scause::ScauseType},
+    kalloc::ENOMEM,
+    oom,
+    page_table::PteFlag,
+    param::{E1000_IRQ, TRAMPOLINE, UART0_IRQ, VIRTIO0_IRQ, VIRTIO1_IRQ},
+    plic, println,
+    proc::{signal, Proc},
+    process::PROCESS_TABLE,
+    random,
+    register::{
+        self,
+        clint::{self, MTIME_FREQ},
+        scause::ScauseType,
+    },
     spinlock::SpinLock,
     uart,
-    virtio::DISK,
+    virtio::{DISK, SWAP_DISK},
 };
 
 /// set up to take exceptions and traps while in the kernel.
@@ -57,6 +67,7 @@ pub unsafe extern "C" fn usertrap() {
 
     // save user program counter
     let p = CPU_TABLE.my_proc();
+    p.enter_kernel();
     let pdata = p.data.get_mut();
     pdata.set_epc(register::sepc::read());
 
@@ -77,11 +88,13 @@ unsafe fn handle_trap(is_user: bool) {
 
             register::sip::clear_ssip();
 
-            CPU_TABLE.my_cpu_mut().yield_process();
+            CPU_TABLE.my_cpu_mut().tick(is_user);
         }
         ScauseType::IntSExt => {
             // this is a supervisor external interrupt, via PLIC.
             let irq = plic::claim();
+            // when devices interrupt is hard to predict.
+            random::stir();
 
             match irq as usize {
                 UART0_IRQ => {
@@ -90,6 +103,9 @@ unsafe fn handle_trap(is_user: bool) {
                 VIRTIO0_IRQ => {
                     DISK.lock().intr();
                 }
+                VIRTIO1_IRQ => {
+                    SWAP_DISK.lock().intr();
+                }
                 E1000_IRQ => {
                     E1000.intr();
                 }
@@ -107,41 +123,138 @@ unsafe fn handle_trap(is_user: bool) {
                 panic!("kerneltrap: handling syscall");
             }
             let p = CPU_TABLE.my_proc();
+            if p.is_killed() {
+                PROCESS_TABLE.exit(p, -1);
+            }
             p.syscall();
         }
-        ScauseType::ExcPageLoad | ScauseType::ExcPageStoreAtomic => {
-            if is_user {
-                let fault_addr = register::stval::read();
-                if let Err(e) = CPU_TABLE.my_proc().data.get_mut().lazy_mmap(fault_addr) {
-                    panic!(
-                        "handle_trap: failed to lazy allocate. {}. scause {:?} stval {:#x}",
-                        e, scause, fault_addr
-                    );
+        ScauseType::ExcPageInstruction
+        | ScauseType::ExcPageLoad
+        | ScauseType::ExcPageStoreAtomic => {
+            let fault_addr = register::stval::read();
+            if !is_user {
+                panic!(
+                    "kerneltrap: page fault {:?} stval {:#x}",
+                    scause, fault_addr
+                );
+            }
+            let access = match scause {
+                ScauseType::ExcPageInstruction => PteFlag::EXEC,
+                ScauseType::ExcPageLoad => PteFlag::READ,
+                _ => PteFlag::WRITE,
+            };
+            let p = CPU_TABLE.my_proc();
+            p.inner.lock().usage.nfault += 1;
+            match p.data.get_mut().handle_page_fault(fault_addr, access) {
+                Ok(()) => {}
+                Err(e) if e == ENOMEM => oom::recover(p),
+                Err(e) => {
+                    println!("usertrap: failed to handle page fault. {}", e);
+                    user_fault(p);
                 }
             }
         }
         ScauseType::Unknown(v) => {
-            panic!(
-                "handle_trap: scause {:#x} stval {:#x} is_user={}",
-                v,
-                register::stval::read(),
-                is_user,
-            );
+            if !is_user {
+                panic!(
+                    "handle_trap: scause {:#x} stval {:#x} is_user={}",
+                    v,
+                    register::stval::read(),
+                    is_user,
+                );
+            }
+            // illegal instruction, misaligned access, instruction page fault, ...
+            user_fault(CPU_TABLE.my_proc());
         }
     }
 }
 
+/// the user process caused an exception that the kernel cannot resolve.
+/// rather than taking down the whole machine, report it and send the process a signal (SIGSEGV,
+/// SIGILL, ...). unless a handler catches it, the process exits with status -1 on the way back to
+/// user space.
+unsafe fn user_fault(p: &mut Proc) {
+    let scause = register::scause::read();
+    let mut guard = p.inner.lock();
+    println!(
+        "usertrap: unexpected scause {:#x} pid={} sepc={:#x} stval={:#x}",
+        scause,
+        guard.pid,
+        p.data.get_mut().get_epc(),
+        register::stval::read(),
+    );
+    signal::force(p, &mut guard, signal::from_scause(scause));
+    drop(guard);
+}
+
 static TICKS: SpinLock<usize> = SpinLock::new(0, "ticks");
 
+/// the number of clock tick interrupts since boot.
+pub fn ticks() -> usize {
+    let guard = TICKS.lock();
+    let ticks = *guard;
+    drop(guard);
+    ticks
+}
+
+/// sleeps the process `p` for `n` clock ticks.
+pub fn sleep_ticks(p: &mut Proc, n: usize) -> Result<(), &'static str> {
+    let mut guard = TICKS.lock();
+    let ticks0 = *guard;
+    while *guard - ticks0 < n {
+        if p.is_killed() {
+            drop(guard);
+            return Err("sleep: killed");
+        }
+        guard = p.sleep(&TICKS as *const _ as usize, guard);
+    }
+    drop(guard);
+    Ok(())
+}
+
 fn clock_intr() {
     let mut guard = TICKS.lock();
     *guard += 1;
-    drop(guard)
+    let now = *guard;
+    unsafe { PROCESS_TABLE.wakeup(&TICKS as *const _ as usize) };
+    drop(guard);
+
+    unsafe { PROCESS_TABLE.expire_timers(now) };
+}
+
+pub const CLOCK_REALTIME: i32 = 0;
+pub const CLOCK_MONOTONIC: i32 = 1;
+
+#[repr(C)]
+pub struct TimeSpec {
+    pub sec: u64,
+    pub nsec: u64,
+}
+
+impl TimeSpec {
+    /// the time elapsed since boot, read from the CLINT's mtime register.
+    /// there is no real-time clock, so CLOCK_REALTIME counts from boot as well.
+    pub fn now() -> Self {
+        let mtime = unsafe { clint::read_mtime() };
+        Self {
+            sec: mtime / MTIME_FREQ,
+            nsec: (mtime % MTIME_FREQ) * (1_000_000_000 / MTIME_FREQ),
+        }
+    }
 }
 
 /// return to user space
 pub unsafe fn user_trap_ret() {
     let p = CPU_TABLE.my_proc();
+
+    // this may redirect the process to a signal handler, or kill it.
+    signal::deliver(p);
+
+    // a process that has been killed while it was in the kernel must not run any more user code.
+    if p.is_killed() {
+        PROCESS_TABLE.exit(p, -1);
+    }
+
     let pdata = p.data.get_mut();
 
     // about to switch the destination of traps from kerneltrap() to usertrap(),
@@ -157,6 +270,7 @@ pub unsafe fn user_trap_ret() {
     register::stvec::write(TRAMPOLINE + (uservec as usize - trampoline as usize));
 
     let satp = pdata.setup_user_ret();
+    p.enter_user(satp);
     register::sstatus::intr_on_to_user();
     register::sepc::write(pdata.get_epc());
 
@@ -169,5 +283,5 @@ pub unsafe fn user_trap_ret() {
     let user_ret_virt = TRAMPOLINE + (userret as usize - trampoline as usize);
     let user_ret_virt: extern "C" fn(usize, usize) -> ! = mem::transmute(user_ret_virt);
 
-    user_ret_virt(TRAPFRAME, satp);
+    user_ret_virt(pdata.get_trapframe_va(), satp);
 }
@@ -1,6 +1,7 @@
-/// driver for qemu's virtio disk device.
+/// driver for qemu's virtio disk devices.
 /// uses qemu's mmio interface to virtio.
 /// qemu presents a "legacy" virtio interface.
+/// the file system is on the first disk, and the swap area on the second one.
 
 const NUM: usize = 8; // this many virtio descriptors. must be a power of two.
 
@@ -14,7 +15,7 @@ use array_macro::array;
 use crate::{
     bio::{BufGuard, BSIZE},
     cpu::CPU_TABLE,
-    param::{PAGESIZE, VIRTIO0},
+    param::{PAGESIZE, VIRTIO0, VIRTIO1},
     process::PROCESS_TABLE,
     spinlock::SpinLock,
 };
@@ -137,12 +138,15 @@ pub struct Disk {
     used_idx: u32,
     info: [Info; NUM],
     ops: [BlkReq; NUM],
+    // the address of the mmio registers.
+    base: usize,
 }
 
-pub static DISK: SpinLock<Disk> = SpinLock::new(Disk::new(), "disk");
+pub static DISK: SpinLock<Disk> = SpinLock::new(Disk::new(VIRTIO0), "disk");
+pub static SWAP_DISK: SpinLock<Disk> = SpinLock::new(Disk::new(VIRTIO1), "swap_disk");
 
 impl Disk {
-    const fn new() -> Self {
+    const fn new(base: usize) -> Self {
         Self {
             pad1: PaddedPage {},
             desc: array![_ => Desc::new(); NUM],
@@ -153,26 +157,27 @@ impl Disk {
             used_idx: 0,
             info: array![_ => Info::new(); NUM],
             ops: array![_ => BlkReq::new(); NUM],
+            base,
         }
     }
 
-    pub unsafe fn init(&mut self) {
-        if read(VIRTIO_MMIO_MAGIC_VALUE) != 0x74726976
-            || read(VIRTIO_MMIO_VERSION) != 1
-            || read(VIRTIO_MMIO_DEVICE_ID) != 2
-            || read(VIRTIO_MMIO_VENDOR_ID) != 0x554d4551
+    pub unsafe fn init(&mut self) -> Result<(), &'static str> {
+        if self.read_reg(VIRTIO_MMIO_MAGIC_VALUE) != 0x74726976
+            || self.read_reg(VIRTIO_MMIO_VERSION) != 1
+            || self.read_reg(VIRTIO_MMIO_DEVICE_ID) != 2
+            || self.read_reg(VIRTIO_MMIO_VENDOR_ID) != 0x554d4551
         {
-            panic!("could not find virtio disk");
+            return Err("could not find virtio disk");
         }
 
         let mut status: u32 = 0;
         status |= VIRTIO_CONFIG_S_ACKNOWLEDGE;
-        write(VIRTIO_MMIO_STATUS, status);
+        self.write_reg(VIRTIO_MMIO_STATUS, status);
         status |= VIRTIO_CONFIG_S_DRIVER;
-        write(VIRTIO_MMIO_STATUS, status);
+        self.write_reg(VIRTIO_MMIO_STATUS, status);
 
         // negotiate features
-        let mut features: u32 = read(VIRTIO_MMIO_DEVICE_FEATURES);
+        let mut features: u32 = self.read_reg(VIRTIO_MMIO_DEVICE_FEATURES);
         features &= !(1u32 << VIRTIO_BLK_F_RO);
         features &= !(1u32 << VIRTIO_BLK_F_SCSI);
         features &= !(1u32 << VIRTIO_BLK_F_CONFIG_WCE);
@@ -180,40 +185,61 @@ impl Disk {
         features &= !(1u32 << VIRTIO_F_ANY_LAYOUT);
         features &= !(1u32 << VIRTIO_RING_F_EVENT_IDX);
         features &= !(1u32 << VIRTIO_RING_F_INDIRECT_DESC);
-        write(VIRTIO_MMIO_DRIVER_FEATURES, features);
+        self.write_reg(VIRTIO_MMIO_DRIVER_FEATURES, features);
 
         // tell device that feature negotiation is complete.
         status |= VIRTIO_CONFIG_S_FEATURES_OK;
-        write(VIRTIO_MMIO_STATUS, status);
+        self.write_reg(VIRTIO_MMIO_STATUS, status);
 
         // tell device we're complete ready.
         status |= VIRTIO_CONFIG_S_DRIVER_OK;
-        write(VIRTIO_MMIO_STATUS, status);
+        self.write_reg(VIRTIO_MMIO_STATUS, status);
 
-        write(VIRTIO_MMIO_GUEST_PAGE_SIZE, PAGESIZE as u32);
+        self.write_reg(VIRTIO_MMIO_GUEST_PAGE_SIZE, PAGESIZE as u32);
 
         // initialize queue 0.
-        write(VIRTIO_MMIO_QUEUE_SEL, 0);
-        let max: u32 = read(VIRTIO_MMIO_QUEUE_NUM_MAX);
+        self.write_reg(VIRTIO_MMIO_QUEUE_SEL, 0);
+        let max: u32 = self.read_reg(VIRTIO_MMIO_QUEUE_NUM_MAX);
         if max == 0 {
             panic!("virtio disk has no queue 0");
         } else if max < NUM as u32 {
             panic!("virtio disk max queue too short");
         }
-        write(VIRTIO_MMIO_QUEUE_NUM, NUM as u32);
+        self.write_reg(VIRTIO_MMIO_QUEUE_NUM, NUM as u32);
 
         let pfn: usize = (self as *const Disk as usize) >> 12;
-        write(VIRTIO_MMIO_QUEUE_PFN, u32::try_from(pfn).unwrap());
+        self.write_reg(VIRTIO_MMIO_QUEUE_PFN, u32::try_from(pfn).unwrap());
 
         // all NUM descriptors start out unused.
         self.free.iter_mut().for_each(|v| *v = true);
+        Ok(())
+    }
+
+    /// the size of the disk in 512-byte sectors.
+    pub fn capacity(&self) -> usize {
+        unsafe {
+            self.read_reg(VIRTIO_MMIO_CONFIG) as usize
+                | (self.read_reg(VIRTIO_MMIO_CONFIG + 4) as usize) << 32
+        }
+    }
+
+    #[inline]
+    unsafe fn read_reg(&self, offset: usize) -> u32 {
+        let src = (self.base + offset) as *const u32;
+        ptr::read_volatile(src)
+    }
+
+    #[inline]
+    unsafe fn write_reg(&self, offset: usize, v: u32) {
+        let dst = (self.base + offset) as *mut u32;
+        ptr::write_volatile(dst, v);
     }
 
     pub fn intr(&mut self) {
         unsafe {
-            write(
+            self.write_reg(
                 VIRTIO_MMIO_INTERRUPT_ACK,
-                read(VIRTIO_MMIO_INTERRUPT_STATUS) & 0x3,
+                self.read_reg(VIRTIO_MMIO_INTERRUPT_STATUS) & 0x3,
             )
         };
 
@@ -306,18 +332,31 @@ impl Disk {
 
 impl SpinLock<Disk> {
     pub fn read(&self, buf: &mut BufGuard) {
-        self.rw(buf, false);
+        let sector = buf.blockno as usize * (BSIZE / 512);
+        self.rw(sector, buf.data_ptr_mut() as usize, BSIZE, false);
     }
 
     pub fn write(&self, buf: &mut BufGuard) {
-        self.rw(buf, true);
+        let sector = buf.blockno as usize * (BSIZE / 512);
+        self.rw(sector, buf.data_ptr_mut() as usize, BSIZE, true);
+    }
+
+    /// reads `len` bytes from `sector` into the memory at `addr`, which is also the channel the
+    /// reader sleeps on until the disk is done.
+    pub fn read_at(&self, sector: usize, addr: usize, len: usize) {
+        self.rw(sector, addr, len, false);
+    }
+
+    /// writes `len` bytes at `addr` to `sector`.
+    pub fn write_at(&self, sector: usize, addr: usize, len: usize) {
+        self.rw(sector, addr, len, true);
     }
 
     /// block operations use three descriptors:
     /// one for type/reserved/sector
     /// one for the data
     /// one for a 1-byte status result
-    fn rw(&self, buf: &mut BufGuard, writing: bool) {
+    fn rw(&self, sector: usize, addr: usize, len: usize, writing: bool) {
         let mut guard = self.lock();
 
         // allocate three descriptors
@@ -341,7 +380,7 @@ impl SpinLock<Disk> {
             VIRTIO_BLK_T_IN
         };
         buf0.reserved = 0;
-        buf0.sector = (buf.blockno as usize * (BSIZE / 512)) as usize;
+        buf0.sector = sector;
 
         // buf0 (type/reserved/sector)
         guard.desc[idx[0]].addr = buf0 as *mut _ as usize;
@@ -350,9 +389,9 @@ impl SpinLock<Disk> {
         guard.desc[idx[0]].next = idx[1].try_into().unwrap();
 
         // data
-        let buf_ptr = buf.data_ptr_mut();
-        guard.desc[idx[1]].addr = buf_ptr as usize;
-        guard.desc[idx[1]].len = BSIZE.try_into().unwrap();
+        let buf_ptr = addr;
+        guard.desc[idx[1]].addr = buf_ptr;
+        guard.desc[idx[1]].len = len.try_into().unwrap();
         guard.desc[idx[1]].flags = if writing { 0 } else { VRING_DESC_F_WRITE };
         guard.desc[idx[1]].flags |= VRING_DESC_F_NEXT;
         guard.desc[idx[1]].next = idx[2].try_into().unwrap();
@@ -367,7 +406,7 @@ impl SpinLock<Disk> {
 
         // record struct buf for intr()
         guard.info[idx[0]].disk = true;
-        guard.info[idx[0]].buf_chan = Some(buf_ptr as usize);
+        guard.info[idx[0]].buf_chan = Some(buf_ptr);
 
         // tell the device the first index in our chain of descriptors.
         let avail_idx = guard.avail.idx as usize % NUM;
@@ -381,36 +420,24 @@ impl SpinLock<Disk> {
         fence(Ordering::SeqCst);
 
         unsafe {
-            write(VIRTIO_MMIO_QUEUE_NOTIFY, 0);
+            guard.write_reg(VIRTIO_MMIO_QUEUE_NOTIFY, 0);
         }
 
         // wait for intr() to say request has finised
         while guard.info[idx[0]].disk {
             unsafe {
-                guard = CPU_TABLE.my_proc().sleep(buf_ptr as usize, guard);
+                guard = CPU_TABLE.my_proc().sleep(buf_ptr, guard);
             }
         }
         // tidy up
         let res = guard.info[idx[0]].buf_chan.take();
-        assert_eq!(res.unwrap(), buf_ptr as usize);
+        assert_eq!(res.unwrap(), buf_ptr);
         guard.free_chain(idx[0]);
 
         drop(guard);
     }
 }
 
-#[inline]
-unsafe fn read(offset: usize) -> u32 {
-    let src = (VIRTIO0 + offset) as *const u32;
-    ptr::read_volatile(src)
-}
-
-#[inline]
-unsafe fn write(offset: usize, v: u32) {
-    let dst = (VIRTIO0 + offset) as *mut u32;
-    ptr::write_volatile(dst, v);
-}
-
 const VIRTIO_MMIO_MAGIC_VALUE: usize = 0x000;
 const VIRTIO_MMIO_VERSION: usize = 0x004;
 const VIRTIO_MMIO_DEVICE_ID: usize = 0x008; // device type; 1 is net, 2 is disk
@@ -428,6 +455,7 @@ const VIRTIO_MMIO_QUEUE_NOTIFY: usize = 0x050;
 const VIRTIO_MMIO_INTERRUPT_STATUS: usize = 0x060;
 const VIRTIO_MMIO_INTERRUPT_ACK: usize = 0x064;
 const VIRTIO_MMIO_STATUS: usize = 0x070; // read/write
+const VIRTIO_MMIO_CONFIG: usize = 0x100; // the device's configuration, the capacity first
 
 const VIRTIO_CONFIG_S_ACKNOWLEDGE: u32 = 1;
 const VIRTIO_CONFIG_S_DRIVER: u32 = 2;
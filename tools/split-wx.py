@@ -0,0 +1,256 @@
+# Moves the data of a prebuilt C user program to pages of its own, so that it is loaded as a
+# read-only executable segment and a writable one instead of a single RWX segment.
+#
+# The programs are linked with -N at 0, with .sdata, .sbss and .bss right after .rodata, and
+# come with no relocations. The data is moved up by whole pages, which leaves the low 12 bits
+# of each address as they are, so only the auipc of each pc-relative reference to the data
+# needs its upper 20 bits bumped. The code must be medany, with no gp-relative accesses, and no
+# initialized data may point into the moved data. The debug sections are dropped.
+#
+#   python3 tools/split-wx.py user/_sh [...]
+
+import struct
+import sys
+
+PAGE = 0x1000
+SHF_WRITE = 0x1
+SHF_ALLOC = 0x2
+SHF_EXECINSTR = 0x4
+SHT_SYMTAB = 2
+SHT_STRTAB = 3
+SHT_NOBITS = 8
+SHN_LORESERVE = 0xff00
+PT_LOAD = 1
+PF_X, PF_W, PF_R = 1, 2, 4
+
+EHDR = struct.Struct('<16sHHIQQQIHHHHHH')
+PHDR = struct.Struct('<IIQQQQQQ')
+SHDR = struct.Struct('<IIQQQQIIQQ')
+SYM = struct.Struct('<IBBHQQ')
+
+
+def align_up(x, a):
+    return (x + a - 1) // a * a
+
+
+def align_down(x, a):
+    return x // a * a
+
+
+def sext(x, bits):
+    return x - (1 << bits) if x & (1 << (bits - 1)) else x
+
+
+def cstr(buf, off):
+    return buf[off:buf.index(b'\0', off)].decode()
+
+
+def written_regs(insn, size):
+    """the registers an instruction may write, conservatively for the compressed ones."""
+    if size == 4:
+        opcode = insn & 0x7f
+        if opcode in (0x23, 0x27, 0x63):  # stores and branches
+            return set()
+        return {(insn >> 7) & 31}
+    # the rd/rs1 field of the CI and CR formats, and the rd' fields of the others.
+    return {(insn >> 7) & 31, 8 + ((insn >> 2) & 7), 8 + ((insn >> 7) & 7)}
+
+
+def is_jump(insn, size):
+    if size == 4:
+        return insn & 0x7f in (0x63, 0x67, 0x6f)
+    op, funct3 = insn & 3, insn >> 13
+    return (op == 1 and funct3 in (5, 6, 7)) or (op == 2 and funct3 == 4)
+
+
+def decode(text, base):
+    """yields (pc, insn, size) over the instructions of `text`, loaded at `base`."""
+    off = 0
+    while off + 2 <= len(text):
+        half = struct.unpack_from('<H', text, off)[0]
+        if half & 3 == 3:
+            yield base + off, struct.unpack_from('<I', text, off)[0], 4
+            off += 4
+        else:
+            yield base + off, half, 2
+            off += 2
+
+
+def pcrel_targets(insns, i):
+    """the addresses the auipc at insns[i] is paired with, by the instructions that use the
+    register it sets before it is set again or control leaves the block."""
+    pc, insn, _ = insns[i]
+    rd = (insn >> 7) & 31
+    hi = pc + sext(insn & 0xfffff000, 32)
+    targets = []
+    for _, use, size in insns[i + 1:i + 16]:
+        if size == 4:
+            opcode, rs1 = use & 0x7f, (use >> 15) & 31
+            if rs1 == rd:
+                if opcode in (0x03, 0x07, 0x67) or (opcode == 0x13 and (use >> 12) & 7 == 0):
+                    targets.append(hi + sext(use >> 20, 12))
+                elif opcode in (0x23, 0x27):
+                    imm = ((use >> 25) << 5) | ((use >> 7) & 31)
+                    targets.append(hi + sext(imm, 12))
+        if rd in written_regs(use, size) or is_jump(use, size):
+            break
+    return targets
+
+
+def split(path):
+    elf = bytearray(open(path, 'rb').read())
+    ehdr = list(EHDR.unpack_from(elf, 0))
+    shoff, shentsize, shnum, shstrndx = ehdr[6], ehdr[11], ehdr[12], ehdr[13]
+    shdrs = [list(SHDR.unpack_from(elf, shoff + i * shentsize)) for i in range(shnum)]
+    shstr = shdrs[shstrndx]
+    names = [cstr(elf, shstr[4] + sh[0]) for sh in shdrs]
+
+    alloc = sorted((i for i, sh in enumerate(shdrs) if sh[2] & SHF_ALLOC), key=lambda i: shdrs[i][3])
+    # .text is marked writable by -N as well.
+    rw = [i for i in alloc if shdrs[i][2] & (SHF_WRITE | SHF_EXECINSTR) == SHF_WRITE]
+    ro = [i for i in alloc if i not in rw]
+    ro_end = max(shdrs[i][3] + shdrs[i][5] for i in ro)
+    if rw and min(shdrs[i][3] for i in rw) < ro_end:
+        sys.exit(f'{path}: the writable sections are not after the others')
+
+    if rw:
+        old_lo = min(shdrs[i][3] for i in rw)
+        old_hi = max(shdrs[i][3] + shdrs[i][5] for i in rw)
+        delta = align_up(ro_end, PAGE) - align_down(old_lo, PAGE)
+    else:
+        old_lo = old_hi = ro_end
+        delta = 0
+
+    def moved(addr):
+        return old_lo <= addr <= old_hi and delta != 0
+
+    # the read-only image, with the references to the data moved along with it.
+    image = bytearray(ro_end)
+    for i in ro:
+        sh = shdrs[i]
+        if sh[1] != SHT_NOBITS:
+            image[sh[3]:sh[3] + sh[5]] = elf[sh[4]:sh[4] + sh[5]]
+    patched = 0
+    for i in ro:
+        sh = shdrs[i]
+        if not sh[2] & SHF_EXECINSTR:
+            continue
+        insns = list(decode(image[sh[3]:sh[3] + sh[5]], sh[3]))
+        for n, (pc, insn, size) in enumerate(insns):
+            if size == 4 and (insn >> 15) & 31 == 3 and insn & 0x7f not in (0x37, 0x17, 0x6f):
+                sys.exit(f'{path}: gp-relative access at {pc:#x}')
+            if size != 4 or insn & 0x7f != 0x17:
+                continue
+            targets = pcrel_targets(insns, n)
+            hits = [moved(t) for t in targets]
+            if any(hits) != all(hits):
+                sys.exit(f'{path}: auipc at {pc:#x} refers both to the data and elsewhere')
+            if hits and hits[0]:
+                insn = (insn + delta) & 0xffffffff
+                struct.pack_into('<I', image, pc, insn)
+                patched += 1
+
+    data_start = align_down(old_lo, PAGE) + delta
+    data = bytearray()
+    for i in rw:
+        sh = shdrs[i]
+        if sh[1] != SHT_NOBITS:
+            data[len(data):] = bytes(sh[3] + delta - data_start - len(data))
+            data += elf[sh[4]:sh[4] + sh[5]]
+
+    # the pointers to the data objects in initialized data, as in `char *args[] = { big, 0 }`.
+    objects = set()
+    for sh in shdrs:
+        if sh[1] == SHT_SYMTAB:
+            for off in range(sh[4], sh[4] + sh[5], SYM.size):
+                _, _, _, shndx, value, _ = SYM.unpack_from(elf, off)
+                if shndx in rw:
+                    objects.add(value)
+    pointers = 0
+    for i in alloc:
+        sh = shdrs[i]
+        if sh[1] == SHT_NOBITS or sh[2] & SHF_EXECINSTR:
+            continue
+        buf, start = (data, sh[3] + delta - data_start) if i in rw else (image, sh[3])
+        for off in range(start, start + sh[5] - 7, 8):
+            value = struct.unpack_from('<Q', buf, off)[0]
+            if not moved(value):
+                continue
+            if value not in objects:
+                sys.exit(f'{path}: {names[i]}+{off - start:#x} may point into the data')
+            struct.pack_into('<Q', buf, off, value + delta)
+            pointers += 1
+
+    # the ELF header and the program headers, then the segments, each on a page of its own.
+    phnum = 2 if rw else 1
+    out = bytearray(PAGE)
+    text_off = len(out)
+    out += image
+    out[len(out):] = bytes(align_up(len(out), PAGE) - len(out))
+    data_off = len(out)
+    out += data
+    phdrs = [(PT_LOAD, PF_R | PF_X, text_off, 0, 0, len(image), len(image), PAGE)]
+    if rw:
+        memsz = old_hi + delta - data_start
+        phdrs.append((PT_LOAD, PF_R | PF_W, data_off, data_start, data_start, len(data), memsz, PAGE))
+
+    # the sections kept: the loaded ones, and the symbols.
+    keep = [0] + alloc + [i for i, sh in enumerate(shdrs) if sh[1] == SHT_SYMTAB]
+    symtab = keep[-1] if shdrs[keep[-1]][1] == SHT_SYMTAB else None
+    if symtab is not None:
+        keep.append(shdrs[symtab][6])
+    new_index = {old: new for new, old in enumerate(keep)}
+    shstrtab = bytearray(b'\0')
+    new_shdrs = []
+    for i in keep:
+        sh = list(shdrs[i])
+        name = len(shstrtab) if i else 0
+        if i:
+            shstrtab += names[i].encode() + b'\0'
+        sh[0] = name
+        if i == 0:
+            pass
+        elif i in ro:
+            sh[2] &= ~SHF_WRITE
+            sh[4] = text_off + sh[3]
+        elif i in rw:
+            sh[3] += delta
+            sh[4] = data_off + sh[3] - data_start
+        elif i == symtab:
+            syms = bytearray(elf[sh[4]:sh[4] + sh[5]])
+            for off in range(0, len(syms), SYM.size):
+                name_off, info, other, shndx, value, size = SYM.unpack_from(syms, off)
+                if shndx in rw:
+                    value += delta
+                if 0 < shndx < SHN_LORESERVE:
+                    shndx = new_index.get(shndx, 0)
+                SYM.pack_into(syms, off, name_off, info, other, shndx, value, size)
+            out[len(out):] = bytes(align_up(len(out), 8) - len(out))
+            sh[4] = len(out)
+            sh[6] = new_index[sh[6]]
+            out += syms
+        else:
+            sh[4] = len(out)
+            out += elf[shdrs[i][4]:shdrs[i][4] + sh[5]]
+        new_shdrs.append(sh)
+    new_shdrs.append([len(shstrtab), SHT_STRTAB, 0, 0, len(out), 0, 0, 0, 1, 0])
+    shstrtab += b'.shstrtab\0'
+    new_shdrs[-1][5] = len(shstrtab)
+    out += shstrtab
+    out[len(out):] = bytes(align_up(len(out), 8) - len(out))
+
+    ehdr[5] = EHDR.size  # phoff
+    ehdr[6] = len(out)  # shoff
+    ehdr[10], ehdr[11], ehdr[12], ehdr[13] = phnum, SHDR.size, len(new_shdrs), len(new_shdrs) - 1
+    EHDR.pack_into(out, 0, *ehdr)
+    for n, ph in enumerate(phdrs):
+        PHDR.pack_into(out, EHDR.size + n * PHDR.size, *ph)
+    for sh in new_shdrs:
+        out += SHDR.pack(*sh)
+
+    open(path, 'wb').write(out)
+    print(f'{path}: data moved up by {delta:#x}, {patched} references and {pointers} pointers patched')
+
+
+for path in sys.argv[1:]:
+    split(path)
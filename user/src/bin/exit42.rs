@@ -48,4 +48,21 @@ mod tests {
         assert_eq!(pid, wpid);
         assert_eq!(84i32, status);
     }
+
+    #[test_case]
+    fn test_exit42_concurrently() {
+        // the children page in the same text, shared through the page cache.
+        for _ in 0..8 {
+            let pid = sys_fork();
+            assert!(pid >= 0);
+            if pid == 0 {
+                assert!(sys_exec(&["exit42\0".as_ptr(), ptr::null()]) > 0);
+            }
+        }
+        for _ in 0..8 {
+            let mut status = 0i32;
+            assert!(sys_wait(&mut status) > 0);
+            assert_eq!(42i32, status);
+        }
+    }
 }
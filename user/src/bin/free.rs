@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(xv6rs_user::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use xv6rs_user::{
+    entry_point,
+    meminfo::{MemInfo, MEMINFO_SITES, TAG_NAMES},
+    println,
+    syscall::sys_meminfo,
+    Args,
+};
+
+const KIB_PER_PAGE: usize = 4;
+
+entry_point!(main);
+fn main(args: &mut Args) -> Result<i32, &'static str> {
+    let flags = match args.nth(1) {
+        None => 0,
+        Some("-s") => MEMINFO_SITES,
+        Some(_) => return Err("usage: free [-s]"),
+    };
+
+    let mut info = MemInfo::default();
+    if sys_meminfo(&mut info, flags) < 0 {
+        return Err("free: meminfo failed");
+    }
+
+    println!("        total     used     free    slabs   cached (KiB)");
+    println!(
+        "mem: {:>8} {:>8} {:>8} {:>8} {:>8}",
+        info.total_pages * KIB_PER_PAGE,
+        (info.total_pages - info.free_pages) * KIB_PER_PAGE,
+        info.free_pages * KIB_PER_PAGE,
+        info.slab_pages * KIB_PER_PAGE,
+        info.cached_pages * KIB_PER_PAGE
+    );
+    println!(
+        "swap: {:>7} {:>8} {:>8}",
+        info.swap_pages * KIB_PER_PAGE,
+        info.swap_used * KIB_PER_PAGE,
+        (info.swap_pages - info.swap_used) * KIB_PER_PAGE
+    );
+    println!(
+        "kernel heap: {} bytes in use, {} at peak",
+        info.heap_bytes, info.heap_peak
+    );
+    println!("objects     live    total");
+    for (name, count) in TAG_NAMES.iter().zip(info.tags.iter()) {
+        println!("{:<8} {:>7} {:>8}", name, count.live, count.total);
+    }
+    Ok(0)
+}
@@ -10,7 +10,7 @@ use xv6rs_user::{
     entry_point,
     fcntl::O_RDWR,
     println,
-    syscall::{sys_dup, sys_exec, sys_fork, sys_mknod, sys_open, sys_wait},
+    syscall::{sys_dup, sys_exec, sys_fork, sys_mknod, sys_open, sys_setsid, sys_wait},
     Args,
 };
 
@@ -31,6 +31,9 @@ fn main(_: &mut Args) -> Result<i32, &'static str> {
             return Err("fork failed");
         }
         if pid == 0 {
+            // the shell leads a session of its own, so that ^C interrupts only the commands it
+            // runs.
+            sys_setsid();
             let cmd = "sh\0";
             sys_exec(&[cmd.as_ptr(), ptr::null()]);
             return Err("exec failed");
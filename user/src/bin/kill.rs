@@ -0,0 +1,28 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(xv6rs_user::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use xv6rs_user::{entry_point, println, signal::SIGTERM, syscall::sys_kill, Args};
+
+entry_point!(main);
+fn main(args: &mut Args) -> Result<i32, &'static str> {
+    let mut sig = SIGTERM;
+    let mut n = 0;
+    for arg in args.skip(1) {
+        if let Some(s) = arg.strip_prefix('-') {
+            sig = s.parse::<i32>().or_else(|_| Err("kill: signal must be a number"))?;
+            continue;
+        }
+        n += 1;
+        let pid = arg.parse::<i32>().or_else(|_| Err("kill: pid must be a number"))?;
+        if sys_kill(pid, sig) < 0 {
+            println!("kill: cannot kill {}", pid);
+        }
+    }
+    if n == 0 {
+        return Err("usage: kill [-sig] pid...");
+    }
+    Ok(0)
+}
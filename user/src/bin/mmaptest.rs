@@ -4,13 +4,23 @@
 #![test_runner(xv6rs_user::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
-use core::{ptr, slice::from_raw_parts};
+use core::{
+    ptr,
+    slice::{from_raw_parts, from_raw_parts_mut},
+};
 
 use xv6rs_user::{
     entry_point,
     fcntl::{O_CREATE, O_RDONLY, O_RDWR, O_WRONLY},
+    mman::{
+        MAP_ANONYMOUS, MAP_FAILED, MAP_FIXED, MAP_PRIVATE, MAP_SHARED, MS_SYNC, PROT_READ,
+        PROT_WRITE,
+    },
     println,
-    syscall::{sys_close, sys_mmap, sys_open, sys_read, sys_write},
+    syscall::{
+        sys_close, sys_exit, sys_fork, sys_mmap, sys_msync, sys_munmap, sys_open, sys_read,
+        sys_wait, sys_write,
+    },
     Args,
 };
 
@@ -27,18 +37,213 @@ fn main(_: &mut Args) -> Result<i32, &'static str> {
     println!();
 
     let size = PAGESIZE + PAGESIZE;
-    let buf = sys_mmap(ptr::null(), size, 1 << 1 | 1 << 2, 1 << 2, fd, 0);
+    let addr = sys_mmap(
+        ptr::null(),
+        size,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE,
+        fd,
+        0,
+    );
+    if addr == MAP_FAILED {
+        return Err("mmap");
+    }
     println!("mmap created!");
-    let buf = unsafe { from_raw_parts(buf as *const u8, size) };
+    let buf = unsafe { from_raw_parts(addr as *const u8, size) };
     println!("buf[0] {}", buf[0]);
     println!("buf[1] {}", buf[1]);
 
     println!("verify content");
     vaild_content(buf)?;
 
+    println!("munmap the first page");
+    if sys_munmap(addr as *const u8, PAGESIZE) < 0 {
+        return Err("munmap the first page");
+    }
+    // the second page is still mapped.
+    if buf[PAGESIZE] != b'A' {
+        return Err("the second page is lost after munmap the first");
+    }
+    if sys_munmap((addr + PAGESIZE) as *const u8, PAGESIZE) < 0 {
+        return Err("munmap the second page");
+    }
+    sys_close(fd);
+
+    shared_written_back_on_munmap(f)?;
+    shared_written_back_on_msync(f)?;
+    hint_and_fixed(f)?;
+    shared_written_back_on_exit(f)?;
+
     Ok(0)
 }
 
+fn map_shared(f: &str) -> Result<&'static mut [u8], &'static str> {
+    let fd = sys_open(f, O_RDWR);
+    if fd < 0 {
+        return Err("open");
+    }
+    let size = PAGESIZE + PAGESIZE;
+    let addr = sys_mmap(ptr::null(), size, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    // the mapping keeps the file open.
+    sys_close(fd);
+    if addr == MAP_FAILED {
+        return Err("mmap shared");
+    }
+    Ok(unsafe { from_raw_parts_mut(addr as *mut u8, size) })
+}
+
+// reads the whole file, which must still be 1.5 pages long.
+fn read_file(f: &str) -> Result<[u8; PAGESIZE * 2], &'static str> {
+    let fd = sys_open(f, O_RDONLY);
+    if fd < 0 {
+        return Err("open");
+    }
+    let mut data = [0u8; PAGESIZE * 2];
+    let n = sys_read(fd, &mut data);
+    sys_close(fd);
+    if n != (PAGESIZE + PAGESIZE / 2) as i32 {
+        return Err("file size changed");
+    }
+    Ok(data)
+}
+
+fn shared_written_back_on_munmap(f: &str) -> Result<(), &'static str> {
+    println!("write back on munmap");
+    let buf = map_shared(f)?;
+    buf[0] = b'B';
+    buf[PAGESIZE] = b'B';
+    // the bytes past the end of the file are not written back.
+    buf[PAGESIZE * 2 - 1] = b'B';
+    if sys_munmap(buf.as_ptr(), buf.len()) < 0 {
+        return Err("munmap shared");
+    }
+
+    let data = read_file(f)?;
+    if data[0] != b'B' || data[1] != b'A' || data[PAGESIZE] != b'B' {
+        return Err("content invalid after munmap");
+    }
+    Ok(())
+}
+
+fn shared_written_back_on_msync(f: &str) -> Result<(), &'static str> {
+    println!("write back on msync");
+    let buf = map_shared(f)?;
+    buf[1] = b'C';
+    if sys_msync(buf.as_ptr(), buf.len(), MS_SYNC) < 0 {
+        return Err("msync");
+    }
+
+    // still mapped, but the file has it.
+    let data = read_file(f)?;
+    if data[0] != b'B' || data[1] != b'C' {
+        return Err("content invalid after msync");
+    }
+    if sys_munmap(buf.as_ptr(), buf.len()) < 0 {
+        return Err("munmap shared");
+    }
+    Ok(())
+}
+
+fn hint_and_fixed(f: &str) -> Result<(), &'static str> {
+    println!("address hint and MAP_FIXED");
+    let fd = sys_open(f, O_RDONLY);
+    if fd < 0 {
+        return Err("open");
+    }
+    // the second page of the file only, at the address asked for.
+    let hint = 0x1000_0000;
+    let addr = sys_mmap(
+        hint as *const u8,
+        PAGESIZE,
+        PROT_READ,
+        MAP_PRIVATE,
+        fd,
+        PAGESIZE,
+    );
+    sys_close(fd);
+    if addr != hint {
+        return Err("mmap did not take the hint");
+    }
+    let buf = unsafe { from_raw_parts(addr as *const u8, PAGESIZE) };
+    if buf[0] != b'B' || buf[1] != b'A' || buf[PAGESIZE / 2] != 0 {
+        return Err("content invalid at the offset");
+    }
+
+    // a hint that overlaps a mapping is not taken.
+    let anon = PROT_READ | PROT_WRITE;
+    let other = sys_mmap(
+        hint as *const u8,
+        PAGESIZE,
+        anon,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+        -1,
+        0,
+    );
+    if other == MAP_FAILED || other == hint {
+        return Err("mmap took a hint in use");
+    }
+
+    // MAP_FIXED replaces the file mapping.
+    let fixed = sys_mmap(
+        hint as *const u8,
+        PAGESIZE,
+        anon,
+        MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED,
+        -1,
+        0,
+    );
+    if fixed != hint || unsafe { (hint as *const u8).read_volatile() } != 0 {
+        return Err("MAP_FIXED did not replace the mapping");
+    }
+    // but never the heap.
+    let heap = sys_mmap(
+        ptr::null(),
+        PAGESIZE,
+        anon,
+        MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED,
+        -1,
+        0,
+    );
+    if heap != MAP_FAILED {
+        return Err("MAP_FIXED overlapped the heap");
+    }
+
+    if sys_munmap(hint as *const u8, PAGESIZE) < 0 || sys_munmap(other as *const u8, PAGESIZE) < 0 {
+        return Err("munmap");
+    }
+    Ok(())
+}
+
+fn shared_written_back_on_exit(f: &'static str) -> Result<(), &'static str> {
+    println!("write back on exit");
+    let pid = sys_fork();
+    if pid < 0 {
+        return Err("fork");
+    }
+    if pid == 0 {
+        // exit without munmap.
+        match map_shared(f) {
+            Ok(buf) => {
+                buf[2] = b'D';
+                sys_exit(0);
+            }
+            Err(_) => sys_exit(1),
+        }
+    }
+    let mut status = 0;
+    if sys_wait(&mut status) != pid || status != 0 {
+        return Err("child failed");
+    }
+
+    let data = read_file(f)?;
+    if data[0] != b'B' || data[1] != b'C' || data[2] != b'D' {
+        return Err("content invalid after exit");
+    }
+
+    println!("Succeed.");
+    Ok(())
+}
+
 fn cat(fd: i32) -> Result<(), &'static str> {
     let mut buf = [0u8; 512];
     let mut n = sys_read(fd, &mut buf);
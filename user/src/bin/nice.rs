@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(xv6rs_user::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::ptr;
+
+use xv6rs_user::{
+    entry_point,
+    syscall::{sys_exec, sys_getpid, sys_setpriority},
+    Args,
+};
+
+const MAXARG: usize = 16;
+
+entry_point!(main);
+fn main(args: &mut Args) -> Result<i32, &'static str> {
+    let mut args = args.skip(1);
+    let prio = args
+        .next()
+        .ok_or("usage: nice priority command [args...]")?
+        .parse::<i32>()
+        .or_else(|_| Err("nice: priority must be a number"))?;
+    if sys_setpriority(sys_getpid(), prio) < 0 {
+        return Err("nice: cannot set priority");
+    }
+
+    // the arguments are already null-terminated.
+    let mut argv = [ptr::null(); MAXARG + 1];
+    for (i, arg) in args.enumerate() {
+        if i == MAXARG {
+            return Err("nice: too many arguments");
+        }
+        argv[i] = arg.as_ptr();
+    }
+    if argv[0].is_null() {
+        return Err("usage: nice priority command [args...]");
+    }
+    sys_exec(&argv);
+    Err("nice: exec failed")
+}
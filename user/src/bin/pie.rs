@@ -0,0 +1,17 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(xv6rs_user::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use xv6rs_user::{entry_point, println, Args};
+
+entry_point!(main);
+
+/// prints the address of its code and that of its stack. it is built position-independent, so
+/// both change from one exec to the next.
+fn main(_args: &mut Args) -> Result<i32, &'static str> {
+    let local = 0u8;
+    println!("{:#x} {:#x}", main as usize, &local as *const u8 as usize);
+    Ok(0)
+}
@@ -0,0 +1,20 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(xv6rs_user::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use xv6rs_user::{entry_point, syscall::sys_sleep, Args};
+
+entry_point!(main);
+fn main(args: &mut Args) -> Result<i32, &'static str> {
+    let n = args
+        .nth(1)
+        .ok_or("usage: sleep ticks")?
+        .parse::<i32>()
+        .or_else(|_| Err("sleep: ticks must be a number"))?;
+    if sys_sleep(n) < 0 {
+        return Err("sleep: interrupted");
+    }
+    Ok(0)
+}
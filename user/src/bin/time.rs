@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(xv6rs_user::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::ptr;
+
+use xv6rs_user::{
+    entry_point, println,
+    syscall::{sys_exec, sys_exit, sys_fork, sys_getrusage, sys_uptime, sys_wait},
+    time::{Rusage, RUSAGE_CHILDREN},
+    Args,
+};
+
+const MAXARG: usize = 16;
+
+entry_point!(main);
+fn main(args: &mut Args) -> Result<i32, &'static str> {
+    // the arguments are already null-terminated.
+    let mut argv = [ptr::null(); MAXARG + 1];
+    for (i, arg) in args.skip(1).enumerate() {
+        if i == MAXARG {
+            return Err("time: too many arguments");
+        }
+        argv[i] = arg.as_ptr();
+    }
+    if argv[0].is_null() {
+        return Err("usage: time command [args...]");
+    }
+
+    let start = sys_uptime();
+    let pid = sys_fork();
+    if pid < 0 {
+        return Err("time: fork failed");
+    }
+    if pid == 0 {
+        sys_exec(&argv);
+        println!("time: exec failed");
+        sys_exit(1);
+    }
+
+    let mut status = 0i32;
+    if sys_wait(&mut status) != pid {
+        return Err("time: wait failed");
+    }
+    let real = sys_uptime() - start;
+
+    let mut usage = Rusage::default();
+    if sys_getrusage(RUSAGE_CHILDREN, &mut usage) < 0 {
+        return Err("time: getrusage failed");
+    }
+    println!(
+        "real {} user {} sys {} ticks, {} voluntary and {} involuntary switches, {} faults",
+        real, usage.utime, usage.stime, usage.nvcsw, usage.nivcsw, usage.nfault
+    );
+    Ok(status)
+}
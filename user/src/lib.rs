@@ -11,9 +11,13 @@ extern crate alloc; // Use the alloc crate for heap allocation
 pub mod allocator;
 pub mod fcntl;
 pub mod fstat;
+pub mod meminfo;
+pub mod mman;
 pub mod net;
 pub mod printf;
+pub mod signal;
 pub mod syscall;
+pub mod time;
 
 use core::{panic::PanicInfo, slice::from_raw_parts, str::from_utf8_unchecked};
 
@@ -156,14 +160,30 @@ extern "C" fn _start() {
 
 #[cfg(test)]
 mod tests {
-    use core::{ptr, str::from_utf8_unchecked};
+    use alloc::vec;
+    use core::{
+        mem, ptr,
+        str::from_utf8_unchecked,
+        sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    };
 
     use crate::{
-        fcntl::{O_CREATE, O_RDWR, O_WRONLY},
+        fcntl::{O_CREATE, O_RDONLY, O_RDWR, O_WRONLY},
+        meminfo::MemInfo,
+        mman::{
+            MAP_ANONYMOUS, MAP_FAILED, MAP_PRIVATE, MAP_SHARED, PROT_NONE, PROT_READ, PROT_WRITE,
+        },
+        signal::{SigAction, SIGALRM, SIGCHLD, SIGKILL, SIGSEGV, SIGTERM},
         syscall::{
-            sys_chdir, sys_close, sys_getenv, sys_listenv, sys_mkdir, sys_open, sys_setenv,
-            sys_unlink, sys_unsetenv, sys_write,
+            sys_alarm, sys_chdir, sys_clock_gettime, sys_clone, sys_close, sys_dup, sys_exec,
+            sys_fork, sys_futex_wait, sys_futex_wake, sys_getenv, sys_getpid, sys_getppid,
+            sys_getpriority, sys_getrusage, sys_join, sys_kill, sys_listenv, sys_meminfo,
+            sys_mkdir, sys_mmap, sys_mprotect, sys_munmap, sys_open, sys_pipe, sys_read, sys_sbrk,
+            sys_sched_getaffinity, sys_sched_setaffinity, sys_setenv, sys_setpriority, sys_setsid,
+            sys_sigaction, sys_sleep, sys_unlink, sys_unsetenv, sys_uptime, sys_wait, sys_waitpid,
+            sys_write, EFAULT, EINTR, WNOHANG,
         },
+        time::{Rusage, TimeSpec, CLOCK_MONOTONIC, RUSAGE_CHILDREN, RUSAGE_SELF},
     };
 
     use super::*;
@@ -215,4 +235,625 @@ mod tests {
         let unset_result = sys_unsetenv("TEST_ENV");
         assert!(unset_result >= 0, "sys_unsetenv failed");
     }
+
+    #[test_case]
+    fn illegal_instruction_kills_only_the_child() {
+        let pid = sys_fork();
+        assert!(pid >= 0);
+        if pid == 0 {
+            unsafe { core::arch::asm!("unimp") };
+            sys_exit(0);
+        }
+        let mut status = 0i32;
+        assert_eq!(pid, sys_wait(&mut status));
+        assert_eq!(-1, status);
+    }
+
+    #[test_case]
+    fn kill_spinning_child() {
+        let pid = sys_fork();
+        assert!(pid >= 0);
+        if pid == 0 {
+            loop {}
+        }
+        assert_eq!(0, sys_kill(pid, SIGKILL));
+        let mut status = 0i32;
+        assert_eq!(pid, sys_wait(&mut status));
+        assert_eq!(-1, status);
+    }
+
+    #[test_case]
+    fn init_survives_signals() {
+        // the init proc has pid 0.
+        assert!(sys_kill(0, SIGKILL) < 0);
+        assert!(sys_kill(0, SIGTERM) < 0);
+        assert_eq!(0, sys_kill(0, SIGCHLD));
+        assert_eq!(0, sys_kill(0, 0));
+    }
+
+    #[test_case]
+    fn setsid_makes_a_session_leader() {
+        let pid = sys_fork();
+        assert!(pid >= 0);
+        if pid == 0 {
+            // the leader of a session of its own, which it cannot start again.
+            let led = sys_setsid() == sys_getpid() && sys_setsid() < 0;
+            sys_exit(if led { 0 } else { 1 });
+        }
+        let mut status = -1i32;
+        assert_eq!(pid, sys_wait(&mut status));
+        assert_eq!(0, status);
+    }
+
+    static ALARMED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn on_alarm(_sig: i32) {
+        ALARMED.store(true, Ordering::SeqCst);
+    }
+
+    #[test_case]
+    fn alarm_runs_handler() {
+        assert_eq!(0, sys_sigaction(SIGALRM, Some(&SigAction::new(on_alarm)), None));
+        assert_eq!(0, sys_alarm(1));
+        while !ALARMED.load(Ordering::SeqCst) {}
+        assert_eq!(0, sys_sigaction(SIGALRM, Some(&SigAction::default()), None));
+    }
+
+    #[test_case]
+    fn sleep_advances_clocks() {
+        let mut ts0 = TimeSpec::default();
+        assert_eq!(0, sys_clock_gettime(CLOCK_MONOTONIC, &mut ts0));
+        let t0 = sys_uptime();
+        assert_eq!(0, sys_sleep(2));
+        assert!(sys_uptime() - t0 >= 2);
+
+        let mut ts1 = TimeSpec::default();
+        assert_eq!(0, sys_clock_gettime(CLOCK_MONOTONIC, &mut ts1));
+        assert!(ts1.nsec < 1_000_000_000);
+        assert!(ts1.as_nanos() > ts0.as_nanos());
+    }
+
+    #[test_case]
+    fn waitpid_reaps_the_given_child() {
+        let ppid = sys_getpid();
+        let pid1 = sys_fork();
+        assert!(pid1 >= 0);
+        if pid1 == 0 {
+            sys_exit(if sys_getppid() == ppid { 1 } else { -2 });
+        }
+        let pid2 = sys_fork();
+        assert!(pid2 >= 0);
+        if pid2 == 0 {
+            sys_sleep(2);
+            sys_exit(2);
+        }
+
+        let mut status = 0i32;
+        assert_eq!(0, sys_waitpid(pid2, &mut status, WNOHANG));
+        assert_eq!(pid2, sys_waitpid(pid2, &mut status, 0));
+        assert_eq!(2, status);
+        assert_eq!(pid1, sys_waitpid(-1, &mut status, 0));
+        assert_eq!(1, status);
+        assert!(sys_waitpid(-1, &mut status, WNOHANG) < 0);
+    }
+
+    #[test_case]
+    fn orphan_is_adopted_by_init() {
+        let mut fds = [0i32; 2];
+        assert_eq!(0, sys_pipe(&mut fds));
+        let pid = sys_fork();
+        assert!(pid >= 0);
+        if pid == 0 {
+            if sys_fork() == 0 {
+                // wait for the parent to exit.
+                let ppid = sys_getppid();
+                while sys_getppid() == ppid {
+                    sys_sleep(1);
+                }
+                sys_write(fds[1], &[sys_getppid() as u8]);
+            }
+            sys_exit(0);
+        }
+        let mut status = 0i32;
+        assert_eq!(pid, sys_wait(&mut status));
+        sys_close(fds[1]);
+
+        // the init proc has pid 0.
+        let mut buf = [0xffu8; 1];
+        assert_eq!(1, sys_read(fds[0], &mut buf));
+        assert_eq!(0, buf[0]);
+        sys_close(fds[0]);
+    }
+
+    #[test_case]
+    fn set_and_get_priority() {
+        let pid = sys_getpid();
+        assert_eq!(0, sys_getpriority(pid));
+        assert_eq!(0, sys_setpriority(pid, 2));
+        assert_eq!(2, sys_getpriority(pid));
+        assert!(sys_setpriority(pid, 100) < 0);
+        assert_eq!(0, sys_setpriority(pid, 0));
+    }
+
+    #[test_case]
+    fn pin_to_one_hart() {
+        let pid = sys_getpid();
+        let all = sys_sched_getaffinity(pid);
+        assert!(all & 1 != 0);
+        assert_eq!(0, sys_sched_setaffinity(pid, 1));
+        assert_eq!(1, sys_sched_getaffinity(pid));
+
+        // a child inherits the mask.
+        let child = sys_fork();
+        assert!(child >= 0);
+        if child == 0 {
+            sys_exit(sys_sched_getaffinity(sys_getpid()));
+        }
+        let mut status = 0i32;
+        assert_eq!(child, sys_wait(&mut status));
+        assert_eq!(1, status);
+
+        assert!(sys_sched_setaffinity(pid, 0) < 0);
+        assert_eq!(0, sys_sched_setaffinity(pid, all as usize));
+    }
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn count_up(n: usize) -> i32 {
+        for _ in 0..n {
+            COUNTER.fetch_add(1, Ordering::SeqCst);
+        }
+        n as i32
+    }
+
+    #[test_case]
+    fn threads_share_memory() {
+        const NTHREAD: usize = 4;
+        // each thread needs a stack of its own, so they live on the heap.
+        let mut stacks = vec![0u8; 4096 * NTHREAD];
+        let mut tids = [0i32; NTHREAD];
+        COUNTER.store(0, Ordering::SeqCst);
+        for (i, stack) in stacks.chunks_mut(4096).enumerate() {
+            tids[i] = sys_clone(count_up, 1000 * (i + 1), stack);
+            assert!(tids[i] > 0);
+        }
+
+        for (i, &tid) in tids.iter().enumerate() {
+            let mut status = 0i32;
+            assert_eq!(tid, sys_join(tid, &mut status));
+            assert_eq!(1000 * (i as i32 + 1), status);
+        }
+        assert_eq!(10000, COUNTER.load(Ordering::SeqCst));
+    }
+
+    static FLAG: AtomicU32 = AtomicU32::new(0);
+
+    extern "C" fn wait_for_flag(_: usize) -> i32 {
+        while FLAG.load(Ordering::SeqCst) == 0 {
+            sys_futex_wait(&FLAG, 0, 0);
+        }
+        7
+    }
+
+    #[test_case]
+    fn futex_wakes_a_waiting_thread() {
+        FLAG.store(0, Ordering::SeqCst);
+        let mut stack = vec![0u8; 4096];
+        let tid = sys_clone(wait_for_flag, 0, &mut stack);
+        assert!(tid > 0);
+
+        sys_sleep(2);
+        FLAG.store(1, Ordering::SeqCst);
+        sys_futex_wake(&FLAG, 1);
+
+        let mut status = 0i32;
+        assert_eq!(tid, sys_join(tid, &mut status));
+        assert_eq!(7, status);
+    }
+
+    #[test_case]
+    fn futex_wait_checks_value_and_times_out() {
+        let word = AtomicU32::new(1);
+        // the value has already changed.
+        assert!(sys_futex_wait(&word, 0, 0) < 0);
+
+        let start = sys_uptime();
+        assert!(sys_futex_wait(&word, 1, 2) < 0);
+        assert!(sys_uptime() - start >= 2);
+        assert_eq!(0, sys_futex_wake(&word, 1));
+    }
+
+    static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn on_interrupt(_sig: i32) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+
+    #[test_case]
+    fn futex_wait_is_interrupted_by_a_signal() {
+        let word = AtomicU32::new(1);
+        assert_eq!(
+            0,
+            sys_sigaction(SIGALRM, Some(&SigAction::new(on_interrupt)), None)
+        );
+        assert_eq!(0, sys_alarm(2));
+        // nobody wakes the word, so only the alarm ends the wait.
+        assert_eq!(-EINTR, sys_futex_wait(&word, 1, 0));
+        assert!(INTERRUPTED.load(Ordering::SeqCst));
+        assert_eq!(0, sys_sigaction(SIGALRM, Some(&SigAction::default()), None));
+    }
+
+    #[test_case]
+    fn rusage_counts_reaped_children() {
+        let mut before = Rusage::default();
+        assert_eq!(0, sys_getrusage(RUSAGE_CHILDREN, &mut before));
+
+        let pid = sys_fork();
+        assert!(pid >= 0);
+        if pid == 0 {
+            // burn a few ticks in user mode.
+            let start = sys_uptime();
+            while sys_uptime() - start < 3 {}
+            sys_exit(0);
+        }
+        let mut status = 0i32;
+        assert_eq!(pid, sys_wait(&mut status));
+
+        let mut after = Rusage::default();
+        assert_eq!(0, sys_getrusage(RUSAGE_CHILDREN, &mut after));
+        assert!(after.utime + after.stime > before.utime + before.stime);
+        assert!(after.nvcsw > before.nvcsw);
+
+        let mut own = Rusage::default();
+        assert_eq!(0, sys_getrusage(RUSAGE_SELF, &mut own));
+        assert!(sys_getrusage(1, &mut own) < 0);
+    }
+
+    #[test_case]
+    fn meminfo_counts_pipes() {
+        const PIPE: usize = 1;
+        let mut before = MemInfo::default();
+        assert_eq!(0, sys_meminfo(&mut before, 0));
+        assert!(before.free_pages < before.total_pages);
+        assert!(before.heap_bytes > 0 && before.heap_peak >= before.heap_bytes);
+
+        let mut fds = [0i32; 2];
+        assert_eq!(0, sys_pipe(&mut fds));
+        let mut during = MemInfo::default();
+        assert_eq!(0, sys_meminfo(&mut during, 0));
+        assert_eq!(before.tags[PIPE].live + 1, during.tags[PIPE].live);
+        assert_eq!(before.tags[PIPE].total + 1, during.tags[PIPE].total);
+
+        sys_close(fds[0]);
+        sys_close(fds[1]);
+        let mut after = MemInfo::default();
+        assert_eq!(0, sys_meminfo(&mut after, 0));
+        assert_eq!(before.tags[PIPE].live, after.tags[PIPE].live);
+        assert!(sys_meminfo(&mut after, 2) < 0);
+    }
+
+    #[test_case]
+    fn oom_kills_the_leaking_child() {
+        let mut before = MemInfo::default();
+        assert_eq!(0, sys_meminfo(&mut before, 0));
+
+        let pid = sys_fork();
+        assert!(pid >= 0);
+        if pid == 0 {
+            // more than the physical memory and the swap area together. the child exits only if
+            // it is not killed: with 1 if it all fits, and with 2 if sbrk() fails instead.
+            let leaked = leak(before.total_pages + before.swap_pages + 4096);
+            sys_exit(if leaked { 1 } else { 2 });
+        }
+        let mut status = 0i32;
+        assert_eq!(pid, sys_wait(&mut status));
+        assert_eq!(-1, status);
+
+        let mut after = MemInfo::default();
+        assert_eq!(0, sys_meminfo(&mut after, 0));
+        assert_eq!(before.oom_kills + 1, after.oom_kills);
+        assert!(after.free_pages + 64 >= before.free_pages);
+    }
+
+    /// grows the heap by at least `pages` pages, 16MiB at a time, writing to each page.
+    /// returns false if sbrk() fails first.
+    fn leak(pages: usize) -> bool {
+        const CHUNK: usize = 16 * 1024 * 1024;
+        for _ in 0..(pages * 4096 + CHUNK - 1) / CHUNK {
+            let p = sys_sbrk(CHUNK as i32);
+            if p as isize == -1 {
+                return false;
+            }
+            for off in (0..CHUNK).step_by(4096) {
+                unsafe { ptr::write_volatile(p.add(off), 1) };
+            }
+        }
+        true
+    }
+
+    #[test_case]
+    fn wait_into_swapped_out_status() {
+        let mut fds = [0i32; 2];
+        assert_eq!(0, sys_pipe(&mut fds));
+        let pid = sys_fork();
+        assert!(pid >= 0);
+        if pid == 0 {
+            sys_close(fds[1]);
+            let mut go = [0u8; 1];
+            assert_eq!(1, sys_read(fds[0], &mut go));
+            // more than the free memory, so that the hand goes over the parent asleep in wait()
+            // and takes its pages as well.
+            let mut info = MemInfo::default();
+            assert_eq!(0, sys_meminfo(&mut info, 0));
+            let leaked = leak(info.free_pages + 4096);
+            assert_eq!(0, sys_meminfo(&mut info, 0));
+            sys_exit(if leaked && info.swap_used > 0 { 7 } else { 1 });
+        }
+        sys_close(fds[0]);
+
+        // a dirty page of the parent only, which the child has no copy-on-write share of.
+        let page = map_anonymous(4096, MAP_PRIVATE);
+        let status = unsafe { &mut *(page as *mut i32) };
+        *status = 0;
+        assert_eq!(1, sys_write(fds[1], b"g"));
+        sys_close(fds[1]);
+        assert_eq!(pid, sys_wait(status));
+        assert_eq!(7, *status);
+        assert_eq!(0, sys_munmap(page, 4096));
+    }
+
+    static FAULTS: AtomicUsize = AtomicUsize::new(0);
+    static FAULT_PAGE: AtomicUsize = AtomicUsize::new(0);
+
+    /// lets the faulting access through on return, like a JIT or a growable buffer would.
+    extern "C" fn on_segv(_sig: i32) {
+        FAULTS.fetch_add(1, Ordering::SeqCst);
+        let page = FAULT_PAGE.load(Ordering::SeqCst) as *const u8;
+        assert_eq!(0, sys_mprotect(page, 4096, PROT_READ | PROT_WRITE));
+    }
+
+    #[test_case]
+    fn mprotect_faults_on_violation() {
+        let len = 2 * 4096;
+        let p = sys_mmap(
+            ptr::null(),
+            len,
+            PROT_READ | PROT_WRITE,
+            MAP_PRIVATE | MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+        assert_ne!(MAP_FAILED, p);
+        let p = p as *mut u8;
+        let upper = unsafe { p.add(4096) };
+        unsafe { upper.write_volatile(7) };
+        FAULT_PAGE.store(upper as usize, Ordering::SeqCst);
+        assert_eq!(
+            0,
+            sys_sigaction(SIGSEGV, Some(&SigAction::new(on_segv)), None)
+        );
+
+        // only the upper page becomes read-only, which splits the region.
+        assert_eq!(0, sys_mprotect(upper, 4096, PROT_READ));
+        unsafe { p.write_volatile(1) };
+        assert_eq!(0, FAULTS.load(Ordering::SeqCst));
+        unsafe { upper.write_volatile(8) };
+        assert_eq!(1, FAULTS.load(Ordering::SeqCst));
+        assert_eq!(8, unsafe { upper.read_volatile() });
+
+        // a guard page faults even on a load.
+        assert_eq!(0, sys_mprotect(upper, 4096, PROT_NONE));
+        assert_eq!(8, unsafe { upper.read_volatile() });
+        assert_eq!(2, FAULTS.load(Ordering::SeqCst));
+
+        assert_eq!(0, sys_sigaction(SIGSEGV, Some(&SigAction::default()), None));
+        assert!(sys_mprotect(p.wrapping_add(1), 4096, PROT_READ) < 0);
+        assert!(sys_mprotect(p, len, 1 << 4) < 0);
+        assert_eq!(0, sys_munmap(p, len));
+        assert!(sys_mprotect(p, len, PROT_READ) < 0);
+    }
+
+    #[test_case]
+    fn read_into_read_only_memory() {
+        let page = map_anonymous(4096, MAP_PRIVATE);
+        unsafe { page.write_volatile(1) };
+        assert_eq!(0, sys_mprotect(page, 4096, PROT_READ));
+        let fd = sys_open("init\0", O_RDONLY);
+        assert!(fd >= 0);
+
+        // the syscall fails, rather than the kernel, and the page is left alone.
+        let buf = unsafe { core::slice::from_raw_parts_mut(page, 16) };
+        assert_eq!(-EFAULT, sys_read(fd, buf));
+        assert_eq!(1, unsafe { page.read_volatile() });
+        // so it does with the text, which is read-only and executable.
+        let text = read_into_read_only_memory as usize as *mut u8;
+        let buf = unsafe { core::slice::from_raw_parts_mut(text, 16) };
+        assert_eq!(-EFAULT, sys_read(fd, buf));
+
+        sys_close(fd);
+        assert_eq!(0, sys_munmap(page, 4096));
+    }
+
+    fn map_anonymous(len: usize, flags: usize) -> *mut u8 {
+        let p = sys_mmap(
+            ptr::null(),
+            len,
+            PROT_READ | PROT_WRITE,
+            flags | MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+        assert_ne!(MAP_FAILED, p);
+        p as *mut u8
+    }
+
+    #[test_case]
+    fn mmap_refuses_unknown_flags() {
+        // 1 << 8 marks the stack in the kernel, which user space cannot pass itself off as.
+        let flags = MAP_PRIVATE | MAP_ANONYMOUS | 1 << 8;
+        let p = sys_mmap(ptr::null(), 4096, PROT_READ | PROT_WRITE, flags, -1, 0);
+        assert_eq!(MAP_FAILED, p);
+    }
+
+    #[test_case]
+    fn fork_shares_or_copies_mmap_regions() {
+        let shared = map_anonymous(4096, MAP_SHARED);
+        let private = map_anonymous(4096, MAP_PRIVATE);
+        unsafe { private.write_volatile(1) };
+
+        let pid = sys_fork();
+        assert!(pid >= 0);
+        if pid == 0 {
+            let ok = unsafe { private.read_volatile() } == 1;
+            unsafe {
+                shared.write_volatile(2);
+                private.write_volatile(3);
+            }
+            sys_exit(if ok { 0 } else { 1 });
+        }
+        let mut status = 0i32;
+        assert_eq!(pid, sys_wait(&mut status));
+        assert_eq!(0, status);
+        assert_eq!(2, unsafe { shared.read_volatile() });
+        assert_eq!(1, unsafe { private.read_volatile() });
+
+        assert_eq!(0, sys_munmap(shared, 4096));
+        assert_eq!(0, sys_munmap(private, 4096));
+    }
+
+    #[test_case]
+    fn many_small_mmap_regions() {
+        // more than the old fixed table of VMAs could hold. every other one is read-only, so
+        // they are not merged.
+        let mut regions = vec![ptr::null_mut(); 150];
+        for (i, p) in regions.iter_mut().enumerate() {
+            *p = map_anonymous(4096, MAP_PRIVATE);
+            if i % 2 == 1 {
+                assert_eq!(0, sys_mprotect(*p, 4096, PROT_READ));
+            }
+        }
+        for p in regions {
+            assert_eq!(0, sys_munmap(p, 4096));
+        }
+    }
+
+    /// uses `depth` KiB of stack or so.
+    fn recurse(depth: usize) -> usize {
+        let mut frame = [0u8; 1024];
+        unsafe { ptr::write_volatile(&mut frame[0], depth as u8) };
+        if depth == 0 {
+            return 0;
+        }
+        recurse(depth - 1) + unsafe { ptr::read_volatile(&frame[0]) } as usize
+    }
+
+    #[test_case]
+    fn text_is_not_writable() {
+        let pid = sys_fork();
+        assert!(pid >= 0);
+        if pid == 0 {
+            let text = text_is_not_writable as usize as *mut u8;
+            unsafe { ptr::write_volatile(text, 0) };
+            sys_exit(0);
+        }
+        let mut status = 0i32;
+        assert_eq!(pid, sys_wait(&mut status));
+        assert_eq!(-1, status);
+    }
+
+    // `ret`, in the read-only data.
+    static RET: [u32; 1] = [0x0000_8067];
+
+    #[test_case]
+    fn rodata_is_not_executable() {
+        let pid = sys_fork();
+        assert!(pid >= 0);
+        if pid == 0 {
+            let f: extern "C" fn() = unsafe { mem::transmute(RET.as_ptr()) };
+            f();
+            sys_exit(0);
+        }
+        let mut status = 0i32;
+        assert_eq!(pid, sys_wait(&mut status));
+        assert_eq!(-1, status);
+    }
+
+    // more than a page of the bss, which is not in the file.
+    static mut BSS: [u8; 3 * 4096] = [0; 3 * 4096];
+
+    #[test_case]
+    fn bss_is_zeroed() {
+        let bss = unsafe { &mut *ptr::addr_of_mut!(BSS) };
+        assert!(bss.iter().all(|&b| b == 0));
+        bss[4096] = 1;
+        assert_eq!(1, unsafe { ptr::read_volatile(&bss[4096]) });
+    }
+
+    #[test_case]
+    fn stack_grows_on_demand() {
+        // far more than the page the stack starts with.
+        let expected: usize = (1..=256usize).map(|d| d as u8 as usize).sum();
+        assert_eq!(expected, recurse(256));
+    }
+
+    #[test_case]
+    fn stack_overflow_kills_only_the_child() {
+        let pid = sys_fork();
+        assert!(pid >= 0);
+        if pid == 0 {
+            // more than the stack may grow to, so it runs into the guard page.
+            recurse(2048);
+            sys_exit(0);
+        }
+        let mut status = 0i32;
+        assert_eq!(pid, sys_wait(&mut status));
+        assert_eq!(-1, status);
+    }
+
+    /// runs the position-independent program `pie`, and returns the addresses it prints: that of
+    /// its code, and that of its stack.
+    fn pie_layout() -> (usize, usize) {
+        let mut fds = [0i32; 2];
+        assert_eq!(0, sys_pipe(&mut fds));
+        let pid = sys_fork();
+        assert!(pid >= 0);
+        if pid == 0 {
+            sys_close(1);
+            sys_dup(fds[1]);
+            sys_close(fds[0]);
+            sys_close(fds[1]);
+            sys_exec(&["pie\0".as_ptr(), ptr::null()]);
+            sys_exit(1);
+        }
+        sys_close(fds[1]);
+        let mut buf = [0u8; 64];
+        let mut len = 0;
+        loop {
+            let n = sys_read(fds[0], &mut buf[len..]);
+            if n <= 0 {
+                break;
+            }
+            len += n as usize;
+        }
+        sys_close(fds[0]);
+        let mut status = 0i32;
+        assert_eq!(pid, sys_wait(&mut status));
+        assert_eq!(0, status);
+
+        let out = core::str::from_utf8(&buf[..len]).unwrap();
+        let mut addrs = out
+            .split_whitespace()
+            .map(|addr| usize::from_str_radix(addr.trim_start_matches("0x"), 16).unwrap());
+        (addrs.next().unwrap(), addrs.next().unwrap())
+    }
+
+    #[test_case]
+    fn pie_layout_is_randomized() {
+        let (text1, stack1) = pie_layout();
+        let (text2, stack2) = pie_layout();
+        // loaded past the kernel's PIE_BASE, at another base and with another stack each time.
+        assert!(text1 >= 0x1_0000 && text2 >= 0x1_0000);
+        assert_ne!(text1, text2);
+        assert_ne!(stack1, stack2);
+    }
 }
@@ -0,0 +1,35 @@
+//! The kernel's memory usage, reported by meminfo().
+
+/// meminfo() flags: print the live tagged objects by the site that created them on the console.
+/// the kernel records the sites only if it is built with the kalloc-debug feature.
+pub const MEMINFO_SITES: i32 = 1;
+
+/// the kinds of kernel objects counted, in the order of `MemInfo::tags`.
+pub const TAG_NAMES: [&str; NTAG] = ["file", "pipe", "socket", "mbuf"];
+pub const NTAG: usize = 4;
+
+/// the live objects of a kind, and those ever created.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TagCount {
+    pub live: usize,
+    pub total: usize,
+}
+
+/// the pages of physical memory, free, in the slab caches and in the page cache, the pages of the
+/// swap area and those in use, the bytes of the live kernel allocations and their peak, and the
+/// processes killed for memory.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct MemInfo {
+    pub total_pages: usize,
+    pub free_pages: usize,
+    pub slab_pages: usize,
+    pub cached_pages: usize,
+    pub swap_pages: usize,
+    pub swap_used: usize,
+    pub heap_bytes: usize,
+    pub heap_peak: usize,
+    pub oom_kills: usize,
+    pub tags: [TagCount; NTAG],
+}
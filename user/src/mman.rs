@@ -0,0 +1,19 @@
+//! mmap() protections and flags, which follow the bits of the kernel's page table entries and
+//! `MapFlag`.
+
+pub const PROT_NONE: usize = 0;
+pub const PROT_READ: usize = 1 << 1;
+pub const PROT_WRITE: usize = 1 << 2;
+pub const PROT_EXEC: usize = 1 << 3;
+
+pub const MAP_SHARED: usize = 1 << 0;
+pub const MAP_PRIVATE: usize = 1 << 1;
+pub const MAP_ANONYMOUS: usize = 1 << 2;
+/// place the mapping exactly at the address, replacing what is mapped there.
+pub const MAP_FIXED: usize = 1 << 4;
+
+/// mmap() returns it if it fails.
+pub const MAP_FAILED: usize = usize::MAX;
+
+pub const MS_ASYNC: i32 = 1;
+pub const MS_SYNC: i32 = 4;
@@ -0,0 +1,58 @@
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGQUIT: i32 = 3;
+pub const SIGILL: i32 = 4;
+pub const SIGTRAP: i32 = 5;
+pub const SIGABRT: i32 = 6;
+pub const SIGBUS: i32 = 7;
+pub const SIGFPE: i32 = 8;
+pub const SIGKILL: i32 = 9;
+pub const SIGUSR1: i32 = 10;
+pub const SIGSEGV: i32 = 11;
+pub const SIGUSR2: i32 = 12;
+pub const SIGPIPE: i32 = 13;
+pub const SIGALRM: i32 = 14;
+pub const SIGTERM: i32 = 15;
+pub const SIGCHLD: i32 = 17;
+pub const SIGCONT: i32 = 18;
+pub const SIGSTOP: i32 = 19;
+pub const SIGTSTP: i32 = 20;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+pub const SIG_BLOCK: i32 = 0;
+pub const SIG_UNBLOCK: i32 = 1;
+pub const SIG_SETMASK: i32 = 2;
+
+/// The default action (`SIG_DFL`) is the zero value.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct SigAction {
+    pub handler: usize,
+    pub mask: u32,
+    pub flags: u32,
+    // filled in by sys_sigaction()
+    pub restorer: usize,
+}
+
+impl SigAction {
+    pub fn new(handler: extern "C" fn(i32)) -> Self {
+        Self {
+            handler: handler as usize,
+            ..Default::default()
+        }
+    }
+
+    pub fn ignore() -> Self {
+        Self {
+            handler: SIG_IGN,
+            ..Default::default()
+        }
+    }
+}
+
+/// the bit of `sig` in a signal mask.
+pub const fn sigmask(sig: i32) -> u32 {
+    1 << sig
+}
@@ -1,5 +1,23 @@
-use crate::{fstat::FileStat, net::SockAddr};
-use core::mem;
+use crate::{
+    fstat::FileStat,
+    meminfo::MemInfo,
+    net::SockAddr,
+    signal::SigAction,
+    time::{Rusage, TimeSpec},
+};
+use core::{mem, ptr, sync::atomic::AtomicU32};
+
+/// waitpid() option: return immediately if no child has exited.
+pub const WNOHANG: i32 = 1;
+
+/// the error a syscall returns, negated, when the kernel has no memory left for it.
+pub const ENOMEM: i32 = 12;
+
+/// the error a syscall returns, negated, when given a buffer the process cannot access.
+pub const EFAULT: i32 = 14;
+
+/// the error a syscall returns, negated, when it gives up sleeping for a signal to handle.
+pub const EINTR: i32 = 4;
 
 extern "C" {
     /// 1
@@ -11,9 +29,15 @@ extern "C" {
     /// 3
     /// int wait(int *status)
     fn __wait(addr: *mut i32) -> i32;
+    /// 4
+    /// int pipe(int p[])
+    fn __pipe(fds: *mut i32) -> i32;
     /// 5
     /// int read(int fd, char *buf, int n)
     fn __read(fd: i32, addr: *const u8, n: i32) -> i32;
+    /// 6
+    /// int kill(int pid, int sig)
+    fn __kill(pid: i32, sig: i32) -> i32;
     /// 7
     /// int exec(char *file, char *argv[])
     fn __exec(addr: *const u8, argv: *const *const u8) -> i32;
@@ -26,10 +50,19 @@ extern "C" {
     /// 10
     /// int dup(int fd)
     fn __dup(fd: i32);
+    /// 11
+    /// int getpid()
+    fn __getpid() -> i32;
     /// 12
     /// char *sbrk(int n)
     /// Grow process's memory by n bytes. Returns start of new memory.
     fn __sbrk(n: i32) -> *mut u8;
+    /// 13
+    /// int sleep(int n)
+    fn __sleep(n: i32) -> i32;
+    /// 14
+    /// int uptime()
+    fn __uptime() -> i32;
     /// 15
     /// int open(char *file, int flags)
     fn __open(addr: *const u8, mode: i32) -> i32;
@@ -79,6 +112,71 @@ extern "C" {
     /// 31
     /// int listenv(char *buf, size_t size)
     fn __listenv(buf: *mut u8, size: usize) -> i32;
+    /// 32
+    /// int sigaction(int sig, const struct sigaction *act, struct sigaction *oldact)
+    fn __sigaction(sig: i32, act: *const SigAction, oldact: *mut SigAction) -> i32;
+    /// 33
+    /// int sigprocmask(int how, const sigset_t *set, sigset_t *oldset)
+    fn __sigprocmask(how: i32, set: *const u32, oldset: *mut u32) -> i32;
+    /// 34
+    /// int sigreturn()
+    fn __sigreturn() -> !;
+    /// 35
+    /// int alarm(int n)
+    fn __alarm(n: i32) -> i32;
+    /// 36
+    /// int clock_gettime(int clockid, struct timespec *tp)
+    fn __clock_gettime(clockid: i32, tp: *mut TimeSpec) -> i32;
+    /// 37
+    /// int getppid()
+    fn __getppid() -> i32;
+    /// 38
+    /// int waitpid(int pid, int *status, int options)
+    fn __waitpid(pid: i32, addr: *mut i32, options: i32) -> i32;
+    /// 39
+    /// int setpriority(int pid, int prio)
+    fn __setpriority(pid: i32, prio: i32) -> i32;
+    /// 40
+    /// int getpriority(int pid)
+    fn __getpriority(pid: i32) -> i32;
+    /// 41
+    /// int sched_setaffinity(int pid, int mask)
+    fn __sched_setaffinity(pid: i32, mask: usize) -> i32;
+    /// 42
+    /// int sched_getaffinity(int pid)
+    fn __sched_getaffinity(pid: i32) -> i32;
+    /// 43
+    /// int clone(int (*fn)(void *), void *arg, void *stack, void (*ret)(int))
+    fn __clone(f: usize, arg: usize, stack: usize, ret: usize) -> i32;
+    /// 44
+    /// int join(int tid, int *status)
+    fn __join(tid: i32, addr: *mut i32) -> i32;
+    /// 45
+    /// int futex_wait(int *addr, int expected, int timeout)
+    fn __futex_wait(addr: *const u32, expected: u32, timeout: i32) -> i32;
+    /// 46
+    /// int futex_wake(int *addr, int n)
+    fn __futex_wake(addr: *const u32, n: i32) -> i32;
+    /// 47
+    /// int getrusage(int who, struct rusage *usage)
+    fn __getrusage(who: i32, usage: *mut Rusage) -> i32;
+    /// 48
+    /// int munmap(void *addr, size_t length)
+    fn __munmap(addr: *const u8, size: usize) -> i32;
+    /// 49
+    /// int msync(void *addr, size_t length, int flags)
+    fn __msync(addr: *const u8, size: usize, flags: i32) -> i32;
+    /// 50
+    /// int mprotect(void *addr, size_t length, int prot)
+    fn __mprotect(addr: *const u8, size: usize, prot: usize) -> i32;
+    /// 51
+    /// int meminfo(struct meminfo *info, int flags)
+    fn __meminfo(info: *mut MemInfo, flags: i32) -> i32;
+    /// 52
+    /// int setsid()
+    fn __setsid() -> i32;
+    /// exit() with the return value of a thread's function, which is still in a0.
+    fn __thread_exit() -> !;
 }
 
 // 1
@@ -96,11 +194,21 @@ pub fn sys_wait(status: &mut i32) -> i32 {
     unsafe { __wait(status as *mut _) }
 }
 
+// 4
+pub fn sys_pipe(fds: &mut [i32; 2]) -> i32 {
+    unsafe { __pipe(fds.as_mut_ptr()) }
+}
+
 // 5
 pub fn sys_read(fd: i32, buf: &mut [u8]) -> i32 {
     unsafe { __read(fd, buf.as_mut_ptr(), buf.len() as i32) }
 }
 
+// 6
+pub fn sys_kill(pid: i32, sig: i32) -> i32 {
+    unsafe { __kill(pid, sig) }
+}
+
 // 7
 pub fn sys_exec(argv: &[*const u8]) -> i32 {
     unsafe { __exec(argv[0], argv.as_ptr()) }
@@ -121,11 +229,26 @@ pub fn sys_dup(fd: i32) {
     unsafe { __dup(fd) }
 }
 
+// 11
+pub fn sys_getpid() -> i32 {
+    unsafe { __getpid() }
+}
+
 // 12
 pub fn sys_sbrk(n: i32) -> *mut u8 {
     unsafe { __sbrk(n) }
 }
 
+// 13
+pub fn sys_sleep(n: i32) -> i32 {
+    unsafe { __sleep(n) }
+}
+
+// 14
+pub fn sys_uptime() -> i32 {
+    unsafe { __uptime() }
+}
+
 // 15
 pub fn sys_open(path: &str, mode: i32) -> i32 {
     unsafe { __open(path.as_ptr(), mode) }
@@ -214,3 +337,126 @@ pub fn sys_unsetenv(name: &str) -> i32 {
 pub fn sys_listenv(buf: &mut [u8]) -> i32 {
     unsafe { __listenv(buf.as_mut_ptr(), buf.len()) }
 }
+
+// 32
+pub fn sys_sigaction(sig: i32, act: Option<&SigAction>, oldact: Option<&mut SigAction>) -> i32 {
+    let act = act.map(|act| {
+        let mut act = *act;
+        // handlers return into sigreturn().
+        act.restorer = __sigreturn as usize;
+        act
+    });
+    unsafe {
+        __sigaction(
+            sig,
+            act.as_ref().map_or(ptr::null(), |act| act as *const _),
+            oldact.map_or(ptr::null_mut(), |act| act as *mut _),
+        )
+    }
+}
+
+// 33
+pub fn sys_sigprocmask(how: i32, set: Option<&u32>, oldset: Option<&mut u32>) -> i32 {
+    unsafe {
+        __sigprocmask(
+            how,
+            set.map_or(ptr::null(), |set| set as *const _),
+            oldset.map_or(ptr::null_mut(), |set| set as *mut _),
+        )
+    }
+}
+
+// 35
+pub fn sys_alarm(n: i32) -> i32 {
+    unsafe { __alarm(n) }
+}
+
+// 36
+pub fn sys_clock_gettime(clockid: i32, tp: &mut TimeSpec) -> i32 {
+    unsafe { __clock_gettime(clockid, tp as *mut _) }
+}
+
+// 37
+pub fn sys_getppid() -> i32 {
+    unsafe { __getppid() }
+}
+
+// 38
+pub fn sys_waitpid(pid: i32, status: &mut i32, options: i32) -> i32 {
+    unsafe { __waitpid(pid, status as *mut _, options) }
+}
+
+// 39
+pub fn sys_setpriority(pid: i32, prio: i32) -> i32 {
+    unsafe { __setpriority(pid, prio) }
+}
+
+// 40
+pub fn sys_getpriority(pid: i32) -> i32 {
+    unsafe { __getpriority(pid) }
+}
+
+// 41
+pub fn sys_sched_setaffinity(pid: i32, mask: usize) -> i32 {
+    unsafe { __sched_setaffinity(pid, mask) }
+}
+
+// 42
+pub fn sys_sched_getaffinity(pid: i32) -> i32 {
+    unsafe { __sched_getaffinity(pid) }
+}
+
+// 43
+/// runs `f(arg)` in a new thread on `stack`, which must outlive the thread. the value `f` returns
+/// is the thread's exit status.
+pub fn sys_clone(f: extern "C" fn(usize) -> i32, arg: usize, stack: &mut [u8]) -> i32 {
+    // the stack grows down from the end of the buffer.
+    let top = (stack.as_mut_ptr() as usize + stack.len()) & !0xf;
+    unsafe { __clone(f as usize, arg, top, __thread_exit as usize) }
+}
+
+// 44
+pub fn sys_join(tid: i32, status: &mut i32) -> i32 {
+    unsafe { __join(tid, status as *mut _) }
+}
+
+// 45
+/// sleeps while `word` holds `expected`, for at most `timeout` ticks unless it is 0.
+pub fn sys_futex_wait(word: &AtomicU32, expected: u32, timeout: i32) -> i32 {
+    unsafe { __futex_wait(word.as_ptr(), expected, timeout) }
+}
+
+// 46
+pub fn sys_futex_wake(word: &AtomicU32, n: i32) -> i32 {
+    unsafe { __futex_wake(word.as_ptr(), n) }
+}
+
+// 47
+pub fn sys_getrusage(who: i32, usage: &mut Rusage) -> i32 {
+    unsafe { __getrusage(who, usage as *mut _) }
+}
+
+// 48
+pub fn sys_munmap(addr: *const u8, size: usize) -> i32 {
+    unsafe { __munmap(addr, size) }
+}
+
+// 49
+pub fn sys_msync(addr: *const u8, size: usize, flags: i32) -> i32 {
+    unsafe { __msync(addr, size, flags) }
+}
+
+// 50
+pub fn sys_mprotect(addr: *const u8, size: usize, prot: usize) -> i32 {
+    unsafe { __mprotect(addr, size, prot) }
+}
+
+// 51
+pub fn sys_meminfo(info: &mut MemInfo, flags: i32) -> i32 {
+    unsafe { __meminfo(info as *mut _, flags) }
+}
+
+// 52
+pub fn sys_setsid() -> i32 {
+    unsafe { __setsid() }
+}
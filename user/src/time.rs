@@ -0,0 +1,31 @@
+pub const CLOCK_REALTIME: i32 = 0;
+pub const CLOCK_MONOTONIC: i32 = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TimeSpec {
+    pub sec: u64,
+    pub nsec: u64,
+}
+
+impl TimeSpec {
+    pub fn as_nanos(&self) -> u64 {
+        self.sec * 1_000_000_000 + self.nsec
+    }
+}
+
+/// getrusage() who: the calling process.
+pub const RUSAGE_SELF: i32 = 0;
+/// getrusage() who: the children that have been waited for.
+pub const RUSAGE_CHILDREN: i32 = -1;
+
+/// resource usage, with times in clock ticks.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Rusage {
+    pub utime: usize,
+    pub stime: usize,
+    pub nvcsw: usize,
+    pub nivcsw: usize,
+    pub nfault: usize,
+}